@@ -2,12 +2,22 @@ export interface ConfigData {
   // Server settings
   ip: string;
   port: number;
+  storage?: "toml" | "sqlite";
+  db_path?: string | null;
 
   // App settings
   check_update: boolean;
   auto_update: boolean;
   no_fs?: boolean;
   log_to_file?: boolean;
+  metrics_require_auth?: boolean;
+
+  // Audit log settings
+  audit_log?: boolean;
+  audit_max_entries?: number;
+  audit_retention_hours?: number;
+  audit_log_bodies?: boolean;
+  audit_body_limit?: number;
 
   // Network settings
   password: string;
@@ -29,6 +39,7 @@ export interface ConfigData {
   skip_non_pro: boolean;
   skip_rate_limit: boolean;
   skip_normal_pro: boolean;
+  cookie_strategy?: string;
 
   // Prompt configurations
   use_real_roles: boolean;
@@ -11,9 +11,19 @@ export interface UsageBreakdown {
 export interface CookieStatus {
   cookie: string;
   reset_time: number | null;
+  sonnet_reset_time?: number | null;
+  opus_reset_time?: number | null;
   supports_claude_1m_sonnet?: boolean | null;
   supports_claude_1m_opus?: boolean | null;
   count_tokens_allowed?: boolean | null;
+  weight?: number | null;
+  last_used_at?: number | null;
+  label?: string | null;
+  notes?: string | null;
+  owner?: string | null;
+  group?: string | null;
+  last_checked_at?: number | null;
+  last_error?: string | null;
   // New usage buckets
   session_usage?: UsageBreakdown;
   weekly_usage?: UsageBreakdown;
@@ -0,0 +1,21 @@
+use axum::Json;
+use axum_auth::AuthBearer;
+use serde_json::{Value, json};
+
+use super::error::ApiError;
+use crate::{config::CLEWDR_CONFIG, services::response_cache::RESPONSE_CACHE};
+
+/// API endpoint to drop every cached response, from memory and from disk
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - Number of purged in memory responses on success
+pub async fn api_purge_cache(AuthBearer(t): AuthBearer) -> Result<Json<Value>, ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ApiError::unauthorized());
+    }
+    let purged = RESPONSE_CACHE.purge().await;
+    Ok(Json(json!({ "purged": purged })))
+}
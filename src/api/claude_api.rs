@@ -0,0 +1,21 @@
+use axum::{Extension, extract::State, response::Response};
+
+use crate::{
+    error::ClewdrError,
+    middleware::claude::{ClaudeCodePreprocess, ClaudeContext},
+    providers::claude::{ClaudeInvocation, ClaudeProviderResponse, ClaudeProviders},
+    services::response_cache::invoke_cached,
+};
+
+pub async fn api_claude_api(
+    State(providers): State<ClaudeProviders>,
+    ClaudeCodePreprocess(params, context): ClaudeCodePreprocess,
+) -> Result<(Extension<ClaudeContext>, Response), ClewdrError> {
+    let ClaudeProviderResponse { context, response } = invoke_cached(
+        &providers,
+        "api",
+        ClaudeInvocation::messages(params, context.clone()),
+    )
+    .await?;
+    Ok((Extension(context), response))
+}
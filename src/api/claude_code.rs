@@ -1,5 +1,3 @@
-use std::sync::Arc;
-
 use axum::{Extension, extract::State, response::Response};
 
 use crate::{
@@ -7,26 +5,30 @@ use crate::{
     middleware::claude::{ClaudeCodePreprocess, ClaudeContext},
     providers::{
         LLMProvider,
-        claude::{ClaudeCodeProvider, ClaudeInvocation, ClaudeProviderResponse},
+        claude::{ClaudeInvocation, ClaudeProviderResponse, ClaudeProviders},
     },
+    services::response_cache::invoke_cached,
 };
 
 pub async fn api_claude_code(
-    State(provider): State<Arc<ClaudeCodeProvider>>,
+    State(providers): State<ClaudeProviders>,
     ClaudeCodePreprocess(params, context): ClaudeCodePreprocess,
 ) -> Result<(Extension<ClaudeContext>, Response), ClewdrError> {
-    let ClaudeProviderResponse { context, response } = provider
-        .invoke(ClaudeInvocation::messages(params, context.clone()))
-        .await?;
+    let ClaudeProviderResponse { context, response } = invoke_cached(
+        &providers,
+        "code",
+        ClaudeInvocation::messages(params, context.clone()),
+    )
+    .await?;
     Ok((Extension(context), response))
 }
 
 pub async fn api_claude_code_count_tokens(
-    State(provider): State<Arc<ClaudeCodeProvider>>,
+    State(providers): State<ClaudeProviders>,
     ClaudeCodePreprocess(mut params, context): ClaudeCodePreprocess,
 ) -> Result<Response, ClewdrError> {
     params.stream = Some(false);
-    let ClaudeProviderResponse { response, .. } = provider
+    let ClaudeProviderResponse { response, .. } = providers
         .invoke(ClaudeInvocation::count_tokens(params, context))
         .await?;
     Ok(response)
@@ -1,14 +1,10 @@
-use std::sync::Arc;
-
 use axum::{Extension, extract::State, response::Response};
 
 use crate::{
     error::ClewdrError,
     middleware::claude::{ClaudeContext, ClaudeWebPreprocess},
-    providers::{
-        LLMProvider,
-        claude::{ClaudeInvocation, ClaudeProviderResponse, ClaudeWebProvider},
-    },
+    providers::claude::{ClaudeInvocation, ClaudeProviderResponse, ClaudeProviders},
+    services::response_cache::invoke_cached,
 };
 /// Axum handler for the API messages
 /// Main API endpoint for handling message requests to Claude
@@ -22,11 +18,14 @@ use crate::{
 /// # Returns
 /// * `Response` - Stream or JSON response from Claude
 pub async fn api_claude_web(
-    State(provider): State<Arc<ClaudeWebProvider>>,
+    State(providers): State<ClaudeProviders>,
     ClaudeWebPreprocess(params, context): ClaudeWebPreprocess,
 ) -> Result<(Extension<ClaudeContext>, Response), ClewdrError> {
-    let ClaudeProviderResponse { context, response } = provider
-        .invoke(ClaudeInvocation::messages(params, context.clone()))
-        .await?;
+    let ClaudeProviderResponse { context, response } = invoke_cached(
+        &providers,
+        "web",
+        ClaudeInvocation::messages(params, context.clone()),
+    )
+    .await?;
     Ok((Extension(context), response))
 }
@@ -22,10 +22,13 @@ pub async fn api_get_config(
     }
 
     let mut config_json = json!(CLEWDR_CONFIG.load().as_ref());
-    // remove cookie_array and wasted_cookie
+    // remove cookie_array, wasted_cookie and the key lists, they are managed separately
     if let Some(obj) = config_json.as_object_mut() {
         obj.remove("cookie_array");
         obj.remove("wasted_cookie");
+        obj.remove("api_keys");
+        obj.remove("gemini_keys");
+        obj.remove("anthropic_keys");
     }
 
     Ok(Json(config_json))
@@ -48,12 +51,24 @@ pub async fn api_post_config(
         return Err(ApiError::unauthorized());
     }
     let c = c.validate();
+    // the storage backend is chosen at startup, changing it here would be silently ignored
+    {
+        let old_c = CLEWDR_CONFIG.load();
+        if c.storage != old_c.storage || c.db_path != old_c.db_path {
+            return Err(ApiError::bad_request(
+                "storage and db_path can only be changed in the config file and take effect after a restart",
+            ));
+        }
+    }
     // update config
     CLEWDR_CONFIG.rcu(|old_c| {
         let mut new_c = ClewdrConfig::clone(&c);
-        // add cookie_array and wasted_cookie
+        // add cookie_array, wasted_cookie and the key lists
         new_c.cookie_array = old_c.cookie_array.to_owned();
         new_c.wasted_cookie = old_c.wasted_cookie.to_owned();
+        new_c.api_keys = old_c.api_keys.to_owned();
+        new_c.gemini_keys = old_c.gemini_keys.to_owned();
+        new_c.anthropic_keys = old_c.anthropic_keys.to_owned();
         new_c
     });
     if let Err(e) = CLEWDR_CONFIG.load().save().await {
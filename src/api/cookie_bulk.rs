@@ -0,0 +1,363 @@
+use std::collections::HashSet;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use axum_auth::AuthBearer;
+use chrono::Utc;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::info;
+
+use super::{error::ApiError, misc::invalidate_cookie_cache};
+use crate::{
+    config::{CLEWDR_CONFIG, ClewdrCookie, CookieStatus, Reason},
+    error::ClewdrError,
+    services::{cookie_actor::CookieActorHandle, health_check::probe},
+};
+
+/// Format of an import body
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// Detected from the body
+    #[default]
+    Auto,
+    /// One cookie per line
+    Lines,
+    /// Array of cookie strings or cookie objects, or an export document
+    Json,
+    /// `cookie` column with an optional `weight` column, the header row is optional
+    Csv,
+}
+
+/// Query parameters of the import endpoint
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    format: ImportFormat,
+    /// Bootstrap every new cookie before adding it
+    #[serde(default)]
+    validate: bool,
+}
+
+/// An entry of an import body, numbered from 1 by line, or by position for JSON
+struct ImportEntry {
+    line: usize,
+    cookie: Result<CookieStatus, String>,
+}
+
+/// Outcome of one entry of an import
+#[derive(Serialize)]
+struct ImportResult {
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cookie: Option<String>,
+    /// `added`, `exhausted`, `duplicate`, `invalid` or `error`
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl ImportResult {
+    fn new(line: usize, cookie: &ClewdrCookie, status: &'static str) -> Self {
+        Self {
+            line,
+            cookie: Some(cookie.ellipse()),
+            status,
+            error: None,
+        }
+    }
+
+    fn with_error(mut self, error: impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+fn detect_format(body: &str) -> ImportFormat {
+    let body = body.trim_start();
+    if body.starts_with('[') || body.starts_with('{') {
+        ImportFormat::Json
+    } else if body.lines().next().is_some_and(|l| l.contains(',')) {
+        ImportFormat::Csv
+    } else {
+        ImportFormat::Lines
+    }
+}
+
+fn parse_cookie(s: &str) -> Result<CookieStatus, String> {
+    CookieStatus::new(s, None).map_err(|e| e.to_string())
+}
+
+/// Splits an import body into cookie entries
+fn parse_import(body: &str, format: ImportFormat) -> Result<Vec<ImportEntry>, String> {
+    let format = match format {
+        ImportFormat::Auto => detect_format(body),
+        f => f,
+    };
+    let lines = || {
+        body.lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+    };
+    match format {
+        ImportFormat::Json => parse_json(body),
+        ImportFormat::Csv => {
+            let mut entries = vec![];
+            let (mut cookie_col, mut weight_col) = (0, Some(1));
+            for (line, row) in lines() {
+                let fields = row
+                    .split(',')
+                    .map(|f| f.trim().trim_matches('"'))
+                    .collect::<Vec<_>>();
+                if entries.is_empty() && fields.iter().any(|f| f.eq_ignore_ascii_case("cookie")) {
+                    cookie_col = fields
+                        .iter()
+                        .position(|f| f.eq_ignore_ascii_case("cookie"))
+                        .unwrap_or_default();
+                    weight_col = fields.iter().position(|f| f.eq_ignore_ascii_case("weight"));
+                    continue;
+                }
+                let cookie =
+                    parse_cookie(fields.get(cookie_col).unwrap_or(&"")).and_then(|mut c| {
+                        if let Some(weight) = weight_col
+                            .and_then(|i| fields.get(i))
+                            .filter(|w| !w.is_empty())
+                        {
+                            c.weight = Some(
+                                weight
+                                    .parse()
+                                    .map_err(|_| format!("Invalid weight: {weight}"))?,
+                            );
+                        }
+                        Ok(c)
+                    });
+                entries.push(ImportEntry { line, cookie });
+            }
+            Ok(entries)
+        }
+        _ => Ok(lines()
+            .map(|(line, l)| ImportEntry {
+                line,
+                cookie: parse_cookie(l),
+            })
+            .collect()),
+    }
+}
+
+/// Reads an array of cookie strings or objects, or the `valid` and `exhausted` cookies of an
+/// export document
+fn parse_json(body: &str) -> Result<Vec<ImportEntry>, String> {
+    let value = serde_json::from_str::<Value>(body).map_err(|e| format!("Invalid JSON: {e}"))?;
+    let items = match value {
+        Value::Array(items) => items,
+        Value::Object(mut doc) => {
+            let mut items = vec![];
+            for key in ["valid", "exhausted"] {
+                if let Some(Value::Array(cookies)) = doc.remove(key) {
+                    items.extend(cookies);
+                }
+            }
+            items
+        }
+        _ => return Err("Expected an array of cookies or an export document".to_string()),
+    };
+    Ok(items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| ImportEntry {
+            line: i + 1,
+            cookie: match item {
+                Value::String(s) => parse_cookie(&s),
+                v => serde_json::from_value::<CookieStatus>(v).map_err(|e| e.to_string()),
+            },
+        })
+        .collect())
+}
+
+/// API endpoint to import a list of cookies
+///
+/// The body is a newline, JSON or CSV list of cookies, see [`ImportFormat`]. Cookies already
+/// in `cookie_array` or `wasted_cookie`, or repeated in the body, are skipped. With
+/// `validate=true`, each new cookie is bootstrapped first: unusable cookies are not added
+/// and rate limited ones are added as exhausted.
+///
+/// # Arguments
+/// * `s` - Handle of the cookie actor
+/// * `t` - Auth bearer token for admin authentication
+/// * `q` - Format of the body and whether to validate the cookies
+/// * `body` - Cookie list
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - Counts per status and the result of every entry
+pub async fn api_import_cookies(
+    State(s): State<CookieActorHandle>,
+    AuthBearer(t): AuthBearer,
+    Query(q): Query<ImportQuery>,
+    body: String,
+) -> Result<Json<Value>, ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ApiError::unauthorized());
+    }
+    let entries = parse_import(&body, q.format).map_err(ApiError::bad_request)?;
+
+    let mut results = vec![];
+    let mut fresh = vec![];
+    let mut seen = HashSet::new();
+    {
+        let config = CLEWDR_CONFIG.load();
+        for ImportEntry { line, cookie } in entries {
+            let mut cookie = match cookie {
+                Ok(cookie) => cookie,
+                Err(e) => {
+                    results.push(ImportResult {
+                        line,
+                        cookie: None,
+                        status: "error",
+                        error: Some(e),
+                    });
+                    continue;
+                }
+            };
+            if config.cookie_array.contains(&cookie)
+                || config.wasted_cookie.iter().any(|c| *c == cookie)
+                || !seen.insert(cookie.cookie.to_owned())
+            {
+                results.push(ImportResult::new(line, &cookie.cookie, "duplicate"));
+                continue;
+            }
+            cookie.supports_claude_1m_sonnet.get_or_insert(true);
+            cookie.supports_claude_1m_opus.get_or_insert(true);
+            fresh.push((line, cookie));
+        }
+    }
+
+    if q.validate {
+        let concurrency = CLEWDR_CONFIG.load().cookie_health_check_concurrency.max(1);
+        let checked = futures::stream::iter(fresh.into_iter().map(|(line, mut cookie)| {
+            let s = s.to_owned();
+            async move {
+                let result = probe(&s, &mut cookie).await;
+                cookie.last_checked_at = Some(Utc::now().timestamp());
+                cookie.last_error = result.as_ref().err().map(ToString::to_string);
+                (line, cookie, result)
+            }
+        }))
+        .buffered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
+        fresh = vec![];
+        for (line, mut cookie, result) in checked {
+            match result {
+                Ok(()) => fresh.push((line, cookie)),
+                Err(ClewdrError::InvalidCookie {
+                    reason: Reason::TooManyRequest(i) | Reason::Restricted(i),
+                }) => {
+                    cookie.reset_time = Some(i);
+                    fresh.push((line, cookie));
+                }
+                Err(ClewdrError::InvalidCookie {
+                    reason: Reason::TooManyRequestFamily(family, i),
+                }) => {
+                    cookie.set_family_cooldown(family, i);
+                    fresh.push((line, cookie));
+                }
+                Err(e) => {
+                    results.push(ImportResult::new(line, &cookie.cookie, "invalid").with_error(e))
+                }
+            }
+        }
+    }
+
+    let (lines, cookies): (Vec<_>, Vec<_>) = fresh.into_iter().unzip();
+    let added = s
+        .import(cookies.to_owned())
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to import cookies: {}", e)))?;
+    let now = Utc::now().timestamp();
+    for ((line, cookie), added) in lines.into_iter().zip(cookies).zip(added) {
+        let status = match (added, cookie.reset_time) {
+            (false, _) => "duplicate",
+            (true, Some(t)) if t > now => "exhausted",
+            (true, _) => "added",
+        };
+        results.push(ImportResult::new(line, &cookie.cookie, status));
+    }
+    results.sort_by_key(|r| r.line);
+    invalidate_cookie_cache();
+
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let summary = json!({
+        "added": count("added"),
+        "exhausted": count("exhausted"),
+        "duplicate": count("duplicate"),
+        "invalid": count("invalid"),
+        "error": count("error"),
+    });
+    info!("Cookies imported: {}", summary);
+    Ok(Json(json!({
+        "summary": summary,
+        "results": results,
+    })))
+}
+
+/// API endpoint to export every cookie, with its usage and tokens
+///
+/// The document can be imported as is into another instance.
+///
+/// # Arguments
+/// * `s` - Handle of the cookie actor
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - Valid, exhausted and invalid cookies
+pub async fn api_export_cookies(
+    State(s): State<CookieActorHandle>,
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Value>, ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ApiError::unauthorized());
+    }
+    let status = s
+        .get_status()
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to get cookie status: {}", e)))?;
+    Ok(Json(json!({
+        "exported_at": Utc::now().timestamp(),
+        "valid": status.valid,
+        "exhausted": status.exhausted,
+        "invalid": status.invalid,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(id: char) -> String {
+        format!("sk-ant-sid01-{}-AAAAAAAA", id.to_string().repeat(86))
+    }
+
+    #[test]
+    fn parses_lines_csv_and_json() {
+        let body = format!("{}\n\n# comment\nnot a cookie\n", value('a'));
+        let entries = parse_import(&body, ImportFormat::Auto).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].line, 4);
+        assert!(entries[1].cookie.is_err());
+
+        let body = format!("weight,cookie\n3,{}\n", value('b'));
+        let entries = parse_import(&body, ImportFormat::Auto).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].cookie.as_ref().unwrap().weight, Some(3));
+
+        let body = json!({ "valid": [value('c')], "exhausted": [{ "cookie": value('d') }] });
+        let entries = parse_import(&body.to_string(), ImportFormat::Auto).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.cookie.is_ok()));
+    }
+}
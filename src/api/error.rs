@@ -23,6 +23,12 @@ impl ApiError {
             body: serde_json::json!({"error": msg.into()}),
         }
     }
+    pub fn not_found(msg: impl Into<String>) -> Self {
+        Self {
+            code: StatusCode::NOT_FOUND,
+            body: serde_json::json!({"error": msg.into()}),
+        }
+    }
     pub fn internal(msg: impl Into<String>) -> Self {
         Self {
             code: StatusCode::INTERNAL_SERVER_ERROR,
@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use axum::{Extension, extract::State, response::Response};
+
+use crate::{
+    error::ClewdrError,
+    middleware::claude::{ClaudeContext, GeminiPreprocess},
+    providers::{
+        claude::{ClaudeInvocation, ClaudeProviderResponse},
+        gemini::GeminiProvider,
+    },
+    services::response_cache::invoke_cached,
+};
+
+pub async fn api_gemini(
+    State(provider): State<Arc<GeminiProvider>>,
+    GeminiPreprocess(params, context): GeminiPreprocess,
+) -> Result<(Extension<ClaudeContext>, Response), ClewdrError> {
+    let ClaudeProviderResponse { context, response } = invoke_cached(
+        provider.as_ref(),
+        "gemini",
+        ClaudeInvocation::messages(params, context.clone()),
+    )
+    .await?;
+    Ok((Extension(context), response))
+}
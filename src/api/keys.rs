@@ -0,0 +1,161 @@
+use axum::Json;
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::info;
+use wreq::StatusCode;
+
+use super::error::ApiError;
+use crate::{
+    config::{ApiKey, CLEWDR_CONFIG, ClewdrConfig},
+    services::key_usage::KEY_USAGE,
+};
+
+/// Request body identifying a client key to delete
+#[derive(Deserialize)]
+pub struct KeyRef {
+    key: String,
+}
+
+fn check_admin(t: &str) -> Result<(), ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(t) {
+        return Err(ApiError::unauthorized());
+    }
+    Ok(())
+}
+
+async fn save_config() -> Result<(), ApiError> {
+    CLEWDR_CONFIG
+        .load()
+        .save()
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to save config: {}", e)))
+}
+
+/// API endpoint to list all client API keys with their current usage
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - List of keys on success
+pub async fn api_get_keys(AuthBearer(t): AuthBearer) -> Result<Json<Value>, ApiError> {
+    check_admin(&t)?;
+    let keys = CLEWDR_CONFIG
+        .load()
+        .api_keys
+        .iter()
+        .map(|k| ApiKey {
+            usage: KEY_USAGE.usage(&k.key),
+            ..k.to_owned()
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(json!({ "keys": keys })))
+}
+
+/// API endpoint to create a client API key
+/// A random key is generated when `key` is left empty
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `k` - Key definition
+///
+/// # Returns
+/// * `Result<Json<ApiKey>, ApiError>` - The created key on success
+pub async fn api_post_key(
+    AuthBearer(t): AuthBearer,
+    Json(mut k): Json<ApiKey>,
+) -> Result<Json<ApiKey>, ApiError> {
+    check_admin(&t)?;
+    k.key = k.key.trim().to_string();
+    if k.key.is_empty() {
+        k.key = format!("sk-clewdr-{}", uuid::Uuid::new_v4().simple());
+    }
+    let config = CLEWDR_CONFIG.load();
+    if config.user_auth(&k.key) || config.admin_auth(&k.key) {
+        return Err(ApiError::bad_request("Key must differ from the passwords"));
+    }
+    if config.api_keys.iter().any(|e| e.key == k.key) {
+        return Err(ApiError::bad_request("Key already exists"));
+    }
+    k.usage = Default::default();
+    k.created_at = Some(chrono::Utc::now().timestamp());
+    CLEWDR_CONFIG.rcu(|old| {
+        let mut new_c = ClewdrConfig::clone(old);
+        new_c.api_keys.push(k.to_owned());
+        new_c
+    });
+    save_config().await?;
+    info!("API key created: {}", k.ellipse());
+    Ok(Json(k))
+}
+
+/// API endpoint to update a client API key
+/// Replaces label, restrictions and quota while keeping usage counters
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `k` - New key definition, matched by `key`
+///
+/// # Returns
+/// * `Result<Json<ApiKey>, ApiError>` - The updated key on success
+pub async fn api_put_key(
+    AuthBearer(t): AuthBearer,
+    Json(k): Json<ApiKey>,
+) -> Result<Json<ApiKey>, ApiError> {
+    check_admin(&t)?;
+    let Some(old) = CLEWDR_CONFIG
+        .load()
+        .api_keys
+        .iter()
+        .find(|e| e.key == k.key)
+        .cloned()
+    else {
+        return Err(ApiError::not_found("Key not found"));
+    };
+    let updated = ApiKey {
+        usage: old.usage,
+        created_at: old.created_at,
+        ..k
+    };
+    CLEWDR_CONFIG.rcu(|old| {
+        let mut new_c = ClewdrConfig::clone(old);
+        if let Some(e) = new_c.api_keys.iter_mut().find(|e| e.key == updated.key) {
+            *e = ApiKey {
+                usage: e.usage.to_owned(),
+                ..updated.to_owned()
+            };
+        }
+        new_c
+    });
+    save_config().await?;
+    info!("API key updated: {}", updated.ellipse());
+    Ok(Json(updated))
+}
+
+/// API endpoint to delete a client API key
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `k` - Key to delete
+///
+/// # Returns
+/// * `Result<StatusCode, ApiError>` - 204 on success
+pub async fn api_delete_key(
+    AuthBearer(t): AuthBearer,
+    Json(k): Json<KeyRef>,
+) -> Result<StatusCode, ApiError> {
+    check_admin(&t)?;
+    if !CLEWDR_CONFIG.load().api_keys.iter().any(|e| e.key == k.key) {
+        return Err(ApiError::not_found("Key not found"));
+    }
+    CLEWDR_CONFIG.rcu(|old| {
+        let mut new_c = ClewdrConfig::clone(old);
+        new_c.api_keys.retain(|e| e.key != k.key);
+        new_c
+    });
+    KEY_USAGE.remove(&k.key);
+    save_config().await?;
+    info!("API key deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
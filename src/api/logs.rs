@@ -0,0 +1,29 @@
+use axum::{Json, extract::Query};
+use axum_auth::AuthBearer;
+
+use super::error::ApiError;
+use crate::{
+    config::CLEWDR_CONFIG,
+    services::audit::{AUDIT_LOG, AuditPage, AuditQuery},
+};
+
+/// API endpoint to browse the request audit log
+/// Entries are returned newest first
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `query` - Filters (`key`, `endpoint`, `model`, `status`, `since`, `until`)
+///   and pagination (`offset`, `limit`)
+///
+/// # Returns
+/// * `Result<Json<AuditPage>, ApiError>` - Matching entries on success
+pub async fn api_get_logs(
+    AuthBearer(t): AuthBearer,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditPage>, ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ApiError::unauthorized());
+    }
+    AUDIT_LOG.prune();
+    Ok(Json(AUDIT_LOG.query(&query)))
+}
@@ -0,0 +1,45 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, header},
+    response::IntoResponse,
+};
+
+use super::error::ApiError;
+use crate::{
+    config::CLEWDR_CONFIG,
+    services::{cookie_actor::CookieActorHandle, metrics::METRICS},
+};
+
+/// API endpoint exposing metrics in the Prometheus text format
+/// Requires the admin password as Bearer token when `metrics_require_auth` is set
+///
+/// # Arguments
+/// * `s` - Cookie actor handle used to read the cookie pool sizes
+/// * `headers` - Request headers, checked for admin authentication
+///
+/// # Returns
+/// * `Result<impl IntoResponse, ApiError>` - Metrics text on success
+pub async fn api_metrics(
+    State(s): State<CookieActorHandle>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = CLEWDR_CONFIG.load();
+    if config.metrics_require_auth {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !config.admin_auth(token) {
+            return Err(ApiError::unauthorized());
+        }
+    }
+    let cookies = s.get_status().await.ok();
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        METRICS.render(cookies.as_ref()),
+    ))
+}
@@ -19,7 +19,7 @@ use super::error::ApiError;
 use crate::{
     VERSION_INFO,
     claude_code_state::ClaudeCodeState,
-    config::{CLEWDR_CONFIG, CookieStatus},
+    config::{CLEWDR_CONFIG, CookieStatus, Utilization},
     services::cookie_actor::CookieActorHandle,
 };
 
@@ -48,6 +48,11 @@ static COOKIES_CACHE: LazyLock<Cache<String, CookieStatusCache>> = LazyLock::new
 /// Cache key for cookie status
 const COOKIE_STATUS_CACHE_KEY: &str = "all_cookies";
 
+/// Drops the cached cookie status, after the cookies changed
+pub(super) fn invalidate_cookie_cache() {
+    COOKIES_CACHE.invalidate(COOKIE_STATUS_CACHE_KEY);
+}
+
 /// API endpoint to submit a new cookie
 /// Validates and adds the cookie to the cookie manager
 ///
@@ -92,8 +97,9 @@ pub async fn api_post_cookie(
     }
 }
 
-/// API endpoint to update per-cookie 1M support settings
-/// Only updates supports_claude_1m_sonnet / supports_claude_1m_opus on existing cookies
+/// API endpoint to update per-cookie settings
+/// Updates supports_claude_1m_sonnet / supports_claude_1m_opus and, when given, weight, label, notes,
+/// owner and group on existing cookies, an empty string clears a metadata field
 pub async fn api_put_cookie(
     State(s): State<CookieActorHandle>,
     AuthBearer(t): AuthBearer,
@@ -109,17 +115,17 @@ pub async fn api_put_cookie(
         c.supports_claude_1m_opus = Some(true);
     }
 
-    match s.update_cookie_1m_support(c.clone()).await {
+    match s.update_cookie_settings(c.clone()).await {
         Ok(_) => {
-            info!("Cookie 1M flags updated: {}", c.cookie);
+            info!("Cookie settings updated: {}", c.cookie);
             COOKIES_CACHE.invalidate(COOKIE_STATUS_CACHE_KEY);
             info!("Cookie status cache invalidated after cookie update");
             Ok(StatusCode::OK)
         }
         Err(e) => {
-            error!("Failed to update cookie 1M flags: {}", e);
+            error!("Failed to update cookie settings: {}", e);
             Err(ApiError::bad_request(format!(
-                "Failed to update cookie 1M flags: {}",
+                "Failed to update cookie settings: {}",
                 e
             )))
         }
@@ -385,6 +391,12 @@ async fn fetch_usage_percent(
 )> {
     let mut state = ClaudeCodeState::from_cookie(handle, cookie).ok()?;
     let usage = state.fetch_usage_metrics().await.ok()?;
+    if let Some(ref mut cookie) = state.cookie {
+        cookie.utilization = Some(Utilization::from_usage(
+            &usage,
+            chrono::Utc::now().timestamp(),
+        ));
+    }
     state.return_cookie(None).await;
     let five = usage
         .get("five_hour")
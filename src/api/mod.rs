@@ -1,17 +1,44 @@
+mod cache;
+mod claude_api;
 mod claude_code;
 mod claude_web;
 mod config;
+mod cookie_bulk;
 mod error;
+mod gemini;
+mod keys;
+mod logs;
+mod metrics;
 mod misc;
+mod oauth;
+mod pool_keys;
+/// Response cache management endpoint
+pub use cache::api_purge_cache;
+/// Anthropic API key backend message endpoint
+pub use claude_api::api_claude_api;
 pub use claude_code::{api_claude_code, api_claude_code_count_tokens};
 /// Message handling endpoints for creating and managing chat conversations
 pub use claude_web::api_claude_web;
 /// Configuration related endpoints for retrieving and updating Clewdr settings
 pub use config::{api_get_config, api_post_config};
+/// Bulk cookie import and export endpoints
+pub use cookie_bulk::{api_export_cookies, api_import_cookies};
 pub use error::ApiError;
+/// Gemini message endpoint
+pub use gemini::api_gemini;
+/// Client API key management endpoints
+pub use keys::{api_delete_key, api_get_keys, api_post_key, api_put_key};
+/// Request audit log endpoint
+pub use logs::api_get_logs;
+/// Prometheus metrics endpoint
+pub use metrics::api_metrics;
 /// Miscellaneous endpoints for authentication, cookies, and version information
 pub use misc::{
     api_auth, api_delete_cookie, api_get_cookies, api_get_models, api_post_cookie, api_put_cookie,
     api_version,
 };
+/// OAuth login endpoints for accounts without a session cookie
+pub use oauth::{api_oauth_authorize, api_oauth_code, api_oauth_refresh};
+/// Upstream key pool management endpoints
+pub use pool_keys::{api_delete_pool_key, api_get_pool_keys, api_post_pool_key};
 // merged above
@@ -0,0 +1,199 @@
+use std::{sync::LazyLock, time::Duration};
+
+use axum::{Json, extract::State};
+use axum_auth::AuthBearer;
+use moka::sync::Cache;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::info;
+
+use super::{error::ApiError, misc::invalidate_cookie_cache};
+use crate::{
+    claude_code_state::ClaudeCodeState,
+    config::{CLEWDR_CONFIG, ClewdrCookie, CookieStatus, TokenInfo},
+    services::cookie_actor::CookieActorHandle,
+};
+
+/// PKCE verifiers of the pending logins, by state (TTL: 10 minutes)
+static PENDING_LOGINS: LazyLock<Cache<String, String>> = LazyLock::new(|| {
+    Cache::builder()
+        .max_capacity(64)
+        .time_to_live(Duration::from_secs(600)) // 10 minutes
+        .build()
+});
+
+/// Body of the code submission endpoint
+#[derive(Deserialize)]
+pub struct OauthCodeBody {
+    /// Code shown by claude.ai, either alone or as `code#state`
+    code: String,
+    /// State of the login, when not pasted with the code
+    #[serde(default)]
+    state: Option<String>,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    group: Option<String>,
+}
+
+/// Body of the refresh token import endpoint
+#[derive(Deserialize)]
+pub struct OauthRefreshBody {
+    refresh_token: String,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    group: Option<String>,
+}
+
+/// Adds an account that holds only a token to the cookie actor
+///
+/// The account is identified by the uuid of its owner when the token exchange returns one,
+/// so an account logged in twice, or already added with its session cookie, is refused.
+async fn add_account(
+    s: &CookieActorHandle,
+    mut cookie: CookieStatus,
+) -> Result<Json<Value>, ApiError> {
+    if let Some(owner) = cookie.token.as_ref().and_then(TokenInfo::owner_uuid) {
+        let owned =
+            |c: &CookieStatus| c.token.as_ref().and_then(TokenInfo::owner_uuid) == Some(owner);
+        if CLEWDR_CONFIG.load().cookie_array.iter().any(owned) {
+            return Err(ApiError::bad_request("Account already exists"));
+        }
+        if let Some(identity) = ClewdrCookie::oauth_owner(owner) {
+            cookie.cookie = identity;
+        }
+    }
+    let added = s
+        .import(vec![cookie.to_owned()])
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to add account: {}", e)))?;
+    if !added.first().copied().unwrap_or_default() {
+        return Err(ApiError::bad_request("Account already exists"));
+    }
+    invalidate_cookie_cache();
+    info!("OAuth account added: {}", cookie.cookie);
+    Ok(Json(json!({
+        "cookie": cookie.cookie.to_string(),
+        "expires_at": cookie.token.map(|t| t.expires_at.timestamp()),
+    })))
+}
+
+/// API endpoint to start an OAuth login
+///
+/// Open the returned URL in a browser signed in to claude.ai, then submit the code it
+/// shows to [`api_oauth_code`] within 10 minutes.
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - Authorization URL and state of the login
+pub async fn api_oauth_authorize(AuthBearer(t): AuthBearer) -> Result<Json<Value>, ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ApiError::unauthorized());
+    }
+    let authorization = ClaudeCodeState::authorize_url()
+        .map_err(|e| ApiError::internal(format!("Failed to build authorization URL: {}", e)))?;
+    PENDING_LOGINS.insert(
+        authorization.state.to_owned(),
+        authorization.verifier.to_owned(),
+    );
+    Ok(Json(json!({
+        "url": authorization.url.to_string(),
+        "state": authorization.state,
+    })))
+}
+
+/// API endpoint to finish an OAuth login with the code shown by claude.ai
+///
+/// The token is stored in a new account without a session cookie, which only serves
+/// Claude Code requests.
+///
+/// # Arguments
+/// * `s` - Handle of the cookie actor
+/// * `t` - Auth bearer token for admin authentication
+/// * `body` - Pasted code, with the state of the login and the metadata of the account
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - Identifier of the new account and token expiry
+pub async fn api_oauth_code(
+    State(s): State<CookieActorHandle>,
+    AuthBearer(t): AuthBearer,
+    Json(body): Json<OauthCodeBody>,
+) -> Result<Json<Value>, ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ApiError::unauthorized());
+    }
+    let (code, state) = match body.code.trim().split_once('#') {
+        Some((code, state)) => (code.to_string(), Some(state.to_string())),
+        None => (body.code.trim().to_string(), body.state),
+    };
+    let state = state.ok_or_else(|| ApiError::bad_request("Missing login state"))?;
+    let verifier = PENDING_LOGINS
+        .remove(&state)
+        .ok_or_else(|| ApiError::bad_request("Unknown or expired login, start a new one"))?;
+
+    let mut cookie = CookieStatus::oauth();
+    cookie.label = body.label;
+    cookie.group = body.group;
+    let mut code_state = ClaudeCodeState::from_cookie(s.to_owned(), cookie)
+        .map_err(|e| ApiError::internal(e.to_string()))?;
+    code_state
+        .exchange_pasted_code(code, Some(state), verifier)
+        .await
+        .map_err(|e| ApiError::bad_request(format!("Failed to exchange code: {}", e)))?;
+    let cookie = code_state
+        .cookie
+        .ok_or_else(|| ApiError::internal("No account after exchange"))?;
+    add_account(&s, cookie).await
+}
+
+/// API endpoint to add an account from an existing Claude Code refresh token
+///
+/// The token is refreshed once to check it, which invalidates the submitted refresh
+/// token wherever else it is used.
+///
+/// # Arguments
+/// * `s` - Handle of the cookie actor
+/// * `t` - Auth bearer token for admin authentication
+/// * `body` - Refresh token and metadata of the account
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - Identifier of the new account and token expiry
+pub async fn api_oauth_refresh(
+    State(s): State<CookieActorHandle>,
+    AuthBearer(t): AuthBearer,
+    Json(body): Json<OauthRefreshBody>,
+) -> Result<Json<Value>, ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ApiError::unauthorized());
+    }
+    let refresh_token = body.refresh_token.trim().to_string();
+    if refresh_token.is_empty() {
+        return Err(ApiError::bad_request("Missing refresh token"));
+    }
+    // refreshing would revoke the token of the existing account
+    if CLEWDR_CONFIG.load().cookie_array.iter().any(|c| {
+        c.token
+            .as_ref()
+            .is_some_and(|t| t.refresh_token == refresh_token)
+    }) {
+        return Err(ApiError::bad_request("Account already exists"));
+    }
+
+    let mut cookie = CookieStatus::oauth();
+    cookie.token = Some(TokenInfo::from_refresh_token(refresh_token));
+    cookie.label = body.label;
+    cookie.group = body.group;
+    let mut code_state = ClaudeCodeState::from_cookie(s.to_owned(), cookie)
+        .map_err(|e| ApiError::internal(e.to_string()))?;
+    code_state
+        .refresh_token()
+        .await
+        .map_err(|e| ApiError::bad_request(format!("Failed to refresh token: {}", e)))?;
+    let cookie = code_state
+        .cookie
+        .ok_or_else(|| ApiError::internal("No account after refresh"))?;
+    add_account(&s, cookie).await
+}
@@ -0,0 +1,90 @@
+use axum::{Json, extract::State};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::info;
+use wreq::StatusCode;
+
+use super::error::ApiError;
+use crate::{
+    config::{CLEWDR_CONFIG, PooledKey},
+    services::key_pool::KeyPoolHandle,
+};
+
+/// Request body identifying an upstream key
+#[derive(Deserialize)]
+pub struct PoolKeyRef {
+    key: String,
+}
+
+fn check_admin(t: &str) -> Result<(), ApiError> {
+    if !CLEWDR_CONFIG.load().admin_auth(t) {
+        return Err(ApiError::unauthorized());
+    }
+    Ok(())
+}
+
+/// API endpoint to list the keys of an upstream key pool
+///
+/// # Arguments
+/// * `s` - Handle of the key pool
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<Value>, ApiError>` - Keys with their cooldown and validity
+pub async fn api_get_pool_keys(
+    State(s): State<KeyPoolHandle>,
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Value>, ApiError> {
+    check_admin(&t)?;
+    let keys = s
+        .get_status()
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to get keys: {}", e)))?;
+    Ok(Json(json!({ "keys": keys })))
+}
+
+/// API endpoint to add a key to an upstream key pool
+///
+/// # Arguments
+/// * `s` - Handle of the key pool
+/// * `t` - Auth bearer token for admin authentication
+/// * `k` - Key to add
+///
+/// # Returns
+/// * `Result<StatusCode, ApiError>` - OK when the key was added
+pub async fn api_post_pool_key(
+    State(s): State<KeyPoolHandle>,
+    AuthBearer(t): AuthBearer,
+    Json(k): Json<PoolKeyRef>,
+) -> Result<StatusCode, ApiError> {
+    check_admin(&t)?;
+    let key = PooledKey::new(k.key.trim());
+    let ellipsed = key.ellipse();
+    s.submit(key)
+        .await
+        .map_err(|e| ApiError::bad_request(format!("Failed to add key: {}", e)))?;
+    info!("Upstream key added: {}", ellipsed);
+    Ok(StatusCode::OK)
+}
+
+/// API endpoint to remove a key from an upstream key pool
+///
+/// # Arguments
+/// * `s` - Handle of the key pool
+/// * `t` - Auth bearer token for admin authentication
+/// * `k` - Key to remove
+///
+/// # Returns
+/// * `Result<StatusCode, ApiError>` - OK when the key was removed
+pub async fn api_delete_pool_key(
+    State(s): State<KeyPoolHandle>,
+    AuthBearer(t): AuthBearer,
+    Json(k): Json<PoolKeyRef>,
+) -> Result<StatusCode, ApiError> {
+    check_admin(&t)?;
+    s.delete_key(k.key)
+        .await
+        .map_err(|e| ApiError::not_found(format!("Failed to delete key: {}", e)))?;
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,199 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use axum::response::{IntoResponse, Sse, sse::Event as SseEvent};
+use chrono::Utc;
+use colored::Colorize;
+use eventsource_stream::Eventsource;
+use futures::TryStreamExt;
+use http::header::CONTENT_TYPE;
+use snafu::{GenerateImplicitData, ResultExt};
+use tracing::{Instrument, error, info};
+
+use crate::{
+    claude_api_state::{ClaudeApiState, ratelimit_reset},
+    config::{CLEWDR_CONFIG, ModelFamily, Reason},
+    error::{CheckClaudeErr, ClewdrError, WreqSnafu},
+    services::{audit::AUDIT_LOG, key_usage::record_usage, metrics::METRICS},
+    types::claude::{CreateMessageParams, StreamEvent},
+};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+impl ClaudeApiState {
+    /// Attempts to send a chat message to the Anthropic API with retry mechanism
+    ///
+    /// Each attempt uses the next key of the pool. Rate limited or rejected keys are
+    /// returned to the pool with the reason and the request is retried with another key.
+    ///
+    /// # Arguments
+    /// * `p` - The client request body containing messages and configuration
+    ///
+    /// # Returns
+    /// * `Result<axum::response::Response, ClewdrError>` - Response or error
+    pub async fn try_chat(
+        &mut self,
+        p: CreateMessageParams,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
+            if i > 0 {
+                info!("[RETRY] attempt: {}", i.to_string().green());
+                METRICS.record_retry("api");
+            }
+            let key = self.request_key().await?;
+            AUDIT_LOG.record_attempt(self.audit_id, i, key.ellipse());
+            let res = self
+                .send_chat(&key.key, &p)
+                .instrument(tracing::info_span!("claude_api", "key" = key.ellipse()));
+            match res.await {
+                Ok(res) => return Ok(res),
+                Err(e) => {
+                    error!("[{}] {}", key.ellipse().green(), e);
+                    if let ClewdrError::InvalidKey { reason } = e {
+                        self.return_key(Some(reason)).await;
+                        continue;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Err(ClewdrError::TooManyRetries)
+    }
+
+    async fn send_chat(
+        &self,
+        key: &str,
+        p: &CreateMessageParams,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        let mut req = self
+            .client
+            .post(
+                self.endpoint
+                    .join("v1/messages")
+                    .expect("Url parse error")
+                    .to_string(),
+            )
+            .header("x-api-key", key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(p);
+        if let Some(ref beta) = self.anthropic_beta_header {
+            req = req.header("anthropic-beta", beta);
+        }
+        let response = req.send().await.context(WreqSnafu {
+            msg: "Failed to send chat message",
+        })?;
+        let reset = ratelimit_reset(response.headers(), Utc::now().timestamp());
+        // errors are classified like cookie errors, then attributed to the key
+        let response = match response.check_claude().await {
+            Ok(response) => response,
+            Err(ClewdrError::InvalidCookie {
+                reason: Reason::TooManyRequest(ts) | Reason::TooManyRequestFamily(_, ts),
+            }) => {
+                return Err(ClewdrError::InvalidKey {
+                    reason: Reason::TooManyRequest(reset.unwrap_or(ts)),
+                });
+            }
+            Err(ClewdrError::InvalidCookie { reason }) => {
+                return Err(ClewdrError::InvalidKey { reason });
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(reset) = reset {
+            // this request used up a limit, cool the key down until it resets
+            self.return_key(Some(Reason::TooManyRequest(reset))).await;
+        }
+
+        let family = ModelFamily::from_model(&p.model);
+        if !self.stream {
+            return self.materialize_non_stream_response(response, family).await;
+        }
+        Ok(self.forward_stream_with_usage(response, family))
+    }
+
+    async fn materialize_non_stream_response(
+        &self,
+        response: wreq::Response,
+        family: ModelFamily,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        let status = response.status();
+        let content_type = response.headers().get(CONTENT_TYPE).cloned();
+        let bytes = response.bytes().await.context(WreqSnafu {
+            msg: "Failed to read Claude response body",
+        })?;
+        if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+            let usage = &value["usage"];
+            let input = usage["input_tokens"]
+                .as_u64()
+                .unwrap_or(self.usage.input_tokens as u64);
+            let output = usage["output_tokens"].as_u64().unwrap_or_default();
+            record_usage(
+                "api",
+                family,
+                self.api_key.as_deref(),
+                self.audit_id,
+                input,
+                output,
+            );
+        }
+        let mut builder = http::Response::builder().status(status);
+        if let Some(content_type) = content_type {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+        builder
+            .body(axum::body::Body::from(bytes))
+            .map_err(|e| ClewdrError::HttpError {
+                loc: snafu::Location::generate(),
+                source: e,
+            })
+    }
+
+    fn forward_stream_with_usage(
+        &self,
+        response: wreq::Response,
+        family: ModelFamily,
+    ) -> axum::response::Response {
+        let input = Arc::new(AtomicU64::new(self.usage.input_tokens as u64));
+        let output = Arc::new(AtomicU64::new(0));
+        let api_key = self.api_key.to_owned();
+        let audit_id = self.audit_id;
+
+        let stream = response.bytes_stream().eventsource().map_ok(move |event| {
+            match serde_json::from_str::<StreamEvent>(&event.data) {
+                Ok(StreamEvent::MessageStart { message }) => {
+                    if let Some(usage) = message.usage {
+                        input.store(usage.input_tokens as u64, Ordering::Relaxed);
+                    }
+                }
+                // output tokens in message_delta are cumulative
+                Ok(StreamEvent::MessageDelta { usage: Some(u), .. }) => {
+                    output.store(u.output_tokens as u64, Ordering::Relaxed);
+                }
+                Ok(StreamEvent::MessageStop) => {
+                    record_usage(
+                        "api",
+                        family,
+                        api_key.as_deref(),
+                        audit_id,
+                        input.load(Ordering::Relaxed),
+                        output.load(Ordering::Relaxed),
+                    );
+                }
+                _ => {}
+            }
+            // mirror upstream SSE event unchanged
+            let e = SseEvent::default().event(event.event).id(event.id);
+            let e = if let Some(retry) = event.retry {
+                e.retry(retry)
+            } else {
+                e
+            };
+            e.data(event.data)
+        });
+
+        Sse::new(stream)
+            .keep_alive(Default::default())
+            .into_response()
+    }
+}
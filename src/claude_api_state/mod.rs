@@ -0,0 +1,126 @@
+mod chat;
+use chrono::DateTime;
+use http::HeaderMap;
+use tracing::error;
+
+use crate::{
+    config::{CLEWDR_CONFIG, PooledKey, Reason},
+    error::ClewdrError,
+    middleware::claude::ClaudeApiFormat,
+    services::key_pool::KeyPoolHandle,
+    types::claude::Usage,
+};
+
+/// Limits reported in the `anthropic-ratelimit-<limit>-remaining` / `-reset` headers
+const RATE_LIMITS: [&str; 4] = ["requests", "tokens", "input-tokens", "output-tokens"];
+
+/// Unix timestamp until which a key is rate limited according to the response headers
+///
+/// Only limits with nothing remaining are taken into account, the latest of their
+/// reset times wins. `retry-after` is used when no exhausted limit reports one.
+pub fn ratelimit_reset(headers: &HeaderMap, now: i64) -> Option<i64> {
+    let header = |name: String| headers.get(name).and_then(|v| v.to_str().ok());
+    let reset = RATE_LIMITS
+        .iter()
+        .filter(|limit| {
+            header(format!("anthropic-ratelimit-{limit}-remaining"))
+                .and_then(|v| v.parse::<u64>().ok())
+                == Some(0)
+        })
+        .filter_map(|limit| header(format!("anthropic-ratelimit-{limit}-reset")))
+        .filter_map(|v| DateTime::parse_from_rfc3339(v).ok())
+        .map(|t| t.timestamp())
+        .max();
+    reset.or_else(|| {
+        header("retry-after".to_string())
+            .and_then(|v| v.parse::<i64>().ok())
+            .map(|secs| now + secs)
+    })
+}
+
+/// State of a request forwarded to the Anthropic API with a plain API key
+#[derive(Clone)]
+pub struct ClaudeApiState {
+    pub key_pool_handle: KeyPoolHandle,
+    pub key: Option<PooledKey>,
+    pub endpoint: url::Url,
+    pub client: wreq::Client,
+    pub api_format: ClaudeApiFormat,
+    pub stream: bool,
+    pub anthropic_beta_header: Option<String>,
+    pub usage: Usage,
+    pub api_key: Option<String>,
+    pub audit_id: Option<u64>,
+}
+
+impl ClaudeApiState {
+    /// Create a new ClaudeApiState instance
+    pub fn new(key_pool_handle: KeyPoolHandle, client: wreq::Client) -> Self {
+        ClaudeApiState {
+            key_pool_handle,
+            key: None,
+            endpoint: CLEWDR_CONFIG.load().endpoint(),
+            client,
+            api_format: ClaudeApiFormat::Claude,
+            stream: false,
+            anthropic_beta_header: None,
+            usage: Usage::default(),
+            api_key: None,
+            audit_id: None,
+        }
+    }
+
+    /// Requests a new key from the key pool
+    pub async fn request_key(&mut self) -> Result<PooledKey, ClewdrError> {
+        let key = self.key_pool_handle.request().await?;
+        self.key = Some(key.to_owned());
+        Ok(key)
+    }
+
+    /// Returns the current key to the key pool
+    /// Optionally provides a reason for returning the key (e.g., rate limited, invalid)
+    pub async fn return_key(&self, reason: Option<Reason>) {
+        if let Some(ref key) = self.key {
+            self.key_pool_handle
+                .return_key(key.to_owned(), reason)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to send key: {}", e);
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn parses_exhausted_limit_reset() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-requests-remaining",
+            HeaderValue::from_static("12"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-reset",
+            HeaderValue::from_static("2030-01-01T00:10:00Z"),
+        );
+        headers.insert("retry-after", HeaderValue::from_static("30"));
+        assert_eq!(ratelimit_reset(&headers, 1000), Some(1030));
+
+        headers.insert(
+            "anthropic-ratelimit-input-tokens-remaining",
+            HeaderValue::from_static("0"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-input-tokens-reset",
+            HeaderValue::from_static("2030-01-01T00:00:00Z"),
+        );
+        assert_eq!(ratelimit_reset(&headers, 1000), Some(1893456000));
+
+        assert_eq!(ratelimit_reset(&HeaderMap::new(), 1000), None);
+    }
+}
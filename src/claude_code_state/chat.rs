@@ -7,16 +7,22 @@ use axum::{
 use colored::Colorize;
 use eventsource_stream::Eventsource;
 use futures::TryStreamExt;
-use http::header::{ACCEPT, USER_AGENT};
+use http::{
+    HeaderMap, HeaderName,
+    header::{ACCEPT, USER_AGENT},
+};
 use snafu::{GenerateImplicitData, ResultExt};
 use tracing::{Instrument, error, info, warn};
 use wreq::Method;
 
 use crate::{
-    claude_code_state::{ClaudeCodeState, TokenStatus},
-    config::{CLEWDR_CONFIG, Claude1mChannel, ModelFamily},
+    claude_code_state::ClaudeCodeState,
+    config::{CLEWDR_CONFIG, Claude1mChannel, ModelFamily, UNIFIED_RATELIMIT_PREFIX},
     error::{CheckClaudeErr, ClewdrError, WreqSnafu},
-    services::cookie_actor::CookieActorHandle,
+    services::{
+        audit::AUDIT_LOG, cookie_actor::CookieActorHandle, key_usage::record_usage,
+        metrics::METRICS,
+    },
     types::claude::{CountMessageTokensResponse, CreateMessageParams},
 };
 
@@ -49,41 +55,20 @@ impl ClaudeCodeState {
         &mut self,
         p: CreateMessageParams,
     ) -> Result<axum::response::Response, ClewdrError> {
+        let family = ModelFamily::from_model(&p.model);
         for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
             if i > 0 {
                 info!("[RETRY] attempt: {}", i.to_string().green());
+                METRICS.record_retry("code");
             }
             let mut state = self.to_owned();
             let p = p.to_owned();
 
-            let cookie = state.request_cookie().await?;
+            let cookie = state.request_cookie(family).await?;
+            AUDIT_LOG.record_attempt(state.audit_id, i, cookie.cookie.ellipse());
             let retry = async {
-                match state.check_token() {
-                    TokenStatus::None => {
-                        info!("No token found, requesting new token");
-                        let org = state.get_organization().await?;
-                        let code_res = state.exchange_code(&org).await?;
-                        state.exchange_token(code_res).await?;
-                        state.return_cookie(None).await;
-                    }
-                    TokenStatus::Expired => {
-                        info!("Token expired, refreshing token");
-                        state.refresh_token().await?;
-                        state.return_cookie(None).await;
-                    }
-                    TokenStatus::Valid => {
-                        info!("Token is valid, proceeding with request");
-                    }
-                }
-                let Some(access_token) = state.cookie.as_ref().and_then(|c| c.token.to_owned())
-                else {
-                    return Err(ClewdrError::UnexpectedNone {
-                        msg: "No access token found in cookie",
-                    });
-                };
-                state
-                    .send_chat(access_token.access_token.to_owned(), p)
-                    .await
+                let access_token = state.ensure_token().await?;
+                state.send_chat(access_token, p).await
             }
             .instrument(tracing::info_span!(
                 "claude_code",
@@ -138,7 +123,7 @@ impl ClaudeCodeState {
             vec![false]
         };
 
-        let model_family = Self::classify_model(&p.model);
+        let model_family = ModelFamily::from_model(&p.model);
         for (idx, use_context_1m) in attempts.iter().copied().enumerate() {
             match self
                 .execute_claude_request(&access_token, &p, use_context_1m)
@@ -150,7 +135,15 @@ impl ClaudeCodeState {
                     {
                         self.persist_claude_1m_support(ch, true).await;
                     }
-                    return self.handle_success_response(response, model_family).await;
+                    self.record_ratelimit(response.headers()).await;
+                    let relayed = if CLEWDR_CONFIG.load().relay_ratelimit_headers {
+                        Self::relayed_ratelimit_headers(response.headers())
+                    } else {
+                        HeaderMap::new()
+                    };
+                    let mut resp = self.handle_success_response(response, model_family).await?;
+                    resp.headers_mut().extend(relayed);
+                    return Ok(resp);
                 }
                 Err(err) => {
                     let is_last_attempt = idx + 1 == attempts.len();
@@ -228,28 +221,36 @@ impl ClaudeCodeState {
         }
     }
 
-    pub async fn fetch_usage_metrics(&mut self) -> Result<serde_json::Value, ClewdrError> {
-        match self.check_token() {
-            TokenStatus::None => {
-                let org = self.get_organization().await?;
-                let code = self.exchange_code(&org).await?;
-                self.exchange_token(code).await?;
-            }
-            TokenStatus::Expired => {
-                self.refresh_token().await?;
-            }
-            TokenStatus::Valid => {}
+    /// Records the unified rate limit headers of a response in the utilization of the cookie
+    async fn record_ratelimit(&mut self, headers: &HeaderMap) {
+        let Some(cookie) = self.cookie.as_mut() else {
+            return;
+        };
+        let mut utilization = cookie.utilization.to_owned().unwrap_or_default();
+        if !utilization.apply_headers(headers, chrono::Utc::now().timestamp()) {
+            return;
         }
+        cookie.utilization = Some(utilization);
+        let cloned = cookie.clone();
+        if let Err(err) = self.cookie_actor_handle.record_utilization(cloned).await {
+            warn!("Failed to record rate limit headers: {}", err);
+        }
+    }
 
-        let access_token = self
-            .cookie
-            .as_ref()
-            .and_then(|c| c.token.as_ref())
-            .ok_or(ClewdrError::UnexpectedNone {
-                msg: "No access token available",
-            })?
-            .access_token
-            .to_owned();
+    /// Unified rate limit headers of a response, renamed to `x-clewdr-ratelimit-*`
+    fn relayed_ratelimit_headers(headers: &HeaderMap) -> HeaderMap {
+        headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let suffix = name.as_str().strip_prefix(UNIFIED_RATELIMIT_PREFIX)?;
+                let name = HeaderName::try_from(format!("x-clewdr-ratelimit-{suffix}")).ok()?;
+                Some((name, value.to_owned()))
+            })
+            .collect()
+    }
+
+    pub async fn fetch_usage_metrics(&mut self) -> Result<serde_json::Value, ClewdrError> {
+        let access_token = self.ensure_token().await?;
 
         self.client
             .request(Method::GET, CLAUDE_USAGE_URL)
@@ -276,6 +277,7 @@ impl ClaudeCodeState {
         p: CreateMessageParams,
         for_web: bool,
     ) -> Result<axum::response::Response, ClewdrError> {
+        let family = ModelFamily::from_model(&p.model);
         for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
             if i > 0 {
                 info!("[TOKENS][RETRY] attempt: {}", i.to_string().green());
@@ -283,7 +285,7 @@ impl ClaudeCodeState {
             let mut state = self.to_owned();
             let p = p.to_owned();
 
-            let cookie = state.request_cookie().await?;
+            let cookie = state.request_cookie(family).await?;
             let web_attempt_allowed = CLEWDR_CONFIG.load().enable_web_count_tokens;
             let cookie_disallows = matches!(cookie.count_tokens_allowed, Some(false));
             if cookie_disallows || (for_web && !web_attempt_allowed) {
@@ -293,32 +295,8 @@ impl ClaudeCodeState {
                 return Ok(Self::local_count_tokens_response(&p));
             }
             let retry = async {
-                match state.check_token() {
-                    TokenStatus::None => {
-                        info!("No token found, requesting new token");
-                        let org = state.get_organization().await?;
-                        let code_res = state.exchange_code(&org).await?;
-                        state.exchange_token(code_res).await?;
-                        state.return_cookie(None).await;
-                    }
-                    TokenStatus::Expired => {
-                        info!("Token expired, refreshing token");
-                        state.refresh_token().await?;
-                        state.return_cookie(None).await;
-                    }
-                    TokenStatus::Valid => {
-                        info!("Token is valid, proceeding with count_tokens");
-                    }
-                }
-                let Some(access_token) = state.cookie.as_ref().and_then(|c| c.token.to_owned())
-                else {
-                    return Err(ClewdrError::UnexpectedNone {
-                        msg: "No access token found in cookie",
-                    });
-                };
-                state
-                    .perform_count_tokens(access_token.access_token.to_owned(), p, for_web)
-                    .await
+                let access_token = state.ensure_token().await?;
+                state.perform_count_tokens(access_token, p, for_web).await
             }
             .instrument(tracing::info_span!(
                 "claude_code_tokens",
@@ -386,6 +364,7 @@ impl ClaudeCodeState {
                         self.persist_claude_1m_support(ch, true).await;
                     }
                     self.persist_count_tokens_allowed(true).await;
+                    self.record_ratelimit(response.headers()).await;
                     let (resp, _) = Self::materialize_non_stream_response(response).await?;
                     return Ok(resp);
                 }
@@ -439,6 +418,14 @@ impl ClaudeCodeState {
         if input == 0 && output == 0 {
             return;
         }
+        record_usage(
+            "code",
+            family,
+            self.api_key.as_deref(),
+            self.audit_id,
+            input,
+            output,
+        );
         if let Some(cookie) = self.cookie.as_mut() {
             // Lazy boundary refresh if due, then reset period counters and start fresh
             Self::update_cookie_boundaries_if_due(cookie, &self.cookie_actor_handle).await;
@@ -464,6 +451,10 @@ impl ClaudeCodeState {
         let output_sum = Arc::new(AtomicU64::new(0));
         let handle = self.cookie_actor_handle.clone();
         let cookie = self.cookie.clone();
+        // the cookie stays leased until the message is complete
+        let mut lease = self.lease.clone();
+        let api_key = self.api_key.clone();
+        let audit_id = self.audit_id;
 
         let osum = output_sum.clone();
         let stream = response.bytes_stream().eventsource().map_ok(move |event| {
@@ -476,9 +467,18 @@ impl ClaudeCodeState {
                         osum.fetch_add(u.output_tokens as u64, Ordering::Relaxed);
                     }
                     crate::types::claude::StreamEvent::MessageStop => {
+                        lease.take();
+                        let total_out = osum.load(Ordering::Relaxed);
+                        record_usage(
+                            "code",
+                            family,
+                            api_key.as_deref(),
+                            audit_id,
+                            input_tokens,
+                            total_out,
+                        );
                         // on stream completion, persist totals asynchronously
                         if let (Some(cookie), handle) = (cookie.clone(), handle.clone()) {
-                            let total_out = osum.load(Ordering::Relaxed);
                             let mut c = cookie.clone();
                             tokio::spawn(async move {
                                 // Update period boundaries if needed, then accumulate
@@ -638,17 +638,6 @@ impl ClaudeCodeState {
         model.starts_with("claude-opus-4-6")
     }
 
-    fn classify_model(model: &str) -> ModelFamily {
-        let m = model.to_ascii_lowercase();
-        if m.contains("opus") {
-            ModelFamily::Opus
-        } else if m.contains("sonnet") {
-            ModelFamily::Sonnet
-        } else {
-            ModelFamily::Other
-        }
-    }
-
     // ---------------------------------------------
     // Lazy boundary refresh (no timers, fetch-on-due)
     // ---------------------------------------------
@@ -5,10 +5,7 @@ use oauth2::{
     AsyncHttpClient, AuthUrl, AuthorizationCode, Client, ClientId, CsrfToken, EndpointNotSet,
     EndpointSet, HttpClientError, HttpRequest, HttpResponse, PkceCodeChallenge, PkceCodeVerifier,
     RedirectUrl, Scope, StandardRevocableToken, TokenUrl,
-    basic::{
-        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
-        BasicTokenResponse,
-    },
+    basic::{BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse},
     http::{
         self,
         header::{HeaderName, HeaderValue},
@@ -20,7 +17,10 @@ use url::Url;
 
 use crate::{
     claude_code_state::ClaudeCodeState,
-    config::{CC_REDIRECT_URI, CC_TOKEN_URL, CLEWDR_CONFIG, CookieStatus, TokenInfo},
+    config::{
+        CC_AUTHORIZE_URL, CC_REDIRECT_URI, CC_TOKEN_URL, CLEWDR_CONFIG, ClaudeTokenResponse,
+        CookieStatus, TokenInfo,
+    },
     error::{CheckClaudeErr, ClewdrError, UnexpectedNoneSnafu, UrlSnafu, WreqSnafu},
 };
 
@@ -28,9 +28,18 @@ use super::chat::{CLAUDE_API_VERSION, CLAUDE_BETA_BASE};
 
 const CLAUDE_CODE_USER_AGENT: &str = "claude-code/2.0.32";
 
+/// OAuth client reading the account and organization of the token responses
+type ClaudeClient = Client<
+    BasicErrorResponse,
+    ClaudeTokenResponse,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
 type ClaudeOauthClient = Client<
     BasicErrorResponse,
-    BasicTokenResponse,
+    ClaudeTokenResponse,
     BasicTokenIntrospectionResponse,
     StandardRevocableToken,
     BasicRevocationErrorResponse,
@@ -95,8 +104,15 @@ pub struct ExchangeResult {
     org_uuid: String,
 }
 
+/// Authorization URL to open in a browser, with the PKCE verifier needed to redeem its code
+pub struct ManualAuthorization {
+    pub url: Url,
+    pub state: String,
+    pub verifier: String,
+}
+
 fn setup_client(cc_client_id: String) -> Result<ClaudeOauthClient, ClewdrError> {
-    Ok(oauth2::basic::BasicClient::new(ClientId::new(cc_client_id))
+    Ok(ClaudeClient::new(ClientId::new(cc_client_id))
         .set_auth_type(oauth2::AuthType::RequestBody)
         .set_redirect_uri(RedirectUrl::new(CC_REDIRECT_URI.into()).map_err(|_| {
             ClewdrError::UnexpectedNone {
@@ -111,6 +127,51 @@ fn setup_client(cc_client_id: String) -> Result<ClaudeOauthClient, ClewdrError>
 }
 
 impl ClaudeCodeState {
+    /// Builds the claude.ai authorization URL of a manual login
+    ///
+    /// Once the user signs in, claude.ai shows a code to paste back, which is redeemed by
+    /// [`ClaudeCodeState::exchange_pasted_code`] with the returned verifier.
+    pub fn authorize_url() -> Result<ManualAuthorization, ClewdrError> {
+        let cc_client_id = CLEWDR_CONFIG.load().cc_client_id();
+        let client = setup_client(cc_client_id)?.set_auth_uri(
+            AuthUrl::new(CC_AUTHORIZE_URL.into()).map_err(|_| ClewdrError::UnexpectedNone {
+                msg: "Invalid authorize URI",
+            })?,
+        );
+
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, csrf_token) = client
+            .authorize_url(|| CsrfToken::new_random_len(32))
+            .add_scope(Scope::new("user:profile".to_string()))
+            .add_scope(Scope::new("user:inference".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .add_extra_param("code", "true")
+            .url();
+
+        Ok(ManualAuthorization {
+            url,
+            state: csrf_token.secret().to_owned(),
+            verifier: pkce_verifier.secret().to_owned(),
+        })
+    }
+
+    /// Redeems a code pasted from a manual login and stores the token in the cookie
+    pub async fn exchange_pasted_code(
+        &mut self,
+        code: String,
+        state: Option<String>,
+        verifier: String,
+    ) -> Result<(), ClewdrError> {
+        self.exchange_token(ExchangeResult {
+            code,
+            state,
+            verifier: PkceCodeVerifier::new(verifier),
+            // unknown without a session, the API does not need it
+            org_uuid: String::new(),
+        })
+        .await
+    }
+
     pub async fn exchange_code(&self, org_uuid: &str) -> Result<ExchangeResult, ClewdrError> {
         // Build OAuth authorization URL using Url::join for proper URL construction
         let authorize_url = CLEWDR_CONFIG
@@ -143,7 +204,9 @@ impl ClaudeCodeState {
             .post(auth_url.to_string())
             .header(USER_AGENT, CLAUDE_CODE_USER_AGENT)
             .json(&query_params);
-        if let Some(cookie) = self.cookie.as_ref() {
+        if let Some(cookie) = self.cookie.as_ref()
+            && cookie.cookie.is_session()
+        {
             authorize_req = authorize_req.header(COOKIE, cookie.cookie.to_string());
         }
         let redirect_json = authorize_req
@@ -212,9 +275,8 @@ impl ClaudeCodeState {
     }
 
     pub async fn refresh_token(&mut self) -> Result<(), ClewdrError> {
-        let wreq_client = self.get_wreq_client();
         let Some(CookieStatus {
-            token: Some(ref mut token),
+            token: Some(ref token),
             ..
         }) = self.cookie
         else {
@@ -225,10 +287,27 @@ impl ClaudeCodeState {
         if !token.is_expired() {
             return Ok(());
         }
+        self.renew_token().await
+    }
+
+    /// Redeems the refresh token of the cookie, even if its access token is still valid
+    ///
+    /// When the refresh token is no longer valid, the cookie is authorized again.
+    pub async fn renew_token(&mut self) -> Result<(), ClewdrError> {
+        let wreq_client = self.get_wreq_client();
+        let Some(CookieStatus {
+            token: Some(ref mut token),
+            ..
+        }) = self.cookie
+        else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "No token found to refresh token",
+            });
+        };
 
         let cc_client_id = CLEWDR_CONFIG.load().cc_client_id();
 
-        let client = oauth2::basic::BasicClient::new(ClientId::new(cc_client_id))
+        let client = ClaudeClient::new(ClientId::new(cc_client_id))
             .set_auth_type(oauth2::AuthType::RequestBody)
             .set_token_uri(TokenUrl::new(CC_TOKEN_URL.into()).map_err(|_| {
                 ClewdrError::UnexpectedNone {
@@ -248,7 +327,9 @@ impl ClaudeCodeState {
 
         match refresh_result {
             Ok(new_token) => {
+                let account = token.account.take();
                 *token = TokenInfo::new(new_token, org_uuid);
+                token.account = token.account.take().or(account);
                 Ok(())
             }
             Err(e) => {
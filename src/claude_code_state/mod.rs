@@ -1,6 +1,8 @@
 mod chat;
 mod exchange;
 mod organization;
+use std::sync::Arc;
+
 use http::{
     HeaderValue, Method,
     header::{COOKIE, ORIGIN, REFERER},
@@ -12,10 +14,14 @@ use wreq_util::Emulation;
 
 use crate::{
     claude_web_state::SUPER_CLIENT,
-    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, Reason},
+    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, ModelFamily, Reason},
     error::{ClewdrError, WreqSnafu},
     middleware::claude::ClaudeApiFormat,
-    services::cookie_actor::CookieActorHandle,
+    services::{
+        cookie_actor::{CookieActorHandle, CookieLease, CookieRequest},
+        proxy_pool::PROXY_POOL,
+        token_refresh::usable_token,
+    },
     types::claude::Usage,
 };
 
@@ -23,6 +29,8 @@ use crate::{
 pub struct ClaudeCodeState {
     pub cookie_actor_handle: CookieActorHandle,
     pub cookie: Option<CookieStatus>,
+    /// Lease of `cookie`, shared by the clones of the state and the response stream
+    pub lease: Option<Arc<CookieLease>>,
     pub cookie_header_value: HeaderValue,
     pub proxy: Option<wreq::Proxy>,
     pub endpoint: url::Url,
@@ -32,6 +40,10 @@ pub struct ClaudeCodeState {
     pub system_prompt_hash: Option<u64>,
     pub anthropic_beta_header: Option<String>,
     pub usage: Usage,
+    pub api_key: Option<String>,
+    /// Cookie group the request is bound to
+    pub cookie_group: Option<String>,
+    pub audit_id: Option<u64>,
 }
 
 impl ClaudeCodeState {
@@ -40,6 +52,7 @@ impl ClaudeCodeState {
         ClaudeCodeState {
             cookie_actor_handle,
             cookie: None,
+            lease: None,
             cookie_header_value: HeaderValue::from_static(""),
             proxy: CLEWDR_CONFIG.load().wreq_proxy.to_owned(),
             endpoint: CLEWDR_CONFIG.load().endpoint(),
@@ -49,6 +62,9 @@ impl ClaudeCodeState {
             system_prompt_hash: None,
             anthropic_beta_header: None,
             usage: Usage::default(),
+            api_key: None,
+            cookie_group: None,
+            audit_id: None,
         }
     }
 
@@ -58,17 +74,12 @@ impl ClaudeCodeState {
         cookie: CookieStatus,
     ) -> Result<Self, ClewdrError> {
         let mut state = Self::new(cookie_actor_handle);
+        // accounts added through OAuth have no session cookie to send
+        if cookie.cookie.is_session() {
+            state.cookie_header_value = HeaderValue::from_str(cookie.cookie.to_string().as_str())?;
+        }
+        state.proxy = PROXY_POOL.pick(&cookie.cookie);
         state.cookie = Some(cookie);
-        let cookie_value = state
-            .cookie
-            .as_ref()
-            .ok_or(ClewdrError::UnexpectedNone {
-                msg: "Cookie missing while initializing state",
-            })?
-            .cookie
-            .to_string();
-        let header_value = HeaderValue::from_str(cookie_value.as_str())?;
-        state.cookie_header_value = header_value.clone();
         let mut client = wreq::Client::builder()
             .cookie_store(true)
             .emulation(Emulation::Chrome136);
@@ -116,15 +127,29 @@ impl ClaudeCodeState {
 
     /// Requests a new cookie from the cookie manager
     /// Updates the internal state with the new cookie and proxy configuration
-    pub async fn request_cookie(&mut self) -> Result<CookieStatus, ClewdrError> {
-        let res = self
+    pub async fn request_cookie(
+        &mut self,
+        family: ModelFamily,
+    ) -> Result<CookieStatus, ClewdrError> {
+        let lease = self
             .cookie_actor_handle
-            .request(self.system_prompt_hash)
+            .request(CookieRequest {
+                cache_hash: self.system_prompt_hash,
+                family,
+                group: self.cookie_group.to_owned(),
+                session: false,
+            })
             .await?;
+        let res = lease.cookie.to_owned();
         self.cookie = Some(res.to_owned());
-        self.cookie_header_value = HeaderValue::from_str(res.cookie.to_string().as_str())?;
+        self.lease = Some(Arc::new(lease));
+        self.cookie_header_value = if res.cookie.is_session() {
+            HeaderValue::from_str(res.cookie.to_string().as_str())?
+        } else {
+            HeaderValue::from_static("")
+        };
         // Always pull latest proxy/endpoint before building the client
-        self.proxy = CLEWDR_CONFIG.load().wreq_proxy.to_owned();
+        self.proxy = PROXY_POOL.pick(&res.cookie);
         self.endpoint = CLEWDR_CONFIG.load().endpoint();
         let mut client = wreq::Client::builder()
             .cookie_store(true)
@@ -152,6 +177,23 @@ impl ClaudeCodeState {
             TokenStatus::Valid
         }
     }
+
+    /// Makes sure the cookie holds a usable token, sharing any refresh with concurrent
+    /// requests on the same cookie
+    ///
+    /// # Returns
+    /// * `Result<String, ClewdrError>` - Access token of the cookie
+    pub async fn ensure_token(&mut self) -> Result<String, ClewdrError> {
+        let Some(ref mut cookie) = self.cookie else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "No cookie to get a token for",
+            });
+        };
+        let token = usable_token(&self.cookie_actor_handle, cookie).await?;
+        let access_token = token.access_token.to_owned();
+        cookie.token = Some(token);
+        Ok(access_token)
+    }
 }
 
 pub enum TokenStatus {
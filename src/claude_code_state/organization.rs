@@ -12,6 +12,10 @@ use crate::{
 
 impl ClaudeCodeState {
     pub async fn get_organization(&self) -> Result<String, ClewdrError> {
+        // accounts added through OAuth have no session, they must log in again
+        if self.cookie.as_ref().is_some_and(|c| !c.cookie.is_session()) {
+            return Err(Reason::Null.into());
+        }
         let end_point = self
             .endpoint
             .join("api/bootstrap")
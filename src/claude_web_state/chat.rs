@@ -1,5 +1,4 @@
 use colored::Colorize;
-use futures::TryFutureExt;
 use serde_json::json;
 use snafu::ResultExt;
 use tracing::{Instrument, debug, error, info, info_span, warn};
@@ -7,8 +6,9 @@ use wreq::{Method, Response, header::ACCEPT};
 
 use super::ClaudeWebState;
 use crate::{
-    config::CLEWDR_CONFIG,
+    config::{CLEWDR_CONFIG, ModelFamily},
     error::{CheckClaudeErr, ClewdrError, WreqSnafu},
+    services::{audit::AUDIT_LOG, metrics::METRICS},
     types::claude::CreateMessageParams,
     utils::print_out_json,
 };
@@ -36,19 +36,24 @@ impl ClaudeWebState {
         &mut self,
         p: CreateMessageParams,
     ) -> Result<axum::response::Response, ClewdrError> {
+        let family = ModelFamily::from_model(&p.model);
         for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
             if i > 0 {
                 info!("[RETRY] attempt: {}", i.to_string().green());
+                METRICS.record_retry("web");
             }
             let mut state = self.to_owned();
             let p = p.to_owned();
 
-            let cookie = state.request_cookie().await?;
-            // check if request is successful
-            let web_res = async { state.bootstrap().await.and(state.send_chat(p).await) };
-            let transform_res = web_res
-                .and_then(async |r| self.transform_response(r).await)
-                .instrument(info_span!("claude_web", "cookie" = cookie.cookie.ellipse()));
+            let cookie = state.request_cookie(family).await?;
+            AUDIT_LOG.record_attempt(state.audit_id, i, cookie.cookie.ellipse());
+            // check if request is successful, the attempt's state holds the cookie and params
+            let transform_res = async {
+                state.bootstrap().await?;
+                let res = state.send_chat(p).await?;
+                state.transform_response(res).await
+            }
+            .instrument(info_span!("claude_web", "cookie" = cookie.cookie.ellipse()));
 
             match transform_res.await {
                 Ok(b) => {
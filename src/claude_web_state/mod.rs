@@ -1,4 +1,4 @@
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
 use axum::http::{
     HeaderValue,
@@ -14,15 +14,20 @@ use wreq::{
 use wreq_util::Emulation;
 
 use crate::{
-    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, Reason},
+    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, ModelFamily, Reason},
     error::{ClewdrError, WreqSnafu},
     middleware::claude::ClaudeApiFormat,
-    services::cookie_actor::CookieActorHandle,
+    services::{
+        cookie_actor::{CookieActorHandle, CookieLease, CookieRequest},
+        key_usage::record_usage,
+        proxy_pool::PROXY_POOL,
+    },
     types::claude::{CreateMessageParams, Usage},
 };
 
 pub mod bootstrap;
 pub mod chat;
+pub mod tools;
 mod transform;
 /// Placeholder
 pub static SUPER_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
@@ -31,6 +36,8 @@ pub static SUPER_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
 #[derive(Clone)]
 pub struct ClaudeWebState {
     pub cookie: Option<CookieStatus>,
+    /// Lease of `cookie`, shared by the clones of the state and the response stream
+    pub lease: Option<Arc<CookieLease>>,
     cookie_header_value: HeaderValue,
     pub cookie_actor_handle: CookieActorHandle,
     pub org_uuid: Option<String>,
@@ -43,6 +50,10 @@ pub struct ClaudeWebState {
     pub client: Client,
     pub key: Option<(u64, usize)>,
     pub usage: Usage,
+    pub api_key: Option<String>,
+    /// Cookie group the request is bound to
+    pub cookie_group: Option<String>,
+    pub audit_id: Option<u64>,
     // keep the last request params for potential post-call token accounting
     pub last_params: Option<CreateMessageParams>,
 }
@@ -53,6 +64,7 @@ impl ClaudeWebState {
         ClaudeWebState {
             cookie_actor_handle,
             cookie: None,
+            lease: None,
             org_uuid: None,
             conv_uuid: None,
             cookie_header_value: HeaderValue::from_static(""),
@@ -64,10 +76,34 @@ impl ClaudeWebState {
             client: SUPER_CLIENT.to_owned(),
             key: None,
             usage: Usage::default(),
+            api_key: None,
+            cookie_group: None,
+            audit_id: None,
             last_params: None,
         }
     }
 
+    /// Build a ClaudeWebState initialized with an existing cookie snapshot
+    pub fn from_cookie(
+        cookie_actor_handle: CookieActorHandle,
+        cookie: CookieStatus,
+    ) -> Result<Self, ClewdrError> {
+        let mut state = Self::new(cookie_actor_handle);
+        state.cookie_header_value = HeaderValue::from_str(cookie.cookie.to_string().as_str())?;
+        state.proxy = PROXY_POOL.pick(&cookie.cookie);
+        state.cookie = Some(cookie);
+        let mut client = Client::builder()
+            .cookie_store(true)
+            .emulation(Emulation::Chrome136);
+        if let Some(ref proxy) = state.proxy {
+            client = client.proxy(proxy.to_owned());
+        }
+        state.client = client.build().context(WreqSnafu {
+            msg: "Failed to build client for cookie",
+        })?;
+        Ok(state)
+    }
+
     pub fn with_claude_format(mut self) -> Self {
         self.api_format = ClaudeApiFormat::Claude;
         self
@@ -120,11 +156,24 @@ impl ClaudeWebState {
 
     /// Requests a new cookie from the cookie manager
     /// Updates the internal state with the new cookie and proxy configuration
-    pub async fn request_cookie(&mut self) -> Result<CookieStatus, ClewdrError> {
-        let res = self.cookie_actor_handle.request(None).await?;
+    pub async fn request_cookie(
+        &mut self,
+        family: ModelFamily,
+    ) -> Result<CookieStatus, ClewdrError> {
+        let lease = self
+            .cookie_actor_handle
+            .request(CookieRequest {
+                cache_hash: None,
+                family,
+                group: self.cookie_group.to_owned(),
+                session: true,
+            })
+            .await?;
+        let res = lease.cookie.to_owned();
         self.cookie = Some(res.to_owned());
+        self.lease = Some(Arc::new(lease));
         // Always pull latest proxy/endpoint before building the client
-        self.proxy = CLEWDR_CONFIG.load().wreq_proxy.to_owned();
+        self.proxy = PROXY_POOL.pick(&res.cookie);
         self.endpoint = CLEWDR_CONFIG.load().endpoint();
         let mut client = Client::builder()
             .cookie_store(true)
@@ -153,27 +202,24 @@ impl ClaudeWebState {
         }
     }
 
-    fn classify_model(model: &str) -> crate::config::ModelFamily {
-        let m = model.to_ascii_lowercase();
-        if m.contains("opus") {
-            crate::config::ModelFamily::Opus
-        } else if m.contains("sonnet") {
-            crate::config::ModelFamily::Sonnet
-        } else {
-            crate::config::ModelFamily::Other
-        }
-    }
-
     pub async fn persist_usage_totals(&mut self, input: u64, output: u64) {
         if input == 0 && output == 0 {
             return;
         }
+        let family = self
+            .last_params
+            .as_ref()
+            .map(|p| ModelFamily::from_model(&p.model))
+            .unwrap_or_default();
+        record_usage(
+            "web",
+            family,
+            self.api_key.as_deref(),
+            self.audit_id,
+            input,
+            output,
+        );
         if let Some(cookie) = self.cookie.as_mut() {
-            let family = self
-                .last_params
-                .as_ref()
-                .map(|p| Self::classify_model(&p.model))
-                .unwrap_or(crate::config::ModelFamily::Other);
             cookie.add_and_bucket_usage(input, output, family);
             let cloned = cookie.clone();
             if let Err(err) = self.cookie_actor_handle.return_cookie(cloned, None).await {
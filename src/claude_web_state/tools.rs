@@ -0,0 +1,329 @@
+use std::fmt::Write;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::types::claude::{
+    ContentBlock, ContentBlockDelta, CreateMessageParams, CreateMessageResponse,
+    MessageDeltaContent, MessageStartContent, StopReason, StreamEvent, StreamEventBuilder,
+    StreamUsage, Tool, ToolChoice,
+};
+
+const TOOL_CALL_OPEN: &str = "<tool_call>";
+const TOOL_CALL_CLOSE: &str = "</tool_call>";
+const TOOL_RESULT_OPEN: &str = "<tool_result";
+
+/// Whether the request has client tools the web backend has to emulate
+pub fn emulates_tools(p: &CreateMessageParams) -> bool {
+    !matches!(p.tool_choice, Some(ToolChoice::None))
+        && p.tools
+            .iter()
+            .flatten()
+            .any(|t| matches!(t, Tool::Custom(_)))
+}
+
+/// Renders the tool calling protocol and the tool schemas, appended to the system prompt
+///
+/// Claude.ai has no client side tools, so the model is asked to write its calls as
+/// `<tool_call>` blocks holding JSON, which are parsed back out of the completion.
+pub fn render_tools(p: &CreateMessageParams) -> Option<String> {
+    if !emulates_tools(p) {
+        return None;
+    }
+    let mut w = String::from(
+        "# Tools\n\n\
+        You can call the tools below. To call a tool, write a tool call block holding a JSON \
+        object with the tool name and its input, which must follow the input schema of the tool:\n\n\
+        <tool_call>{\"name\": \"tool_name\", \"input\": {\"argument\": \"value\"}}</tool_call>\n\n\
+        You may write some text before your tool calls. After your tool calls, stop your reply: \
+        the results will be sent back in <tool_result> blocks. Never write <tool_result> blocks \
+        yourself.",
+    );
+    match p.tool_choice {
+        Some(ToolChoice::Any { .. }) => w += "\nYou must call at least one tool.",
+        Some(ToolChoice::Tool { ref name, .. }) => {
+            write!(w, "\nYou must call the tool `{name}`.").ok()?
+        }
+        _ => {}
+    }
+    let single = match p.tool_choice {
+        Some(ToolChoice::Auto {
+            disable_parallel_tool_use,
+        })
+        | Some(ToolChoice::Any {
+            disable_parallel_tool_use,
+        })
+        | Some(ToolChoice::Tool {
+            disable_parallel_tool_use,
+            ..
+        }) => disable_parallel_tool_use.unwrap_or_default(),
+        _ => false,
+    };
+    if single {
+        w += "\nCall at most one tool per reply.";
+    }
+    for tool in p.tools.iter().flatten() {
+        let Tool::Custom(tool) = tool else {
+            continue;
+        };
+        write!(w, "\n\n## {}", tool.name).ok()?;
+        if let Some(ref description) = tool.description {
+            write!(w, "\n{}", description.trim()).ok()?;
+        }
+        write!(w, "\nInput schema: {}", tool.input_schema).ok()?;
+    }
+    Some(w)
+}
+
+/// Renders a previous tool call of the assistant the way the model is asked to write them
+pub fn render_tool_use(id: &str, name: &str, input: &Value) -> String {
+    let call = serde_json::json!({ "id": id, "name": name, "input": input });
+    format!("{TOOL_CALL_OPEN}{call}{TOOL_CALL_CLOSE}")
+}
+
+/// Renders the result of a tool call, only the text parts of block contents are kept
+pub fn render_tool_result(tool_use_id: &str, content: &Value, is_error: bool) -> String {
+    let text = match content {
+        Value::String(s) => s.trim().to_string(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|b| b["text"].as_str())
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    };
+    let error = if is_error { " is_error=\"true\"" } else { "" };
+    format!("{TOOL_RESULT_OPEN} id=\"{tool_use_id}\"{error}>\n{text}\n</tool_result>")
+}
+
+/// Call written by the model inside a `<tool_call>` block
+#[derive(Deserialize)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    input: Value,
+}
+
+/// Parses a tool call block, tolerating a markdown code fence around the JSON
+fn parse_tool_call(raw: &str) -> Option<ToolCall> {
+    let raw = raw.trim();
+    let raw = raw
+        .strip_prefix("```json")
+        .or_else(|| raw.strip_prefix("```"))
+        .and_then(|r| r.strip_suffix("```"))
+        .unwrap_or(raw);
+    let mut call = serde_json::from_str::<ToolCall>(raw.trim()).ok()?;
+    if !call.input.is_object() {
+        call.input = Value::Object(Default::default());
+    }
+    Some(call)
+}
+
+/// Splits a completion into text and `tool_use` blocks
+///
+/// Text after the last tool call is dropped, it can only be a hallucinated result.
+/// Malformed tool call blocks are kept as text.
+pub fn parse_tool_calls(completion: &str) -> Vec<ContentBlock> {
+    let mut blocks = vec![];
+    let mut text = String::new();
+    let mut rest = completion;
+    while let Some(start) = rest.find(TOOL_CALL_OPEN) {
+        let after = &rest[start + TOOL_CALL_OPEN.len()..];
+        let Some(end) = after.find(TOOL_CALL_CLOSE) else {
+            break;
+        };
+        text += &rest[..start];
+        match parse_tool_call(&after[..end]) {
+            Some(call) => {
+                if !text.trim().is_empty() {
+                    blocks.push(ContentBlock::text(text.trim().to_string()));
+                }
+                text.clear();
+                blocks.push(ContentBlock::ToolUse {
+                    id: format!("toolu_{}", uuid::Uuid::new_v4().simple()),
+                    name: call.name,
+                    input: call.input,
+                    cache_control: None,
+                    caller: None,
+                });
+            }
+            None => {
+                warn!("Malformed tool call in web completion");
+                text += &rest[start..start + TOOL_CALL_OPEN.len() + end + TOOL_CALL_CLOSE.len()];
+            }
+        }
+        rest = &after[end + TOOL_CALL_CLOSE.len()..];
+    }
+    if blocks.is_empty() {
+        text += rest;
+        blocks.push(ContentBlock::text(text.trim().to_string()));
+    }
+    blocks
+}
+
+/// Replays a complete response as the events of a Claude message stream
+pub fn stream_events(response: CreateMessageResponse) -> Vec<StreamEvent> {
+    let usage = response.usage.unwrap_or_default();
+    let mut builder = StreamEventBuilder::default();
+    let mut events = vec![];
+    builder.start(
+        MessageStartContent {
+            id: response.id,
+            type_: response.type_,
+            role: response.role,
+            content: vec![],
+            model: response.model,
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Some(usage.to_owned()),
+        },
+        &mut events,
+    );
+    for block in response.content {
+        let (content_block, delta) = match block {
+            ContentBlock::ToolUse {
+                id, name, input, ..
+            } => (
+                ContentBlock::ToolUse {
+                    id,
+                    name,
+                    input: Value::Object(Default::default()),
+                    cache_control: None,
+                    caller: None,
+                },
+                ContentBlockDelta::InputJsonDelta {
+                    partial_json: input.to_string(),
+                },
+            ),
+            ContentBlock::Text { text, .. } => (
+                ContentBlock::text(String::new()),
+                ContentBlockDelta::TextDelta { text },
+            ),
+            ContentBlock::Thinking { thinking, .. } => (
+                ContentBlock::Thinking {
+                    signature: String::new(),
+                    thinking: String::new(),
+                },
+                ContentBlockDelta::ThinkingDelta { thinking },
+            ),
+            _ => continue,
+        };
+        builder.block(content_block, delta, &mut events);
+    }
+    builder.finish(
+        MessageDeltaContent {
+            stop_reason: response.stop_reason,
+            stop_sequence: response.stop_sequence,
+        },
+        StreamUsage {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        },
+        &mut events,
+    );
+    events
+}
+
+/// Replaces the text of the response with the text and tool calls of the completion
+pub fn apply_tool_calls(response: &mut CreateMessageResponse, completion: &str) {
+    response
+        .content
+        .retain(|b| !matches!(b, ContentBlock::Text { .. }));
+    response.content.extend(parse_tool_calls(completion));
+    if response
+        .content
+        .iter()
+        .any(|b| matches!(b, ContentBlock::ToolUse { .. }))
+    {
+        response.stop_reason = Some(StopReason::ToolUse);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_text_and_tool_calls() {
+        let completion = "Let me check.\n<tool_call>{\"name\": \"get_weather\", \"input\": {\"city\": \"Paris\"}}</tool_call>\n<tool_result id=\"x\">sunny</tool_result>";
+        let blocks = parse_tool_calls(completion);
+        assert_eq!(blocks.len(), 2);
+        assert!(matches!(&blocks[0], ContentBlock::Text { text, .. } if text == "Let me check."));
+        let ContentBlock::ToolUse { name, input, .. } = &blocks[1] else {
+            panic!("expected a tool_use block");
+        };
+        assert_eq!(name, "get_weather");
+        assert_eq!(input["city"], "Paris");
+    }
+
+    #[test]
+    fn replays_tool_calls_in_stream_order() {
+        let mut response = CreateMessageResponse::text(
+            String::new(),
+            "claude-sonnet-4".to_string(),
+            Default::default(),
+        );
+        apply_tool_calls(
+            &mut response,
+            "Let me check.\n<tool_call>{\"name\": \"get_weather\", \"input\": {\"city\": \"Paris\"}}</tool_call>",
+        );
+        let events = stream_events(response);
+        let types = events
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap()["type"].to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        let StreamEvent::ContentBlockStart {
+            index: 1,
+            content_block: ContentBlock::ToolUse { ref name, .. },
+        } = events[4]
+        else {
+            panic!("expected the tool_use block second: {:?}", events[4]);
+        };
+        assert_eq!(name, "get_weather");
+        let StreamEvent::ContentBlockDelta {
+            index: 1,
+            delta: ContentBlockDelta::InputJsonDelta { ref partial_json },
+        } = events[5]
+        else {
+            panic!("expected the tool input: {:?}", events[5]);
+        };
+        assert_eq!(partial_json, r#"{"city":"Paris"}"#);
+        assert!(matches!(
+            events[7],
+            StreamEvent::MessageDelta {
+                delta: MessageDeltaContent {
+                    stop_reason: Some(StopReason::ToolUse),
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn keeps_malformed_calls_as_text() {
+        let completion = "<tool_call>not json</tool_call> done";
+        let blocks = parse_tool_calls(completion);
+        assert_eq!(blocks.len(), 1);
+        assert!(
+            matches!(&blocks[0], ContentBlock::Text { text, .. } if text == "<tool_call>not json</tool_call> done")
+        );
+    }
+}
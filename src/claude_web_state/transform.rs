@@ -8,7 +8,10 @@ use tracing::warn;
 use wreq::multipart::{Form, Part};
 
 use crate::{
-    claude_web_state::ClaudeWebState,
+    claude_web_state::{
+        ClaudeWebState,
+        tools::{render_tool_result, render_tool_use, render_tools},
+    },
     config::CLEWDR_CONFIG,
     types::{
         claude::{ContentBlock, CreateMessageParams, ImageSource, Message, MessageContent, Role},
@@ -21,7 +24,10 @@ impl ClaudeWebState {
     pub fn transform_request(&self, mut value: CreateMessageParams) -> Option<WebRequestBody> {
         let system = value.system.take();
         let msgs = mem::take(&mut value.messages);
-        let system = merge_system(system.unwrap_or_default());
+        let mut system = merge_system(system.unwrap_or_default());
+        if let Some(tools) = render_tools(&value) {
+            system = format!("{}\n\n{tools}", system.trim());
+        }
         let merged = merge_messages(msgs, system)?;
 
         let mut tools = vec![];
@@ -37,11 +43,8 @@ impl ClaudeWebState {
             } else {
                 None
             },
-            rendering_mode: if value.stream.unwrap_or_default() {
-                "messages".to_string()
-            } else {
-                "raw".to_string()
-            },
+            // block events keep thinking apart from text, also for non stream responses
+            rendering_mode: "messages".to_string(),
             prompt: merged.prompt,
             timezone: TIME_ZONE.to_string(),
             images: merged.images,
@@ -188,6 +191,19 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
                             }
                             None
                         }
+                        ContentBlock::ToolUse {
+                            id, name, input, ..
+                        } => Some(render_tool_use(&id, &name, &input)),
+                        ContentBlock::ToolResult {
+                            tool_use_id,
+                            content,
+                            is_error,
+                            ..
+                        } => Some(render_tool_result(
+                            &tool_use_id,
+                            &content,
+                            is_error.unwrap_or_default(),
+                        )),
                         _ => None,
                     })
                     .collect::<Vec<_>>()
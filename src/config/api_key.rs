@@ -0,0 +1,251 @@
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Endpoint families a client key can be granted access to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyEndpoint {
+    /// Claude.ai web backend (`/v1/...`)
+    Web,
+    /// Claude Code backend (`/code/v1/...`)
+    Code,
+    /// Gemini backend (`/gemini/v1/...`)
+    Gemini,
+    /// Anthropic API key backend (`/api/v1/...`)
+    Api,
+}
+
+impl KeyEndpoint {
+    /// Derives the endpoint family from a request path
+    pub fn from_path(path: &str) -> Self {
+        if path.starts_with("/code/") {
+            KeyEndpoint::Code
+        } else if path.starts_with("/gemini/") {
+            KeyEndpoint::Gemini
+        } else if path.starts_with("/api/v1/") {
+            KeyEndpoint::Api
+        } else {
+            KeyEndpoint::Web
+        }
+    }
+}
+
+/// Request and token limits applied to one accounting window
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct QuotaLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+}
+
+/// Optional daily and monthly quotas of a client key
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct KeyQuota {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily: Option<QuotaLimit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<QuotaLimit>,
+}
+
+/// Usage accumulated within one accounting window
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct UsageWindow {
+    /// Unix timestamp (UTC) at which the window started
+    #[serde(default)]
+    pub period_start: i64,
+    #[serde(default)]
+    pub requests: u64,
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+impl UsageWindow {
+    pub fn tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+
+    /// Starts a fresh window when `period_start` moved past the stored one
+    fn roll(&mut self, period_start: i64) {
+        if self.period_start != period_start {
+            *self = UsageWindow {
+                period_start,
+                ..Default::default()
+            };
+        }
+    }
+
+    fn exceeds(&self, limit: &QuotaLimit) -> bool {
+        limit.max_requests.is_some_and(|max| self.requests >= max)
+            || limit.max_tokens.is_some_and(|max| self.tokens() >= max)
+    }
+}
+
+/// Usage counters of a client key
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct KeyUsage {
+    #[serde(default)]
+    pub daily: UsageWindow,
+    #[serde(default)]
+    pub monthly: UsageWindow,
+    #[serde(default)]
+    pub total_requests: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<i64>,
+}
+
+impl KeyUsage {
+    /// Resets the daily and monthly windows if their period has elapsed
+    pub fn roll(&mut self, now: DateTime<Utc>) {
+        self.daily.roll(day_start(now));
+        self.monthly.roll(month_start(now));
+    }
+
+    pub fn record_request(&mut self, now: DateTime<Utc>) {
+        self.roll(now);
+        self.daily.requests += 1;
+        self.monthly.requests += 1;
+        self.total_requests += 1;
+        self.last_used_at = Some(now.timestamp());
+    }
+
+    pub fn record_tokens(&mut self, now: DateTime<Utc>, input: u64, output: u64) {
+        self.roll(now);
+        for window in [&mut self.daily, &mut self.monthly] {
+            window.input_tokens += input;
+            window.output_tokens += output;
+        }
+        self.total_tokens += input + output;
+    }
+
+    /// Returns which quota is exhausted, if any
+    pub fn exceeded(&self, quota: &KeyQuota) -> Option<&'static str> {
+        if quota.daily.as_ref().is_some_and(|l| self.daily.exceeds(l)) {
+            return Some("daily");
+        }
+        if quota
+            .monthly
+            .as_ref()
+            .is_some_and(|l| self.monthly.exceeds(l))
+        {
+            return Some("monthly");
+        }
+        None
+    }
+}
+
+fn day_start(now: DateTime<Utc>) -> i64 {
+    Utc.with_ymd_and_hms(now.year(), now.month(), now.day(), 0, 0, 0)
+        .single()
+        .map(|d| d.timestamp())
+        .unwrap_or_default()
+}
+
+fn month_start(now: DateTime<Utc>) -> i64 {
+    Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()
+        .map(|d| d.timestamp())
+        .unwrap_or_default()
+}
+
+const fn default_enabled() -> bool {
+    true
+}
+
+/// A client API key managed by the admin
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub key: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Allowed endpoint families, empty means all
+    #[serde(default)]
+    pub endpoints: Vec<KeyEndpoint>,
+    /// Allowed models, empty means all; a trailing `*` matches by prefix
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub quota: KeyQuota,
+    /// Cookie group the requests of the key are bound to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie_group: Option<String>,
+    #[serde(default)]
+    pub usage: KeyUsage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+}
+
+impl ApiKey {
+    pub fn allows_endpoint(&self, endpoint: KeyEndpoint) -> bool {
+        self.endpoints.is_empty() || self.endpoints.contains(&endpoint)
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.models.is_empty()
+            || self
+                .models
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => model.starts_with(prefix),
+                    None => pattern == model,
+                })
+    }
+
+    /// Shortened key for logs and admin listings
+    pub fn ellipse(&self) -> String {
+        let len = self.key.chars().count();
+        if len <= 10 {
+            return self.key.to_owned();
+        }
+        let head = self.key.chars().take(6).collect::<String>();
+        let tail = self.key.chars().skip(len - 4).collect::<String>();
+        format!("{head}...{tail}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> ApiKey {
+        serde_json::from_value(serde_json::json!({
+            "key": "sk-test-0123456789",
+            "models": ["claude-sonnet-*", "claude-opus-4-1"],
+            "quota": { "daily": { "max_requests": 2 } }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_model_patterns() {
+        let key = key();
+        assert!(key.enabled);
+        assert!(key.allows_model("claude-sonnet-4-5"));
+        assert!(key.allows_model("claude-opus-4-1"));
+        assert!(!key.allows_model("claude-opus-4-5"));
+        assert!(key.allows_endpoint(KeyEndpoint::Code));
+    }
+
+    #[test]
+    fn daily_quota_rolls_over() {
+        let key = key();
+        let mut usage = KeyUsage::default();
+        let day1 = Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap();
+        usage.record_request(day1);
+        assert_eq!(usage.exceeded(&key.quota), None);
+        usage.record_request(day1);
+        assert_eq!(usage.exceeded(&key.quota), Some("daily"));
+
+        let day2 = Utc.with_ymd_and_hms(2025, 1, 2, 0, 0, 1).unwrap();
+        usage.roll(day2);
+        assert_eq!(usage.exceeded(&key.quota), None);
+        assert_eq!(usage.monthly.requests, 2);
+        assert_eq!(usage.total_requests, 2);
+    }
+}
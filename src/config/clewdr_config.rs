@@ -2,6 +2,7 @@ use std::{
     collections::HashSet,
     fmt::{Debug, Display},
     net::{IpAddr, SocketAddr},
+    path::PathBuf,
 };
 
 use axum::http::{Uri, uri::Scheme};
@@ -14,22 +15,65 @@ use figment::{
 use http::uri::Authority;
 use passwords::PasswordGenerator;
 use serde::{Deserialize, Serialize};
+use strum::{Display, IntoStaticStr};
 use tokio::spawn;
 use tracing::error;
 use url::Url;
 use wreq::Proxy;
 
-use super::{CONFIG_PATH, ENDPOINT_URL};
+use super::{CONFIG_PATH, DB_NAME, ENDPOINT_URL};
 use crate::{
     Args,
     config::{
-        CC_CLIENT_ID, CookieStatus, UselessCookie, default_check_update, default_ip,
-        default_max_retries, default_port, default_skip_cool_down, default_use_real_roles,
+        ApiKey, CC_CLIENT_ID, CookieStatus, CookieStrategy, PooledKey, ProxyStrategy,
+        UpstreamProxy, UselessCookie, default_audit_body_limit, default_audit_log,
+        default_audit_max_entries, default_audit_retention_hours, default_check_update,
+        default_code_fallback, default_cookie_health_check_concurrency,
+        default_cookie_queue_timeout, default_ip, default_max_retries, default_port,
+        default_proxy_health_check_interval, default_response_cache_size,
+        default_response_cache_ttl, default_skip_cool_down, default_token_refresh_lead,
+        default_use_real_roles, default_utilization_threshold,
     },
     error::ClewdrError,
+    persistence::{self, CookieChange},
     utils::enabled,
 };
 
+/// Opens the database and loads settings and cookies from it
+///
+/// The first time the database is used, the TOML config is imported into it.
+/// Environment variables still override stored settings.
+#[cfg(feature = "db")]
+fn load_from_database(config: ClewdrConfig) -> ClewdrConfig {
+    use figment::providers::Serialized;
+
+    use crate::persistence::SqliteStorage;
+
+    let path = config.db_path();
+    let (db, stored) = match SqliteStorage::open(&path)
+        .and_then(|db| db.load_or_migrate(&config).map(|stored| (db, stored)))
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to open database {}: {}", path.display(), e);
+            return config;
+        }
+    };
+    let Ok(mut loaded) = Figment::from(Serialized::defaults(stored.config))
+        .admerge(Env::prefixed("CLEWDR_").split("__"))
+        .extract_lossy::<ClewdrConfig>()
+        .inspect_err(|e| {
+            error!("Failed to load config from database: {}", e);
+        })
+    else {
+        return config;
+    };
+    loaded.cookie_array = stored.cookies.into_iter().collect();
+    loaded.wasted_cookie = stored.wasted.into_iter().collect();
+    persistence::install_storage(Box::new(db));
+    loaded
+}
+
 /// Generates a random password for authentication
 /// Creates a secure 64-character password with mixed character types
 ///
@@ -51,6 +95,30 @@ fn generate_password() -> String {
     pg.generate_one().unwrap()
 }
 
+/// Where configuration and cookies are persisted
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Everything in the TOML config file
+    #[default]
+    Toml,
+    /// Embedded SQLite database, requires the `db` feature
+    Sqlite,
+}
+
+/// Backends able to serve a Claude request
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Display, IntoStaticStr)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ClaudeBackend {
+    /// Claude Code OAuth lane of the cookies
+    Code,
+    /// claude.ai web completion with the cookies
+    Web,
+    /// Anthropic API key pool
+    Api,
+}
+
 /// A struct representing the configuration of the application
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClewdrConfig {
@@ -59,12 +127,23 @@ pub struct ClewdrConfig {
     pub cookie_array: HashSet<CookieStatus>,
     #[serde(default)]
     pub wasted_cookie: HashSet<UselessCookie>,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+    #[serde(default)]
+    pub gemini_keys: Vec<PooledKey>,
+    #[serde(default)]
+    pub anthropic_keys: Vec<PooledKey>,
 
     // Server settings, cannot hot reload
     #[serde(default = "default_ip")]
     ip: IpAddr,
     #[serde(default = "default_port")]
     port: u16,
+    #[serde(default)]
+    pub storage: StorageBackend,
+    /// Database file, defaults to `clewdr.db` next to the config file
+    #[serde(default)]
+    pub db_path: Option<PathBuf>,
 
     // App settings, can hot reload, but meaningless
     #[serde(default = "default_check_update")]
@@ -75,6 +154,49 @@ pub struct ClewdrConfig {
     pub no_fs: bool,
     #[serde(default)]
     pub log_to_file: bool,
+    #[serde(default)]
+    pub metrics_require_auth: bool,
+
+    // Audit log settings, can hot reload
+    #[serde(default = "default_audit_log")]
+    pub audit_log: bool,
+    #[serde(default = "default_audit_max_entries")]
+    pub audit_max_entries: usize,
+    /// Hours after which entries are dropped, 0 keeps them until `audit_max_entries` is reached
+    #[serde(default = "default_audit_retention_hours")]
+    pub audit_retention_hours: u64,
+    #[serde(default)]
+    pub audit_log_bodies: bool,
+    #[serde(default = "default_audit_body_limit")]
+    pub audit_body_limit: usize,
+
+    // Rate limit settings, can hot reload, 0 disables a limit
+    /// Limits of each client API key, or of each IP for password users
+    #[serde(default)]
+    pub rate_limit_rpm: u64,
+    #[serde(default)]
+    pub rate_limit_tpm: u64,
+    #[serde(default)]
+    pub rate_limit_concurrency: usize,
+    /// Limits shared by all clients
+    #[serde(default)]
+    pub global_rate_limit_rpm: u64,
+    #[serde(default)]
+    pub global_rate_limit_tpm: u64,
+    #[serde(default)]
+    pub global_rate_limit_concurrency: usize,
+
+    // Response cache settings, can hot reload
+    /// Caches responses to identical requests with `temperature: 0`
+    #[serde(default)]
+    pub response_cache: bool,
+    #[serde(default = "default_response_cache_ttl")]
+    pub response_cache_ttl: u64,
+    #[serde(default = "default_response_cache_size")]
+    pub response_cache_size: u64,
+    /// Also keeps cached responses on disk, next to the config file
+    #[serde(default)]
+    pub response_cache_disk: bool,
 
     // Network settings, can hot reload
     #[serde(default)]
@@ -85,6 +207,14 @@ pub struct ClewdrConfig {
     pub proxy: Option<String>,
     #[serde(default)]
     pub rproxy: Option<Url>,
+    /// Proxies the cookies exit through instead of `proxy`
+    #[serde(default)]
+    pub proxies: Vec<UpstreamProxy>,
+    #[serde(default)]
+    pub proxy_strategy: ProxyStrategy,
+    /// Seconds between two checks of the proxies, failing ones are benched until they pass
+    #[serde(default = "default_proxy_health_check_interval")]
+    pub proxy_health_check_interval: u64,
 
     // Api settings, can hot reload
     #[serde(default = "default_max_retries")]
@@ -97,6 +227,12 @@ pub struct ClewdrConfig {
     pub enable_web_count_tokens: bool,
     #[serde(default)]
     pub sanitize_messages: bool,
+    /// Backends tried in order when Claude Code cannot serve a request, empty disables fallback
+    #[serde(default = "default_code_fallback")]
+    pub code_fallback: Vec<ClaudeBackend>,
+    /// Relay the unified rate limit headers of Claude Code responses as `x-clewdr-ratelimit-*`
+    #[serde(default)]
+    pub relay_ratelimit_headers: bool,
 
     // Cookie settings, can hot reload
     #[serde(default)]
@@ -111,6 +247,29 @@ pub struct ClewdrConfig {
     pub skip_rate_limit: bool,
     #[serde(default)]
     pub skip_normal_pro: bool,
+    #[serde(default)]
+    pub cookie_strategy: CookieStrategy,
+    /// Requests a cookie may serve at the same time, 0 for no limit
+    #[serde(default)]
+    pub cookie_max_concurrency: usize,
+    /// Seconds a request waits for a free cookie once every cookie is busy
+    #[serde(default = "default_cookie_queue_timeout")]
+    pub cookie_queue_timeout: u64,
+    /// Seconds between two health checks of every cookie, 0 disables the checks
+    #[serde(default)]
+    pub cookie_health_check_interval: u64,
+    /// Cookies checked at the same time by a health check
+    #[serde(default = "default_cookie_health_check_concurrency")]
+    pub cookie_health_check_concurrency: usize,
+    /// Seconds between two polls of the utilization of every cookie, 0 disables the polls
+    #[serde(default)]
+    pub utilization_poll_interval: u64,
+    /// Session window percent from which a cookie is no longer dispatched, 0 disables it
+    #[serde(default = "default_utilization_threshold")]
+    pub session_utilization_threshold: u32,
+    /// Same for the 7 day windows, of all models and of the model family of the request
+    #[serde(default = "default_utilization_threshold")]
+    pub weekly_utilization_threshold: u32,
 
     // Prompt configurations, can hot reload
     #[serde(default = "default_use_real_roles")]
@@ -127,6 +286,9 @@ pub struct ClewdrConfig {
     pub claude_code_client_id: Option<String>,
     #[serde(default)]
     pub custom_system: Option<String>,
+    /// Seconds before expiry at which tokens are refreshed in the background, 0 disables it
+    #[serde(default = "default_token_refresh_lead")]
+    pub token_refresh_lead: u64,
 
     // Skip field, can hot reload
     #[serde(skip)]
@@ -141,12 +303,20 @@ impl Default for ClewdrConfig {
             auto_update: false,
             cookie_array: HashSet::new(),
             wasted_cookie: HashSet::new(),
+            api_keys: Vec::new(),
+            gemini_keys: Vec::new(),
+            anthropic_keys: Vec::new(),
             password: String::new(),
             admin_password: String::new(),
             proxy: None,
             ip: default_ip(),
             port: default_port(),
+            storage: StorageBackend::default(),
+            db_path: None,
             rproxy: None,
+            proxies: Vec::new(),
+            proxy_strategy: ProxyStrategy::default(),
+            proxy_health_check_interval: default_proxy_health_check_interval(),
             use_real_roles: default_use_real_roles(),
             custom_prompt: String::new(),
             custom_h: None,
@@ -156,16 +326,43 @@ impl Default for ClewdrConfig {
             web_search: false,
             enable_web_count_tokens: false,
             sanitize_messages: false,
+            code_fallback: default_code_fallback(),
+            relay_ratelimit_headers: false,
             skip_first_warning: false,
             skip_second_warning: false,
             skip_restricted: false,
             skip_non_pro: false,
             skip_rate_limit: default_skip_cool_down(),
             skip_normal_pro: false,
+            cookie_strategy: CookieStrategy::default(),
+            cookie_max_concurrency: 0,
+            cookie_queue_timeout: default_cookie_queue_timeout(),
+            cookie_health_check_interval: 0,
+            cookie_health_check_concurrency: default_cookie_health_check_concurrency(),
+            utilization_poll_interval: 0,
+            session_utilization_threshold: default_utilization_threshold(),
+            weekly_utilization_threshold: default_utilization_threshold(),
             claude_code_client_id: None,
             custom_system: None,
+            token_refresh_lead: default_token_refresh_lead(),
             no_fs: false,
             log_to_file: false,
+            metrics_require_auth: false,
+            audit_log: default_audit_log(),
+            audit_max_entries: default_audit_max_entries(),
+            audit_retention_hours: default_audit_retention_hours(),
+            audit_log_bodies: false,
+            audit_body_limit: default_audit_body_limit(),
+            rate_limit_rpm: 0,
+            rate_limit_tpm: 0,
+            rate_limit_concurrency: 0,
+            global_rate_limit_rpm: 0,
+            global_rate_limit_tpm: 0,
+            global_rate_limit_concurrency: 0,
+            response_cache: false,
+            response_cache_ttl: default_response_cache_ttl(),
+            response_cache_size: default_response_cache_size(),
+            response_cache_disk: false,
         }
     }
 }
@@ -200,9 +397,41 @@ impl Display for ClewdrConfig {
             web_url.to_string().green().underline(),
             self.admin_password.yellow(),
         )?;
+        if !self.api_keys.is_empty() {
+            writeln!(f, "API Keys: {}", self.api_keys.len().to_string().blue())?;
+        }
+        if !self.gemini_keys.is_empty() {
+            writeln!(
+                f,
+                "Gemini Keys: {}",
+                self.gemini_keys.len().to_string().blue()
+            )?;
+        }
+        if !self.anthropic_keys.is_empty() {
+            writeln!(
+                f,
+                "Anthropic Keys: {}",
+                self.anthropic_keys.len().to_string().blue()
+            )?;
+        }
+        if self.storage == StorageBackend::Sqlite {
+            writeln!(
+                f,
+                "Database: {}",
+                self.db_path().display().to_string().blue()
+            )?;
+        }
         if let Some(ref proxy) = self.proxy {
             writeln!(f, "Proxy: {}", proxy.to_string().blue())?;
         }
+        if !self.proxies.is_empty() {
+            writeln!(
+                f,
+                "Proxy pool: {} ({})",
+                self.proxies.len().to_string().blue(),
+                self.proxy_strategy
+            )?;
+        }
         if let Some(ref rproxy) = self.rproxy {
             writeln!(f, "Reverse Proxy: {}", rproxy.to_string().blue())?;
         }
@@ -220,11 +449,77 @@ impl Display for ClewdrConfig {
         )?;
         writeln!(f, "Skip normal Pro: {}", enabled(self.skip_normal_pro))?;
         writeln!(f, "Skip rate limit: {}", enabled(self.skip_rate_limit))?;
+        writeln!(
+            f,
+            "Cookie strategy: {}",
+            self.cookie_strategy.to_string().blue()
+        )?;
+        if self.cookie_max_concurrency > 0 {
+            writeln!(
+                f,
+                "Cookie concurrency: {} (queue timeout {}s)",
+                self.cookie_max_concurrency.to_string().blue(),
+                self.cookie_queue_timeout
+            )?;
+        }
+        if self.cookie_health_check_interval > 0 {
+            writeln!(
+                f,
+                "Cookie health check: every {}s ({} at a time)",
+                self.cookie_health_check_interval.to_string().blue(),
+                self.cookie_health_check_concurrency
+            )?;
+        }
+        if self.utilization_poll_interval > 0 {
+            writeln!(
+                f,
+                "Utilization poll: every {}s (thresholds {}%/{}%)",
+                self.utilization_poll_interval.to_string().blue(),
+                self.session_utilization_threshold,
+                self.weekly_utilization_threshold
+            )?;
+        }
         writeln!(
             f,
             "Web count_tokens: {}",
             enabled(self.enable_web_count_tokens)
         )?;
+        if !self.code_fallback.is_empty() {
+            let chain = self
+                .code_fallback
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            writeln!(f, "Claude Code fallback: {}", chain.blue())?;
+        }
+        writeln!(
+            f,
+            "Relay rate limit headers: {}",
+            enabled(self.relay_ratelimit_headers)
+        )?;
+        writeln!(f, "Audit log: {}", enabled(self.audit_log))?;
+        let limits = [
+            ("RPM", self.rate_limit_rpm, self.global_rate_limit_rpm),
+            ("TPM", self.rate_limit_tpm, self.global_rate_limit_tpm),
+            (
+                "concurrency",
+                self.rate_limit_concurrency as u64,
+                self.global_rate_limit_concurrency as u64,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, client, global)| *client > 0 || *global > 0)
+        .map(|(name, client, global)| format!("{name} {client}/{global}"))
+        .collect::<Vec<_>>();
+        if !limits.is_empty() {
+            writeln!(
+                f,
+                "Rate limits (client/global): {}",
+                limits.join(", ").blue()
+            )?;
+        }
+        writeln!(f, "Response cache: {}", enabled(self.response_cache))?;
         Ok(())
     }
 }
@@ -245,8 +540,16 @@ impl ClewdrConfig {
             .to_string()
     }
 
+    /// Path of the SQLite database
+    pub fn db_path(&self) -> PathBuf {
+        self.db_path
+            .to_owned()
+            .unwrap_or_else(|| CONFIG_PATH.with_file_name(DB_NAME))
+    }
+
     /// Loads configuration from files and environment variables
     /// Combines settings from config.toml, clewdr.toml, and environment variables
+    /// With the SQLite backend, settings and cookies come from the database instead
     /// Also loads cookies from a file if specified
     ///
     /// # Returns
@@ -261,14 +564,24 @@ impl ClewdrConfig {
                 error!("Failed to load config: {}", e);
             })
             .unwrap_or_default();
+        if config.storage == StorageBackend::Sqlite && !config.no_fs {
+            #[cfg(feature = "db")]
+            {
+                config = load_from_database(config);
+            }
+            #[cfg(not(feature = "db"))]
+            tracing::warn!("SQLite storage requires the `db` feature, falling back to TOML");
+        }
+        let mut file_cookies = vec![];
         if let Some(ref f) = Args::try_parse().ok().and_then(|a| a.file) {
             // load cookies from file
             if f.exists() {
                 if let Ok(cookies) = std::fs::read_to_string(f) {
-                    let cookies = cookies
+                    file_cookies = cookies
                         .lines()
-                        .filter_map(|line| CookieStatus::new(line, None).ok());
-                    config.cookie_array.extend(cookies);
+                        .filter_map(|line| CookieStatus::new(line, None).ok())
+                        .collect();
+                    config.cookie_array.extend(file_cookies.iter().cloned());
                 } else {
                     error!("Failed to read cookie file: {}", f.display());
                 }
@@ -283,6 +596,16 @@ impl ClewdrConfig {
                 config_clone.save().await.unwrap_or_else(|e| {
                     error!("Failed to save config: {}", e);
                 });
+                if file_cookies.is_empty() {
+                    return;
+                }
+                let changes = file_cookies.into_iter().map(CookieChange::Upsert).collect();
+                persistence::storage()
+                    .save_cookies(changes)
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Failed to save cookies: {}", e);
+                    });
             });
         }
         config
@@ -305,17 +628,12 @@ impl ClewdrConfig {
         SocketAddr::new(self.ip, self.port)
     }
 
-    /// Save the configuration to a file
+    /// Save the configuration to the storage backend
     pub async fn save(&self) -> Result<(), ClewdrError> {
         if self.no_fs {
             return Ok(());
         }
-        if let Some(parent) = CONFIG_PATH.parent()
-            && !parent.exists()
-        {
-            tokio::fs::create_dir_all(parent).await?;
-        }
-        Ok(tokio::fs::write(CONFIG_PATH.as_path(), toml::ser::to_string_pretty(self)?).await?)
+        persistence::storage().save_config(self).await
     }
 
     /// Validate the configuration
@@ -335,6 +653,16 @@ impl ClewdrConfig {
                 })
                 .ok()
         });
+        self.proxies.retain_mut(|p| match Proxy::all(p.url.trim()) {
+            Ok(proxy) => {
+                p.wreq_proxy = Some(proxy);
+                true
+            }
+            Err(e) => {
+                error!("Failed to parse proxy {}: {}", p.url, e);
+                false
+            }
+        });
         self
     }
 }
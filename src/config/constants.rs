@@ -8,9 +8,13 @@ use arc_swap::ArcSwap;
 use clap::Parser;
 use url::Url;
 
-use crate::{Args, config::ClewdrConfig};
+use crate::{
+    Args,
+    config::{ClaudeBackend, ClewdrConfig},
+};
 
 pub const CONFIG_NAME: &str = "clewdr.toml";
+pub const DB_NAME: &str = "clewdr.db";
 pub const CLAUDE_ENDPOINT: &str = "https://api.anthropic.com/";
 #[allow(dead_code)]
 pub const CLAUDE_CONSOLE_ENDPOINT: &str = "https://console.anthropic.com/";
@@ -18,6 +22,7 @@ pub const GEMINI_ENDPOINT: &str = "https://generativelanguage.googleapis.com/";
 pub const CC_CLIENT_ID: &str = "9d1c250a-e61b-44d9-88ed-5944d1962f5e";
 pub const CC_TOKEN_URL: &str = "https://api.anthropic.com/v1/oauth/token";
 pub const CC_REDIRECT_URI: &str = "https://console.anthropic.com/oauth/code/callback";
+pub const CC_AUTHORIZE_URL: &str = "https://claude.ai/oauth/authorize";
 
 pub static ENDPOINT_URL: LazyLock<Url> = LazyLock::new(|| {
     Url::parse(CLAUDE_ENDPOINT).unwrap_or_else(|_| {
@@ -50,6 +55,25 @@ pub static CLEWDR_CONFIG: LazyLock<ArcSwap<ClewdrConfig>> = LazyLock::new(|| {
     ArcSwap::from_pointee(config)
 });
 
+/// Stores the config shared by the tests that go through [`CLEWDR_CONFIG`]
+///
+/// The file system is disabled before the config is first loaded, so tests never write
+/// to it. Cookies may hold one lease at a time and wait at most a second for a slot.
+#[cfg(test)]
+pub fn init_test_config() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        // SAFETY: set once, before the config is loaded, and not read by other tests
+        unsafe { std::env::set_var("CLEWDR_NO_FS", "true") };
+        CLEWDR_CONFIG.store(std::sync::Arc::new(ClewdrConfig {
+            no_fs: true,
+            cookie_max_concurrency: 1,
+            cookie_queue_timeout: 1,
+            ..Default::default()
+        }));
+    });
+}
+
 pub static CONFIG_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
     if let Some(path) = Args::try_parse().ok().and_then(|a| a.config) {
         path
@@ -136,5 +160,101 @@ pub const fn default_skip_cool_down() -> bool {
     true
 }
 
+/// Default setting for recording requests in the audit log
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_audit_log() -> bool {
+    true
+}
+
+/// Default number of audit log entries kept in memory
+///
+/// # Returns
+/// * `usize` - The default value of 1000
+pub const fn default_audit_max_entries() -> usize {
+    1000
+}
+
+/// Default age in hours after which audit log entries are dropped
+///
+/// # Returns
+/// * `u64` - The default value of 24
+pub const fn default_audit_retention_hours() -> u64 {
+    24
+}
+
+/// Default size limit in bytes of request and response bodies in the audit log
+///
+/// # Returns
+/// * `usize` - The default value of 16 KiB
+pub const fn default_audit_body_limit() -> usize {
+    16 * 1024
+}
+
+/// Default time in seconds a request waits for a free cookie
+///
+/// # Returns
+/// * `u64` - The default value of 60
+pub const fn default_cookie_queue_timeout() -> u64 {
+    60
+}
+
+/// Default number of cookies checked at the same time by a health check
+///
+/// # Returns
+/// * `usize` - The default value of 4
+pub const fn default_cookie_health_check_concurrency() -> usize {
+    4
+}
+
+/// Default utilization in percent of a rate limit window at which cookies stop being dispatched
+///
+/// # Returns
+/// * `u32` - The default value of 95
+pub const fn default_utilization_threshold() -> u32 {
+    95
+}
+
+/// Default number of seconds before expiry at which tokens are refreshed in the background
+///
+/// # Returns
+/// * `u64` - The default value of 600
+pub const fn default_token_refresh_lead() -> u64 {
+    600
+}
+
+/// Default number of seconds between two health checks of the proxy pool
+///
+/// # Returns
+/// * `u64` - The default value of 300
+pub const fn default_proxy_health_check_interval() -> u64 {
+    300
+}
+
+/// Default lifetime in seconds of cached responses
+///
+/// # Returns
+/// * `u64` - The default value of 3600
+pub const fn default_response_cache_ttl() -> u64 {
+    3600
+}
+
+/// Default number of responses kept in the memory cache
+///
+/// # Returns
+/// * `u64` - The default value of 1000
+pub const fn default_response_cache_size() -> u64 {
+    1000
+}
+
+/// Default backends tried when Claude Code cannot serve a request
+///
+/// # Returns
+/// * `Vec<ClaudeBackend>` - The default value of Claude Web only
+pub fn default_code_fallback() -> Vec<ClaudeBackend> {
+    vec![ClaudeBackend::Web]
+}
+
 /// Default cookie value for testing purposes
 pub const PLACEHOLDER_COOKIE: &str = "sk-ant-REDACTED";
@@ -6,25 +6,67 @@ use std::{
     sync::LazyLock,
 };
 
+use chrono::DateTime;
+use http::HeaderMap;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use snafu::{GenerateImplicitData, Location};
+use strum::{Display, IntoStaticStr};
 use tracing::info;
 
 use crate::{
-    config::{PLACEHOLDER_COOKIE, TokenInfo},
+    config::{ClewdrConfig, PLACEHOLDER_COOKIE, TokenInfo},
     error::ClewdrError,
 };
 
 /// Model family for usage bucketing
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, IntoStaticStr,
+)]
 #[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
 pub enum ModelFamily {
     Sonnet,
     Opus,
+    #[default]
     Other,
 }
 
+impl ModelFamily {
+    /// Classifies a model name into its family
+    pub fn from_model(model: &str) -> Self {
+        let m = model.to_ascii_lowercase();
+        if m.contains("opus") {
+            ModelFamily::Opus
+        } else if m.contains("sonnet") {
+            ModelFamily::Sonnet
+        } else {
+            ModelFamily::Other
+        }
+    }
+}
+
+/// How the cookie actor picks a cookie for a new request
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum CookieStrategy {
+    /// Rotate through valid cookies in order
+    #[default]
+    RoundRobin,
+    /// Pick the cookie that has been idle the longest
+    LeastRecentlyUsed,
+    /// Pick the cookie with the fewest tokens in the current session window
+    LowestSessionUsage,
+    /// Pick the cookie with the fewest tokens in the current weekly window
+    LowestWeeklyUsage,
+    /// Pick randomly, proportionally to each cookie's weight
+    Weighted,
+    /// Pick uniformly at random
+    Random,
+}
+
 /// Per-model 1M context probing channel
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Claude1mChannel {
@@ -51,6 +93,144 @@ pub struct UsageBreakdown {
     pub opus_output_tokens: u64,
 }
 
+/// Prefix of the unified rate limit headers of Claude Code responses
+pub const UNIFIED_RATELIMIT_PREFIX: &str = "anthropic-ratelimit-unified-";
+
+/// Utilization of the rate limit windows of an account, as reported by Anthropic
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Utilization {
+    /// Percent of the 5 hour session window
+    #[serde(default)]
+    pub session: u32,
+    #[serde(default)]
+    pub session_resets_at: Option<i64>,
+    /// Percent of the 7 day window of all models
+    #[serde(default)]
+    pub seven_day: u32,
+    #[serde(default)]
+    pub seven_day_resets_at: Option<i64>,
+    #[serde(default)]
+    pub seven_day_opus: u32,
+    #[serde(default)]
+    pub seven_day_opus_resets_at: Option<i64>,
+    #[serde(default)]
+    pub seven_day_sonnet: u32,
+    #[serde(default)]
+    pub seven_day_sonnet_resets_at: Option<i64>,
+    /// Unified status of the last response, e.g. `allowed_warning`, unknown when the
+    /// snapshot comes from the usage endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Time of the snapshot (epoch seconds, UTC)
+    #[serde(default)]
+    pub fetched_at: i64,
+}
+
+impl Utilization {
+    /// Reads a response of the OAuth usage endpoint
+    pub fn from_usage(usage: &Value, fetched_at: i64) -> Self {
+        let window = |key: &str| {
+            let percent = usage[key]["utilization"]
+                .as_f64()
+                .map(|v| v.round() as u32)
+                .unwrap_or_default();
+            let resets_at = usage[key]["resets_at"]
+                .as_str()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .map(|t| t.timestamp());
+            (percent, resets_at)
+        };
+        let (session, session_resets_at) = window("five_hour");
+        let (seven_day, seven_day_resets_at) = window("seven_day");
+        let (seven_day_opus, seven_day_opus_resets_at) = window("seven_day_opus");
+        let (seven_day_sonnet, seven_day_sonnet_resets_at) = window("seven_day_sonnet");
+        Self {
+            session,
+            session_resets_at,
+            seven_day,
+            seven_day_resets_at,
+            seven_day_opus,
+            seven_day_opus_resets_at,
+            seven_day_sonnet,
+            seven_day_sonnet_resets_at,
+            status: None,
+            fetched_at,
+        }
+    }
+
+    /// Updates the snapshot from the unified rate limit headers of a Claude Code response
+    ///
+    /// Utilizations are reported as fractions and resets as epoch seconds. Windows missing
+    /// from the headers keep their last value.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the headers carried any of the unified rate limits
+    pub fn apply_headers(&mut self, headers: &HeaderMap, now: i64) -> bool {
+        let header = |name: &str| {
+            headers
+                .get(format!("{UNIFIED_RATELIMIT_PREFIX}{name}"))
+                .and_then(|v| v.to_str().ok())
+        };
+        let mut found = false;
+        for (window, percent, resets_at) in [
+            ("5h", &mut self.session, &mut self.session_resets_at),
+            ("7d", &mut self.seven_day, &mut self.seven_day_resets_at),
+            (
+                "7d_opus",
+                &mut self.seven_day_opus,
+                &mut self.seven_day_opus_resets_at,
+            ),
+            (
+                "7d_sonnet",
+                &mut self.seven_day_sonnet,
+                &mut self.seven_day_sonnet_resets_at,
+            ),
+        ] {
+            let Some(utilization) =
+                header(&format!("{window}-utilization")).and_then(|v| v.parse::<f64>().ok())
+            else {
+                continue;
+            };
+            *percent = (utilization * 100.0).round() as u32;
+            *resets_at = header(&format!("{window}-reset")).and_then(|v| v.parse().ok());
+            found = true;
+        }
+        if let Some(status) = header("status") {
+            self.status = Some(status.to_string());
+            found = true;
+        }
+        if found {
+            self.fetched_at = now;
+        }
+        found
+    }
+
+    /// Checks whether a window used by the family is at or over its threshold
+    ///
+    /// Windows that reset since the snapshot do not count, a threshold of 0 is disabled.
+    pub fn saturated(&self, family: ModelFamily, session: u32, weekly: u32, now: i64) -> bool {
+        let over = |percent: u32, resets_at: Option<i64>, threshold: u32| {
+            threshold > 0 && percent >= threshold && resets_at.is_some_and(|t| t > now)
+        };
+        over(self.session, self.session_resets_at, session)
+            || over(self.seven_day, self.seven_day_resets_at, weekly)
+            || match family {
+                ModelFamily::Opus => {
+                    over(self.seven_day_opus, self.seven_day_opus_resets_at, weekly)
+                }
+                ModelFamily::Sonnet => over(
+                    self.seven_day_sonnet,
+                    self.seven_day_sonnet_resets_at,
+                    weekly,
+                ),
+                ModelFamily::Other => false,
+            }
+    }
+}
+
+/// Prefix of the identity of accounts added through OAuth, which have no session cookie
+const OAUTH_PREFIX: &str = "oauth-";
+
 /// A struct representing a cookie
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ClewdrCookie {
@@ -84,12 +264,37 @@ pub struct CookieStatus {
     pub token: Option<TokenInfo>,
     #[serde(default)]
     pub reset_time: Option<i64>,
+    /// Cooldown of Sonnet models only, set when the Sonnet weekly limit is hit
+    #[serde(default)]
+    pub sonnet_reset_time: Option<i64>,
+    /// Cooldown of Opus models only, set when the Opus weekly limit is hit
+    #[serde(default)]
+    pub opus_reset_time: Option<i64>,
     #[serde(default)]
     pub supports_claude_1m_sonnet: Option<bool>,
     #[serde(default)]
     pub supports_claude_1m_opus: Option<bool>,
     #[serde(default)]
     pub count_tokens_allowed: Option<bool>,
+    /// Relative weight for the weighted strategy, defaults to 1
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// Last time the cookie was dispatched (epoch seconds, UTC)
+    #[serde(default)]
+    pub last_used_at: Option<i64>,
+
+    /// Display name of the account
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Free form notes of the admin
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Person or team owning the account
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Group of the cookie, requests bound to a group only use the cookies of that group
+    #[serde(default)]
+    pub group: Option<String>,
 
     // New: Per-period usage breakdown
     #[serde(default)]
@@ -127,6 +332,16 @@ pub struct CookieStatus {
     pub weekly_sonnet_has_reset: Option<bool>,
     #[serde(default)]
     pub weekly_opus_has_reset: Option<bool>,
+
+    /// Last time the health checker probed the cookie (epoch seconds, UTC)
+    #[serde(default)]
+    pub last_checked_at: Option<i64>,
+    /// Error of the last health check, None if it passed
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Last utilization snapshot of the rate limit windows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utilization: Option<Utilization>,
 }
 
 impl PartialEq for CookieStatus {
@@ -170,9 +385,17 @@ impl CookieStatus {
             cookie,
             token: None,
             reset_time,
+            sonnet_reset_time: None,
+            opus_reset_time: None,
             supports_claude_1m_sonnet: Some(true),
             supports_claude_1m_opus: Some(true),
             count_tokens_allowed: None,
+            weight: None,
+            last_used_at: None,
+            label: None,
+            notes: None,
+            owner: None,
+            group: None,
 
             session_usage: UsageBreakdown::default(),
             weekly_usage: UsageBreakdown::default(),
@@ -188,9 +411,35 @@ impl CookieStatus {
             weekly_has_reset: None,
             weekly_sonnet_has_reset: None,
             weekly_opus_has_reset: None,
+            last_checked_at: None,
+            last_error: None,
+            utilization: None,
         })
     }
 
+    /// Creates the entry of an account added through OAuth, without a session cookie
+    ///
+    /// Its token is set once an authorization code or a refresh token is exchanged.
+    pub fn oauth() -> Self {
+        Self {
+            cookie: ClewdrCookie::oauth(),
+            supports_claude_1m_sonnet: Some(true),
+            supports_claude_1m_opus: Some(true),
+            ..Default::default()
+        }
+    }
+
+    /// Weight used by the weighted selection strategy
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+
+    /// Whether the cookie may serve a request bound to `group`, ungrouped cookies serve unbound
+    /// requests only
+    pub fn in_group(&self, group: Option<&str>) -> bool {
+        self.group.as_deref() == group
+    }
+
     /// Checks if the cookie's reset time has expired
     /// If the reset time has passed, sets it to None so the cookie becomes valid again
     ///
@@ -213,6 +462,59 @@ impl CookieStatus {
         self
     }
 
+    /// Cooldown end of a single model family, if any
+    pub fn family_reset_time(&self, family: ModelFamily) -> Option<i64> {
+        match family {
+            ModelFamily::Sonnet => self.sonnet_reset_time,
+            ModelFamily::Opus => self.opus_reset_time,
+            ModelFamily::Other => None,
+        }
+    }
+
+    /// Checks whether the cookie may serve a request of the given model family
+    pub fn available_for(&self, family: ModelFamily, now: i64) -> bool {
+        self.family_reset_time(family).is_none_or(|t| t <= now)
+    }
+
+    /// Checks whether the last utilization snapshot is under the configured thresholds for
+    /// the given model family
+    pub fn under_thresholds(&self, family: ModelFamily, config: &ClewdrConfig, now: i64) -> bool {
+        self.utilization.as_ref().is_none_or(|u| {
+            !u.saturated(
+                family,
+                config.session_utilization_threshold,
+                config.weekly_utilization_threshold,
+                now,
+            )
+        })
+    }
+
+    /// Puts a single model family on cooldown, other families stay usable
+    ///
+    /// The family's usage is kept, it is only reset when its window rolls over.
+    pub fn set_family_cooldown(&mut self, family: ModelFamily, until: i64) {
+        match family {
+            ModelFamily::Sonnet => self.sonnet_reset_time = Some(until),
+            ModelFamily::Opus => self.opus_reset_time = Some(until),
+            ModelFamily::Other => {}
+        }
+    }
+
+    /// Clears family cooldowns that have expired
+    ///
+    /// # Returns
+    /// Whether any cooldown was cleared
+    pub fn clear_expired_family_cooldowns(&mut self, now: i64) -> bool {
+        let mut changed = false;
+        for reset_time in [&mut self.sonnet_reset_time, &mut self.opus_reset_time] {
+            if reset_time.is_some_and(|t| t <= now) {
+                *reset_time = None;
+                changed = true;
+            }
+        }
+        changed
+    }
+
     pub fn add_token(&mut self, token: TokenInfo) {
         self.token = Some(token);
     }
@@ -403,6 +705,26 @@ impl Default for ClewdrCookie {
 }
 
 impl ClewdrCookie {
+    /// Random identity of an account added through OAuth
+    pub fn oauth() -> Self {
+        Self {
+            inner: format!("{OAUTH_PREFIX}{}", uuid::Uuid::new_v4().simple()),
+        }
+    }
+
+    /// Identity of the OAuth account owning `uuid`, the same however many times it logs in
+    pub fn oauth_owner(uuid: &str) -> Option<Self> {
+        let uuid = uuid::Uuid::parse_str(uuid).ok()?;
+        Some(Self {
+            inner: format!("{OAUTH_PREFIX}{}", uuid.simple()),
+        })
+    }
+
+    /// Whether this is a claude.ai session cookie, false for accounts added through OAuth
+    pub fn is_session(&self) -> bool {
+        !self.inner.starts_with(OAUTH_PREFIX)
+    }
+
     pub fn ellipse(&self) -> String {
         let len = self.inner.len();
         if len > 20 {
@@ -422,6 +744,14 @@ impl FromStr for ClewdrCookie {
         });
         static RE_BASE: LazyLock<Regex> =
             LazyLock::new(|| Regex::new(r"^[0-9A-Za-z_-]{86,120}-[0-9A-Za-z_-]{6}AA$").unwrap());
+        static RE_OAUTH: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^oauth-[0-9a-f]{32}$").unwrap());
+
+        if RE_OAUTH.is_match(s.trim()) {
+            return Ok(Self {
+                inner: s.trim().to_string(),
+            });
+        }
 
         let cleaned = s
             .trim()
@@ -494,4 +824,84 @@ mod tests {
         let result = ClewdrCookie::from_str("invalid-cookie");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_cookie_group() {
+        let base = make_base_cookie_with_len(86);
+        let mut cookie = CookieStatus::new(&base, None).unwrap();
+        assert!(cookie.in_group(None));
+        assert!(!cookie.in_group(Some("team-a")));
+        cookie.group = Some("team-a".to_string());
+        assert!(cookie.in_group(Some("team-a")));
+        assert!(!cookie.in_group(Some("team-b")));
+        assert!(!cookie.in_group(None));
+    }
+
+    #[test]
+    fn test_oauth_cookie() {
+        let cookie = ClewdrCookie::oauth();
+        assert!(!cookie.is_session());
+        let parsed = ClewdrCookie::from_str(&cookie).unwrap();
+        assert_eq!(parsed, cookie);
+        let owner = ClewdrCookie::oauth_owner("3f2a6c1e-8b4d-4e5f-9a7b-0c1d2e3f4a5b").unwrap();
+        assert_eq!(&*owner, "oauth-3f2a6c1e8b4d4e5f9a7b0c1d2e3f4a5b");
+        assert!(ClewdrCookie::from_str(&owner).is_ok());
+        assert!(ClewdrCookie::oauth_owner("").is_none());
+        let base = make_base_cookie_with_len(86);
+        assert!(ClewdrCookie::from_str(&base).unwrap().is_session());
+    }
+
+    #[test]
+    fn test_utilization_thresholds() {
+        let usage = serde_json::json!({
+            "five_hour": { "utilization": 96.4, "resets_at": "2025-01-01T05:00:00+00:00" },
+            "seven_day": { "utilization": 40.0, "resets_at": "2025-01-07T00:00:00+00:00" },
+            "seven_day_opus": { "utilization": 99.0, "resets_at": "2025-01-07T00:00:00+00:00" },
+            "seven_day_sonnet": null,
+        });
+        let u = Utilization::from_usage(&usage, 0);
+        assert_eq!(u.session, 96);
+        assert_eq!(u.seven_day_sonnet_resets_at, None);
+
+        let now = 1735693200; // 2025-01-01T01:00:00Z
+        assert!(u.saturated(ModelFamily::Sonnet, 95, 95, now));
+        assert!(!u.saturated(ModelFamily::Sonnet, 97, 95, now));
+        assert!(u.saturated(ModelFamily::Opus, 97, 95, now));
+        assert!(!u.saturated(ModelFamily::Opus, 0, 0, now));
+        // the session window reset since the snapshot
+        assert!(!u.saturated(ModelFamily::Sonnet, 95, 95, now + 5 * 3600));
+    }
+
+    #[test]
+    fn test_utilization_from_headers() {
+        let mut u = Utilization {
+            seven_day_opus: 80,
+            ..Default::default()
+        };
+        assert!(!u.apply_headers(&HeaderMap::new(), 10));
+        assert_eq!(u.fetched_at, 0);
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("status", "allowed_warning"),
+            ("5h-utilization", "0.962"),
+            ("5h-reset", "1735707600"),
+            ("7d-utilization", "0.4"),
+        ] {
+            headers.insert(
+                format!("{UNIFIED_RATELIMIT_PREFIX}{name}")
+                    .parse::<http::HeaderName>()
+                    .unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        assert!(u.apply_headers(&headers, 10));
+        assert_eq!(u.session, 96);
+        assert_eq!(u.session_resets_at, Some(1735707600));
+        assert_eq!(u.seven_day, 40);
+        assert_eq!(u.seven_day_resets_at, None);
+        assert_eq!(u.seven_day_opus, 80);
+        assert_eq!(u.status.as_deref(), Some("allowed_warning"));
+        assert_eq!(u.fetched_at, 10);
+    }
 }
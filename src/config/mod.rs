@@ -1,12 +1,18 @@
 // Re-export all items from submodules
+mod api_key;
 mod clewdr_config;
 mod constants;
 mod cookie;
+mod pooled_key;
+mod proxy;
 mod reason;
 mod token;
 
+pub use api_key::*;
 pub use clewdr_config::*;
 pub use constants::*;
 pub use cookie::*;
+pub use pooled_key::*;
+pub use proxy::*;
 pub use reason::*;
 pub use token::*;
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Reason;
+
+/// An upstream API key served from a rotating key pool
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PooledKey {
+    pub key: String,
+    /// Unix timestamp (UTC) until which the key is rate limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_until: Option<i64>,
+    /// Why upstream rejected the key, such keys are no longer dispatched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invalid: Option<Reason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<i64>,
+}
+
+impl PartialEq for PooledKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for PooledKey {}
+
+impl PooledKey {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            cooldown_until: None,
+            invalid: None,
+            last_used_at: None,
+        }
+    }
+
+    /// Whether the key can be dispatched at `now`
+    pub fn available(&self, now: i64) -> bool {
+        self.invalid.is_none() && self.cooldown_until.is_none_or(|t| t <= now)
+    }
+
+    /// Shortened key for logs
+    pub fn ellipse(&self) -> String {
+        let len = self.key.chars().count();
+        if len <= 12 {
+            return self.key.to_owned();
+        }
+        let head = self.key.chars().take(8).collect::<String>();
+        let tail = self.key.chars().skip(len - 4).collect::<String>();
+        format!("{head}...{tail}")
+    }
+}
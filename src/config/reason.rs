@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::CookieStatus;
-use crate::config::ClewdrCookie;
+use crate::config::{ClewdrCookie, ModelFamily};
 
 /// Reason why a cookie is considered useless
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Error)]
@@ -19,6 +19,8 @@ pub enum Reason {
     Null,
     Restricted(i64),
     TooManyRequest(i64),
+    /// Rate limited for a single model family only
+    TooManyRequestFamily(ModelFamily, i64),
 }
 
 impl Display for Reason {
@@ -40,6 +42,15 @@ impl Display for Reason {
             Reason::TooManyRequest(i) => {
                 write!(f, "429 Too many request: until {}", format_time(*i))
             }
+            Reason::TooManyRequestFamily(family, i) => {
+                let family: &'static str = family.into();
+                write!(
+                    f,
+                    "429 Too many {} request: until {}",
+                    family,
+                    format_time(*i)
+                )
+            }
         }
     }
 }
@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use oauth2::{EmptyExtraTokenFields, StandardTokenResponse, TokenResponse, basic::BasicTokenType};
+use oauth2::{ExtraTokenFields, StandardTokenResponse, TokenResponse, basic::BasicTokenType};
 use serde::{Deserialize, Serialize};
 use serde_with::{DurationSeconds, TimestampSecondsWithFrac, serde_as};
 use tracing::debug;
@@ -12,6 +12,24 @@ pub struct Organization {
     pub uuid: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Account {
+    pub uuid: String,
+}
+
+/// Fields Anthropic adds to its token responses
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClaudeTokenFields {
+    #[serde(default)]
+    pub organization: Option<Organization>,
+    #[serde(default)]
+    pub account: Option<Account>,
+}
+
+impl ExtraTokenFields for ClaudeTokenFields {}
+
+pub type ClaudeTokenResponse = StandardTokenResponse<ClaudeTokenFields, BasicTokenType>;
+
 #[serde_as]
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TokenInfo {
@@ -22,27 +40,58 @@ pub struct TokenInfo {
     pub refresh_token: String,
     #[serde_as(as = "TimestampSecondsWithFrac")]
     pub expires_at: DateTime<Utc>,
+    /// Account the token was issued to, unknown for tokens stored by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<Account>,
 }
 
 impl TokenInfo {
-    pub fn new(
-        raw: StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>,
-        organization_uuid: String,
-    ) -> Self {
+    /// Token of an exchange, `organization_uuid` is used when the response names none
+    pub fn new(raw: ClaudeTokenResponse, organization_uuid: String) -> Self {
         let expires_at = Utc::now() + raw.expires_in().unwrap_or_default();
+        let extra = raw.extra_fields();
         Self {
             access_token: raw.access_token().secret().to_string(),
             expires_in: raw.expires_in().unwrap_or_default(),
-            organization: Organization {
-                uuid: organization_uuid,
-            },
+            organization: extra
+                .organization
+                .to_owned()
+                .filter(|o| !o.uuid.is_empty())
+                .unwrap_or(Organization {
+                    uuid: organization_uuid,
+                }),
             refresh_token: raw
                 .refresh_token()
                 .map_or_else(Default::default, |rt| rt.secret().to_string()),
             expires_at,
+            account: extra.account.to_owned(),
         }
     }
 
+    /// Token holding only a refresh token, already expired so it is refreshed before use
+    pub fn from_refresh_token(refresh_token: String) -> Self {
+        Self {
+            access_token: String::new(),
+            expires_in: Duration::ZERO,
+            organization: Organization {
+                uuid: String::new(),
+            },
+            refresh_token,
+            expires_at: DateTime::UNIX_EPOCH,
+            account: None,
+        }
+    }
+
+    /// Uuid identifying the owner of the token: its account, or its organization when the
+    /// account is unknown
+    pub fn owner_uuid(&self) -> Option<&str> {
+        let account = self.account.as_ref().map(|a| a.uuid.as_str());
+        [account, Some(self.organization.uuid.as_str())]
+            .into_iter()
+            .flatten()
+            .find(|uuid| !uuid.is_empty())
+    }
+
     pub fn is_expired(&self) -> bool {
         debug!("Expires at: {}", self.expires_at.to_rfc3339());
         Utc::now() >= self.expires_at - Duration::from_secs(60 * 5) // 5 minutes
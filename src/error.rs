@@ -16,7 +16,11 @@ use tokio::sync::oneshot;
 use tracing::{debug, error};
 use wreq::{Response, StatusCode, header::InvalidHeaderValue};
 
-use crate::{config::Reason, types::claude::Message};
+use crate::{
+    config::{ModelFamily, Reason},
+    services::metrics::METRICS,
+    types::claude::Message,
+};
 
 #[derive(Debug, IntoStaticStr, snafu::Snafu)]
 #[snafu(visibility(pub(crate)))]
@@ -101,6 +105,13 @@ pub enum ClewdrError {
     #[snafu(context(false))]
     #[cfg(feature = "portable")]
     ZipError { source: zip::result::ZipError },
+    #[snafu(display("Database error: {}", source))]
+    #[snafu(context(false))]
+    #[cfg(feature = "db")]
+    DatabaseError { source: rusqlite::Error },
+    #[snafu(display("Blocking task failed: {}", source))]
+    #[snafu(context(false))]
+    JoinError { source: tokio::task::JoinError },
     #[snafu(display("Asset Error: {}", msg))]
     AssetError { msg: String },
     #[snafu(display("Invalid version: {}", version))]
@@ -113,6 +124,10 @@ pub enum ClewdrError {
     CookieDispatchError { source: oneshot::error::RecvError },
     #[snafu(display("No cookie available"))]
     NoCookieAvailable,
+    #[snafu(display("No API key available"))]
+    NoKeyAvailable,
+    #[snafu(display("Invalid key: {}", reason))]
+    InvalidKey { reason: Reason },
     #[snafu(display("Invalid Cookie: {}", reason))]
     #[snafu(context(false))]
     InvalidCookie {
@@ -158,6 +173,10 @@ pub enum ClewdrError {
     TimestampError { timestamp: i64 },
     #[snafu(display("Key/Password Invalid"))]
     InvalidAuth,
+    #[snafu(display("Forbidden: {}", msg))]
+    Forbidden { msg: String },
+    #[snafu(display("Quota exceeded: {}", msg))]
+    QuotaExceeded { msg: String },
     #[snafu(whatever, display("{}: {}", message, source.as_ref().map_or_else(|| "Unknown error".into(), |e| e.to_string())))]
     Whatever {
         message: String,
@@ -188,7 +207,15 @@ impl IntoResponse for ClewdrError {
                 (source.status(), json!(source.body_text()))
             }
             ClewdrError::ClaudeHttpError { code, inner } => {
-                return (code, Json(ClaudeError { error: inner })).into_response();
+                let mut resp = (
+                    code,
+                    Json(ClaudeError {
+                        error: inner.to_owned(),
+                    }),
+                )
+                    .into_response();
+                resp.extensions_mut().insert(inner);
+                return resp;
             }
             ClewdrError::TestMessage => {
                 return (
@@ -204,8 +231,13 @@ impl IntoResponse for ClewdrError {
             }
             ClewdrError::TooManyRetries => (StatusCode::GATEWAY_TIMEOUT, json!(self.to_string())),
             ClewdrError::InvalidCookie { .. } => (StatusCode::BAD_REQUEST, json!(self.to_string())),
+            ClewdrError::InvalidKey { .. } => (StatusCode::BAD_REQUEST, json!(self.to_string())),
             ClewdrError::PathNotFound { .. } => (StatusCode::NOT_FOUND, json!(self.to_string())),
             ClewdrError::InvalidAuth => (StatusCode::UNAUTHORIZED, json!(self.to_string())),
+            ClewdrError::Forbidden { .. } => (StatusCode::FORBIDDEN, json!(self.to_string())),
+            ClewdrError::QuotaExceeded { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, json!(self.to_string()))
+            }
             ClewdrError::BadRequest { .. } => (StatusCode::BAD_REQUEST, json!(self.to_string())),
             ClewdrError::InvalidHeaderValue { .. } => {
                 (StatusCode::BAD_REQUEST, json!(self.to_string()))
@@ -220,7 +252,10 @@ impl IntoResponse for ClewdrError {
                 code: Some(status.as_u16()),
             },
         };
-        (status, Json(err)).into_response()
+        let mut resp = (status, Json(err.to_owned())).into_response();
+        // kept so the OpenAI routes can render the error in their own format
+        resp.extensions_mut().insert(err.error);
+        resp
     }
 }
 
@@ -302,7 +337,18 @@ impl CheckClaudeErr for Response {
             .headers()
             .get("anthropic-ratelimit-unified-reset")
             .cloned();
+        // which limit was hit, a weekly per-model limit only affects that family
+        let limited_family = self
+            .headers()
+            .get("anthropic-ratelimit-unified-representative-claim")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|claim| match claim {
+                "seven_day_opus" => Some(ModelFamily::Opus),
+                "seven_day_sonnet" => Some(ModelFamily::Sonnet),
+                _ => None,
+            });
         debug!("Error response status: {}", status);
+        METRICS.record_upstream_error(status.as_u16());
         if status == 302 {
             // blocked by cloudflare
             let error = ClaudeErrorBody {
@@ -391,6 +437,11 @@ impl CheckClaudeErr for Response {
                     "Rate limit exceeded, expires in {} hours",
                     mins as f64 / 60.0
                 );
+                if let Some(family) = limited_family {
+                    return Err(ClewdrError::InvalidCookie {
+                        reason: Reason::TooManyRequestFamily(family, ts),
+                    });
+                }
                 return Err(ClewdrError::InvalidCookie {
                     reason: Reason::TooManyRequest(ts),
                 });
@@ -407,3 +458,118 @@ impl CheckClaudeErr for Response {
         })
     }
 }
+
+/// Error response of the Gemini API
+#[derive(Debug, Deserialize)]
+struct GeminiError {
+    error: GeminiErrorBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiErrorBody {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    details: Vec<Value>,
+}
+
+impl GeminiErrorBody {
+    /// Delay suggested by a `google.rpc.RetryInfo` detail, in seconds
+    fn retry_delay(&self) -> Option<i64> {
+        self.details.iter().find_map(|d| {
+            d["retryDelay"]
+                .as_str()?
+                .trim_end_matches('s')
+                .parse::<f64>()
+                .ok()
+                .map(|s| s.ceil() as i64)
+        })
+    }
+
+    /// Whether a detail reports the API key itself as invalid
+    fn invalid_key(&self) -> bool {
+        self.details
+            .iter()
+            .any(|d| d["reason"].as_str() == Some("API_KEY_INVALID"))
+    }
+
+    /// Whether the error condemns the key, a 403 alone may only deny a model or region
+    fn rejects_key(&self, status: StatusCode) -> bool {
+        status == StatusCode::UNAUTHORIZED || self.invalid_key()
+    }
+}
+
+pub trait CheckGeminiErr
+where
+    Self: Sized,
+{
+    fn check_gemini(self) -> impl Future<Output = Result<Self, ClewdrError>>;
+}
+
+impl CheckGeminiErr for Response {
+    /// Checks response from the Gemini API for errors
+    ///
+    /// Rate limited and invalid keys are reported as [`ClewdrError::InvalidKey`] so the key
+    /// pool can cool them down or drop them, other errors, such as a `PERMISSION_DENIED` on a
+    /// model, are relayed in Claude format.
+    async fn check_gemini(self) -> Result<Self, ClewdrError> {
+        let status = self.status();
+        if status.is_success() {
+            return Ok(self);
+        }
+        debug!("Gemini error response status: {}", status);
+        METRICS.record_upstream_error(status.as_u16());
+        let text = self.text().await.unwrap_or_default();
+        let Ok(err) = serde_json::from_str::<GeminiError>(&text) else {
+            let error = ClaudeErrorBody {
+                message: format!("Unknown error: {text}").into(),
+                r#type: "error_parse_error_body".to_string(),
+                code: Some(status.as_u16()),
+            };
+            return Err(ClewdrError::ClaudeHttpError {
+                code: status,
+                inner: error,
+            });
+        };
+        let err = err.error;
+        if status == 429 {
+            let delay = err.retry_delay().unwrap_or(60);
+            error!("Gemini rate limit exceeded, retry in {} seconds", delay);
+            return Err(ClewdrError::InvalidKey {
+                reason: Reason::TooManyRequest(Utc::now().timestamp() + delay),
+            });
+        }
+        if err.rejects_key(status) {
+            return Err(ClewdrError::InvalidKey {
+                reason: Reason::Null,
+            });
+        }
+        Err(ClewdrError::ClaudeHttpError {
+            code: status,
+            inner: ClaudeErrorBody {
+                message: json!(err.message),
+                r#type: err.status,
+                code: Some(status.as_u16()),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_invalid_gemini_keys_are_rejected() {
+        let error = |reason: &str| GeminiErrorBody {
+            message: String::new(),
+            status: String::new(),
+            details: vec![json!({"reason": reason})],
+        };
+        assert!(error("").rejects_key(StatusCode::UNAUTHORIZED));
+        assert!(error("API_KEY_INVALID").rejects_key(StatusCode::BAD_REQUEST));
+        assert!(!error("").rejects_key(StatusCode::FORBIDDEN));
+    }
+}
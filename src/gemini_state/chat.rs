@@ -0,0 +1,186 @@
+use async_stream::try_stream;
+use axum::{
+    Json,
+    response::{IntoResponse, Sse},
+};
+use colored::Colorize;
+use eventsource_stream::Eventsource;
+use futures::TryStreamExt;
+use snafu::ResultExt;
+use tracing::{Instrument, error, info, warn};
+
+use crate::{
+    config::{CLEWDR_CONFIG, GEMINI_ENDPOINT, ModelFamily},
+    error::{CheckGeminiErr, ClewdrError, WreqSnafu},
+    gemini_state::GeminiState,
+    services::{audit::AUDIT_LOG, key_usage::record_usage, metrics::METRICS},
+    types::{
+        claude::CreateMessageParams,
+        gemini::{GeminiStreamTranslator, GenerateContentRequest, GenerateContentResponse},
+    },
+    utils::sse_event,
+};
+
+/// Checks that a model name can be used as a path segment of the Gemini API
+fn valid_model(model: &str) -> bool {
+    !model.is_empty()
+        && model
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+impl GeminiState {
+    /// Attempts to send a chat message to the Gemini API with retry mechanism
+    ///
+    /// The request is translated once, each attempt uses the next key of the pool.
+    /// Rate limited or rejected keys are returned to the pool with the reason and
+    /// the request is retried with another key.
+    ///
+    /// # Arguments
+    /// * `p` - The client request body containing messages and configuration
+    ///
+    /// # Returns
+    /// * `Result<axum::response::Response, ClewdrError>` - Claude formatted response or error
+    pub async fn try_chat(
+        &mut self,
+        p: CreateMessageParams,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        if !valid_model(&p.model) {
+            return Err(ClewdrError::BadRequest {
+                msg: "Invalid Gemini model name",
+            });
+        }
+        let model = p.model.to_owned();
+        let body = GenerateContentRequest::from(p);
+        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
+            if i > 0 {
+                info!("[RETRY] attempt: {}", i.to_string().green());
+                METRICS.record_retry("gemini");
+            }
+            let key = self.request_key().await?;
+            AUDIT_LOG.record_attempt(self.audit_id, i, key.ellipse());
+            let res = self
+                .send_chat(&key.key, &model, &body)
+                .instrument(tracing::info_span!("gemini", "key" = key.ellipse()));
+            match res.await {
+                Ok(res) => return Ok(res),
+                Err(e) => {
+                    error!("[{}] {}", key.ellipse().green(), e);
+                    if let ClewdrError::InvalidKey { reason } = e {
+                        self.return_key(Some(reason)).await;
+                        continue;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Err(ClewdrError::TooManyRetries)
+    }
+
+    async fn send_chat(
+        &self,
+        key: &str,
+        model: &str,
+        body: &GenerateContentRequest,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        let method = if self.stream {
+            "streamGenerateContent?alt=sse"
+        } else {
+            "generateContent"
+        };
+        let response = self
+            .client
+            .post(format!("{GEMINI_ENDPOINT}v1beta/models/{model}:{method}"))
+            .header("x-goog-api-key", key)
+            .json(body)
+            .send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to send Gemini request",
+            })?
+            .check_gemini()
+            .await?;
+
+        let api_key = self.api_key.to_owned();
+        let audit_id = self.audit_id;
+        let estimated_input = self.usage.input_tokens;
+        if !self.stream {
+            let response = response
+                .json::<GenerateContentResponse>()
+                .await
+                .context(WreqSnafu {
+                    msg: "Failed to parse Gemini response",
+                })?
+                .into_claude(model);
+            let usage = response.usage.to_owned().unwrap_or_default();
+            let input = if usage.input_tokens > 0 {
+                usage.input_tokens
+            } else {
+                estimated_input
+            };
+            record_usage(
+                "gemini",
+                ModelFamily::Other,
+                api_key.as_deref(),
+                audit_id,
+                input as u64,
+                usage.output_tokens as u64,
+            );
+            return Ok(Json(response).into_response());
+        }
+
+        let stream = response
+            .bytes_stream()
+            .eventsource()
+            .map_err(axum::Error::new);
+        let model = model.to_owned();
+        let stream = try_stream! {
+            let mut translator = GeminiStreamTranslator::new(model);
+            futures::pin_mut!(stream);
+            while let Some(event) = stream.try_next().await? {
+                let Ok(chunk) = serde_json::from_str::<GenerateContentResponse>(&event.data) else {
+                    warn!("Skipping malformed Gemini stream chunk");
+                    continue;
+                };
+                for e in translator.push(chunk) {
+                    yield sse_event(&e);
+                }
+            }
+            for e in translator.finish() {
+                yield sse_event(&e);
+            }
+            let usage = translator.usage();
+            let input = if usage.prompt_token_count > 0 {
+                usage.prompt_token_count
+            } else {
+                estimated_input
+            };
+            record_usage(
+                "gemini",
+                ModelFamily::Other,
+                api_key.as_deref(),
+                audit_id,
+                input as u64,
+                usage.output_tokens() as u64,
+            );
+        };
+        Ok(Sse::new(stream)
+            .keep_alive(Default::default())
+            .into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_model_names_that_escape_the_path() {
+        assert!(valid_model("gemini-2.5-pro"));
+        assert!(valid_model("gemini-2.0-flash_exp.1"));
+        assert!(!valid_model(""));
+        assert!(!valid_model("../v1/files"));
+        assert!(!valid_model("gemini-2.5-pro:generateContent?key=x"));
+        assert!(!valid_model("gemini 2.5"));
+    }
+}
@@ -0,0 +1,59 @@
+mod chat;
+use tracing::error;
+
+use crate::{
+    config::{PooledKey, Reason},
+    error::ClewdrError,
+    middleware::claude::ClaudeApiFormat,
+    services::key_pool::KeyPoolHandle,
+    types::claude::Usage,
+};
+
+/// State of a request forwarded to the Gemini API
+#[derive(Clone)]
+pub struct GeminiState {
+    pub key_pool_handle: KeyPoolHandle,
+    pub key: Option<PooledKey>,
+    pub client: wreq::Client,
+    pub api_format: ClaudeApiFormat,
+    pub stream: bool,
+    pub usage: Usage,
+    pub api_key: Option<String>,
+    pub audit_id: Option<u64>,
+}
+
+impl GeminiState {
+    /// Create a new GeminiState instance
+    pub fn new(key_pool_handle: KeyPoolHandle, client: wreq::Client) -> Self {
+        GeminiState {
+            key_pool_handle,
+            key: None,
+            client,
+            api_format: ClaudeApiFormat::Claude,
+            stream: false,
+            usage: Usage::default(),
+            api_key: None,
+            audit_id: None,
+        }
+    }
+
+    /// Requests a new key from the key pool
+    pub async fn request_key(&mut self) -> Result<PooledKey, ClewdrError> {
+        let key = self.key_pool_handle.request().await?;
+        self.key = Some(key.to_owned());
+        Ok(key)
+    }
+
+    /// Returns the current key to the key pool
+    /// Optionally provides a reason for returning the key (e.g., rate limited, invalid)
+    pub async fn return_key(&self, reason: Option<Reason>) {
+        if let Some(ref key) = self.key {
+            self.key_pool_handle
+                .return_key(key.to_owned(), reason)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to send key: {}", e);
+                });
+        }
+    }
+}
@@ -6,11 +6,14 @@ use colored::Colorize;
 use crate::config::CLEWDR_CONFIG;
 
 pub mod api;
+pub mod claude_api_state;
 pub mod claude_code_state;
 pub mod claude_web_state;
 pub mod config;
 pub mod error;
+pub mod gemini_state;
 pub mod middleware;
+pub mod persistence;
 pub mod providers;
 pub mod router;
 pub mod services;
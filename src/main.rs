@@ -7,7 +7,7 @@ use clewdr::{
 use colored::Colorize;
 #[cfg(feature = "mimalloc")]
 use mimalloc::MiMalloc;
-use std::io::IsTerminal;
+use std::{io::IsTerminal, net::SocketAddr};
 use tracing::Subscriber;
 use tracing_subscriber::{
     Layer, Registry,
@@ -134,8 +134,9 @@ async fn main() -> Result<(), ClewdrError> {
         .await
         .with_default_setup()
         .build();
-    // serve the application
-    Ok(axum::serve(listener, router)
+    // serve the application, with the peer address for per IP rate limits
+    let service = router.into_make_service_with_connect_info::<SocketAddr>();
+    Ok(axum::serve(listener, service)
         .with_graceful_shutdown(async {
             tokio::signal::ctrl_c()
                 .await
@@ -1,8 +1,48 @@
-use axum::extract::FromRequestParts;
+use axum::{extract::FromRequestParts, http::request::Parts};
 use axum_auth::AuthBearer;
 use tracing::warn;
 
-use crate::{config::CLEWDR_CONFIG, error::ClewdrError};
+use crate::{
+    config::{ApiKey, CLEWDR_CONFIG, KeyEndpoint},
+    error::ClewdrError,
+    services::key_usage::KEY_USAGE,
+};
+
+/// Client API key that authenticated the current request
+///
+/// Inserted into the request extensions by the user facing auth guards when a
+/// managed key (rather than the shared password) was presented.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedKey(pub ApiKey);
+
+/// Validates a user key against the shared password and the managed API keys
+///
+/// Managed keys must be enabled, allowed on the requested endpoint and within
+/// their quotas. On success the key is attached to the request extensions.
+fn authorize_user(key: &str, parts: &mut Parts) -> Result<(), ClewdrError> {
+    let config = CLEWDR_CONFIG.load();
+    if config.user_auth(key) {
+        return Ok(());
+    }
+    let Some(api_key) = config.api_keys.iter().find(|k| k.key == key) else {
+        return Err(ClewdrError::InvalidAuth);
+    };
+    if !api_key.enabled {
+        warn!("Disabled API key: {}", api_key.ellipse());
+        return Err(ClewdrError::InvalidAuth);
+    }
+    let endpoint = KeyEndpoint::from_path(parts.uri.path());
+    if !api_key.allows_endpoint(endpoint) {
+        return Err(ClewdrError::Forbidden {
+            msg: format!("key {} may not access this endpoint", api_key.ellipse()),
+        });
+    }
+    KEY_USAGE.check_quota(api_key)?;
+    parts
+        .extensions
+        .insert(AuthenticatedKey(api_key.to_owned()));
+    Ok(())
+}
 
 /// Middleware guard that ensures requests have valid admin authentication
 ///
@@ -70,10 +110,11 @@ where
         let AuthBearer(key) = AuthBearer::from_request_parts(parts, &())
             .await
             .map_err(|_| ClewdrError::InvalidAuth)?;
-        if !CLEWDR_CONFIG.load().user_auth(&key) {
-            warn!("Invalid Bearer key: {}", key);
-            return Err(ClewdrError::InvalidAuth);
-        }
+        authorize_user(&key, parts).inspect_err(|e| {
+            if matches!(e, ClewdrError::InvalidAuth) {
+                warn!("Invalid Bearer key: {}", key);
+            }
+        })?;
         Ok(Self)
     }
 }
@@ -94,17 +135,26 @@ where
         _: &S,
     ) -> Result<Self, Self::Rejection> {
         // Try X-API-Key first
-        if let Some(key) = parts.headers.get("x-api-key").and_then(|v| v.to_str().ok())
-            && CLEWDR_CONFIG.load().user_auth(key)
+        if let Some(key) = parts
+            .headers
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
         {
-            return Ok(Self);
+            match authorize_user(&key, parts) {
+                Ok(()) => return Ok(Self),
+                Err(ClewdrError::InvalidAuth) => {}
+                Err(e) => return Err(e),
+            }
         }
 
         // Fall back to Bearer token
-        if let Ok(AuthBearer(key)) = AuthBearer::from_request_parts(parts, &()).await
-            && CLEWDR_CONFIG.load().user_auth(&key)
-        {
-            return Ok(Self);
+        if let Ok(AuthBearer(key)) = AuthBearer::from_request_parts(parts, &()).await {
+            match authorize_user(&key, parts) {
+                Ok(()) => return Ok(Self),
+                Err(ClewdrError::InvalidAuth) => {}
+                Err(e) => return Err(e),
+            }
         }
 
         warn!("No valid authentication found (tried x-api-key and Bearer)");
@@ -1,69 +1,307 @@
+use std::collections::HashMap;
+
 use axum::response::sse::Event;
-use futures::{Stream, TryStreamExt};
+use futures::{Stream, StreamExt, TryStreamExt, future, stream};
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{Value, json};
 
-use crate::types::claude::{ContentBlockDelta, CreateMessageResponse, StreamEvent};
+use crate::types::claude::{
+    ContentBlock, ContentBlockDelta, CreateMessageResponse, StopReason, StreamEvent, Usage,
+};
 
-/// Represents the data structure for streaming events in OpenAI API format
-/// Contains a choices array with deltas of content
+/// A `chat.completion.chunk` of an OpenAI stream
 #[derive(Debug, Serialize)]
-struct StreamEventData {
-    choices: Vec<StreamEventDelta>,
+struct StreamChunk<'a> {
+    id: &'a str,
+    object: &'static str,
+    created: u64,
+    model: &'a str,
+    choices: Vec<StreamChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Value>,
 }
 
-impl StreamEventData {
-    /// Creates a new StreamEventData with the given content
-    ///
-    /// # Arguments
-    /// * `content` - The event content to include
-    ///
-    /// # Returns
-    /// A new StreamEventData instance with the content wrapped in choices array
-    fn new(content: EventContent) -> Self {
-        Self {
-            choices: vec![StreamEventDelta { delta: content }],
-        }
-    }
-}
-
-/// Represents a delta update in a streaming response
-/// Contains the content change for the current chunk
+/// The only choice of a stream chunk, with the content change of the chunk
 #[derive(Debug, Serialize)]
-struct StreamEventDelta {
+struct StreamChoice {
+    index: usize,
     delta: EventContent,
+    finish_reason: Option<&'static str>,
 }
 
-/// Content of an event, either regular content or reasoning (thinking mode)
+/// Content of an event, either the role of the message, regular content, reasoning
+/// (thinking mode), tool calls or nothing for the finish chunk
 /// Uses untagged enum to handle different response formats
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum EventContent {
+    Role { role: &'static str, content: String },
     Content { content: String },
     Reasoning { reasoning_content: String },
+    ToolCalls { tool_calls: Vec<ToolCallDelta> },
+    Empty {},
 }
 
-/// Creates an SSE event with the given content in OpenAI format
+/// Incremental update of a single tool call in an OpenAI stream chunk
 ///
-/// # Arguments
-/// * `content` - The event content to include
+/// The first chunk of a call carries its id, type and function name, later chunks
+/// only append to the JSON encoded arguments.
+#[derive(Debug, Serialize)]
+pub struct ToolCallDelta {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    type_: Option<&'static str>,
+    function: FunctionDelta,
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    arguments: String,
+}
+
+/// Maps a Claude stop reason onto an OpenAI finish reason
+fn finish_reason(stop_reason: Option<&StopReason>) -> &'static str {
+    match stop_reason {
+        Some(StopReason::EndTurn) => "stop",
+        Some(StopReason::MaxTokens) => "length",
+        Some(StopReason::StopSequence) => "stop",
+        Some(StopReason::ToolUse) => "tool_calls",
+        Some(StopReason::PauseTurn) => "stop",
+        Some(StopReason::Refusal) => "content_filter",
+        Some(StopReason::ModelContextWindowExceeded) => "length",
+        None => "stop",
+    }
+}
+
+/// Error body in the OpenAI format
 ///
-/// # Returns
-/// A formatted SSE Event ready to be sent to the client
-pub fn build_event(content: EventContent) -> Event {
-    let event = Event::default();
-    let data = StreamEventData::new(content);
-    event.json_data(data).unwrap()
+/// The OpenAI `type` and `code` follow the Claude error type, or else the HTTP status of
+/// the error, which stream errors do not have.
+pub fn oai_error(status: Option<u16>, r#type: &str, message: &Value) -> Value {
+    let (type_, code) = match (r#type, status) {
+        ("quota_exceeded", _) => ("insufficient_quota", Some("insufficient_quota")),
+        ("authentication_error" | "invalid_auth", _) | (_, Some(401)) => {
+            ("invalid_request_error", Some("invalid_api_key"))
+        }
+        ("permission_error" | "forbidden", _) | (_, Some(403)) => {
+            ("invalid_request_error", Some("permission_denied"))
+        }
+        ("not_found_error", _) | (_, Some(404)) => ("invalid_request_error", Some("not_found")),
+        ("rate_limit_error", _) | (_, Some(429)) => ("requests", Some("rate_limit_exceeded")),
+        ("overloaded_error", _) | (_, Some(503 | 529)) => ("server_error", Some("overloaded")),
+        ("invalid_request_error", _) | (_, Some(400..500)) => ("invalid_request_error", None),
+        _ => ("server_error", None),
+    };
+    let message = match message {
+        Value::String(message) => message.to_owned(),
+        message => message.to_string(),
+    };
+    json!({
+        "error": {
+            "message": message,
+            "type": type_,
+            "param": null,
+            "code": code,
+        }
+    })
+}
+
+/// Converts the events of a Claude stream into the data of OpenAI stream chunks
+///
+/// Every chunk carries the id and model of the message from `message_start`. OpenAI
+/// numbers tool calls separately from other content, so Claude block indices are mapped
+/// onto consecutive tool call indices.
+struct StreamState {
+    /// Whether a final chunk with the token usage is sent before `[DONE]`
+    include_usage: bool,
+    id: String,
+    model: String,
+    created: u64,
+    /// Usage reported by Claude, or estimated from the request until it is
+    usage: Usage,
+    tool_calls: HashMap<usize, usize>,
+    /// Whether the stream was ended, by `[DONE]` or an error
+    done: bool,
+}
+
+impl StreamState {
+    fn new(include_usage: bool, usage: Usage) -> Self {
+        Self {
+            include_usage,
+            id: String::new(),
+            model: String::new(),
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            usage,
+            tool_calls: HashMap::new(),
+            done: false,
+        }
+    }
+
+    fn chunk(&self, delta: EventContent, finish_reason: Option<&'static str>) -> String {
+        let chunk = StreamChunk {
+            id: &self.id,
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: &self.model,
+            choices: vec![StreamChoice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+            usage: None,
+        };
+        serde_json::to_string(&chunk).unwrap()
+    }
+
+    fn convert(&mut self, event: StreamEvent) -> Vec<String> {
+        // nothing may follow `[DONE]` or an error
+        if self.done {
+            return vec![];
+        }
+        let delta = match event {
+            StreamEvent::MessageStart { message } => {
+                self.id = message.id;
+                self.model = message.model;
+                if let Some(usage) = message.usage
+                    && usage.input_tokens > 0
+                {
+                    self.usage.input_tokens = usage.input_tokens;
+                }
+                Some(EventContent::Role {
+                    role: "assistant",
+                    content: String::new(),
+                })
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: ContentBlock::ToolUse { id, name, .. },
+            } => {
+                let call_index = self.tool_calls.len();
+                self.tool_calls.insert(index, call_index);
+                Some(EventContent::ToolCalls {
+                    tool_calls: vec![ToolCallDelta {
+                        index: call_index,
+                        id: Some(id),
+                        type_: Some("function"),
+                        function: FunctionDelta {
+                            name: Some(name),
+                            arguments: String::new(),
+                        },
+                    }],
+                })
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                ContentBlockDelta::TextDelta { text } => {
+                    Some(EventContent::Content { content: text })
+                }
+                ContentBlockDelta::ThinkingDelta { thinking } => Some(EventContent::Reasoning {
+                    reasoning_content: thinking,
+                }),
+                ContentBlockDelta::InputJsonDelta { partial_json } => self
+                    .tool_calls
+                    .get(&index)
+                    .map(|&call_index| EventContent::ToolCalls {
+                        tool_calls: vec![ToolCallDelta {
+                            index: call_index,
+                            id: None,
+                            type_: None,
+                            function: FunctionDelta {
+                                name: None,
+                                arguments: partial_json,
+                            },
+                        }],
+                    }),
+                _ => None,
+            },
+            StreamEvent::MessageDelta { delta, usage } => {
+                if let Some(usage) = usage {
+                    // output tokens are cumulative
+                    self.usage.output_tokens = usage.output_tokens;
+                    if usage.input_tokens > 0 {
+                        self.usage.input_tokens = usage.input_tokens;
+                    }
+                }
+                let Some(reason) = delta.stop_reason else {
+                    return vec![];
+                };
+                return vec![
+                    self.chunk(EventContent::Empty {}, Some(finish_reason(Some(&reason)))),
+                ];
+            }
+            StreamEvent::MessageStop => return self.finish(),
+            StreamEvent::Error { error } => {
+                // no usage chunk nor `[DONE]`, so clients do not take it for a normal end
+                self.done = true;
+                let error = oai_error(None, &error.type_, &json!(error.message));
+                return vec![error.to_string()];
+            }
+            _ => None,
+        };
+        delta.map(|d| self.chunk(d, None)).into_iter().collect()
+    }
+
+    /// Sends the usage chunk when requested, then `[DONE]`, only once
+    fn finish(&mut self) -> Vec<String> {
+        if std::mem::replace(&mut self.done, true) {
+            return vec![];
+        }
+        let mut data = vec![];
+        if self.include_usage {
+            let chunk = StreamChunk {
+                id: &self.id,
+                object: "chat.completion.chunk",
+                created: self.created,
+                model: &self.model,
+                choices: vec![],
+                usage: Some(json!({
+                    "prompt_tokens": self.usage.input_tokens,
+                    "completion_tokens": self.usage.output_tokens,
+                    "total_tokens": self.usage.input_tokens + self.usage.output_tokens
+                })),
+            };
+            data.push(serde_json::to_string(&chunk).unwrap());
+        }
+        data.push("[DONE]".to_string());
+        data
+    }
+
+    /// Ends the stream when Claude closes it, with an error if `message_stop` never came
+    ///
+    /// A stream cut short is not ended with `[DONE]`, so clients do not take a truncated
+    /// answer for a complete one.
+    fn end(&mut self) -> Vec<String> {
+        if std::mem::replace(&mut self.done, true) {
+            return vec![];
+        }
+        let error = oai_error(
+            None,
+            "api_error",
+            &json!("Claude closed the stream before message_stop"),
+        );
+        vec![error.to_string()]
+    }
 }
 
 /// Transforms a Claude.ai event stream into an OpenAI-compatible event stream
 ///
 /// Extracts content from Claude events and reformats them to match OpenAI's streaming format.
-/// This function processes each event in the stream, identifying the delta content type
-/// (text or thinking), and converting it to the appropriate OpenAI-compatible event format.
+/// The stream opens with a role chunk, carries a chunk per delta of text, thinking or tool
+/// call arguments, and a finish chunk from the stop reason of `message_delta`. It ends with
+/// the usage chunk when requested and `[DONE]` on `message_stop`.
+/// An upstream error, or Claude closing the stream without `message_stop`, is forwarded as
+/// an OpenAI error object and ends the stream instead.
 ///
 /// # Arguments
 /// * `s` - The input stream of Claude.ai events
+/// * `include_usage` - Whether the client asked for `stream_options.include_usage`
+/// * `usage` - Usage estimated from the request, used when Claude reports none
 ///
 /// # Returns
 /// A stream of OpenAI-compatible SSE events
@@ -71,29 +309,29 @@ pub fn build_event(content: EventContent) -> Event {
 /// # Type Parameters
 /// * `I` - The input stream type
 /// * `E` - The error type for the stream
-pub fn transform_stream<I, E>(s: I) -> impl Stream<Item = Result<Event, E>>
+pub fn transform_stream<I, E>(
+    s: I,
+    include_usage: bool,
+    usage: Usage,
+) -> impl Stream<Item = Result<Event, E>>
 where
     I: Stream<Item = Result<eventsource_stream::Event, E>>,
 {
-    s.try_filter_map(async |eventsource_stream::Event { data, .. }| {
-        let Ok(parsed) = serde_json::from_str::<StreamEvent>(&data) else {
-            return Ok(None);
-        };
-        let StreamEvent::ContentBlockDelta { delta, .. } = parsed else {
-            return Ok(None);
-        };
-        match delta {
-            ContentBlockDelta::TextDelta { text } => {
-                Ok(Some(build_event(EventContent::Content { content: text })))
-            }
-            ContentBlockDelta::ThinkingDelta { thinking } => {
-                Ok(Some(build_event(EventContent::Reasoning {
-                    reasoning_content: thinking,
-                })))
-            }
-            _ => Ok(None),
-        }
-    })
+    let mut state = StreamState::new(include_usage, usage);
+    s.map_ok(Some)
+        .chain(stream::once(future::ready(Ok(None))))
+        .map_ok(move |event| {
+            let data = match event {
+                Some(eventsource_stream::Event { data, .. }) => {
+                    serde_json::from_str::<StreamEvent>(&data)
+                        .map(|parsed| state.convert(parsed))
+                        .unwrap_or_default()
+                }
+                None => state.end(),
+            };
+            stream::iter(data.into_iter().map(|d| Ok(Event::default().data(d))))
+        })
+        .try_flatten()
 }
 
 pub fn transforms_json(input: CreateMessageResponse) -> Value {
@@ -101,11 +339,29 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
         .content
         .iter()
         .filter_map(|block| match block {
-            crate::types::claude::ContentBlock::Text { text, .. } => Some(text.clone()),
+            ContentBlock::Text { text, .. } => Some(text.clone()),
             _ => None,
         })
         .collect::<String>();
 
+    let tool_calls = input
+        .content
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::ToolUse {
+                id, name, input, ..
+            } => Some(serde_json::json!({
+                "id": id,
+                "type": "function",
+                "function": {
+                    "name": name,
+                    "arguments": input.to_string()
+                }
+            })),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
     let usage = input.usage.as_ref().map(|u| {
         serde_json::json!({
             "prompt_tokens": u.input_tokens,
@@ -114,16 +370,18 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
         })
     });
 
-    let finish_reason = match input.stop_reason {
-        Some(crate::types::claude::StopReason::EndTurn) => "stop",
-        Some(crate::types::claude::StopReason::MaxTokens) => "length",
-        Some(crate::types::claude::StopReason::StopSequence) => "stop",
-        Some(crate::types::claude::StopReason::ToolUse) => "tool_calls",
-        Some(crate::types::claude::StopReason::PauseTurn) => "stop",
-        Some(crate::types::claude::StopReason::Refusal) => "content_filter",
-        Some(crate::types::claude::StopReason::ModelContextWindowExceeded) => "length",
-        None => "stop",
-    };
+    let finish_reason = finish_reason(input.stop_reason.as_ref());
+
+    let mut message = serde_json::json!({
+        "role": "assistant",
+        "content": content
+    });
+    if !tool_calls.is_empty() {
+        if content.is_empty() {
+            message["content"] = Value::Null;
+        }
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
 
     serde_json::json!({
         "id": input.id,
@@ -135,12 +393,183 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
         "model": input.model,
         "choices": [{
             "index": 0,
-            "message": {
-                "role": "assistant",
-                "content": content
-            },
+            "message": message,
             "finish_reason": finish_reason
         }],
         "usage": usage
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(data: &[Value]) -> Vec<StreamEvent> {
+        data.iter()
+            .map(|d| serde_json::from_value(d.to_owned()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn errors_follow_the_openai_schema() {
+        let error = oai_error(
+            Some(429),
+            "quota_exceeded",
+            &json!("Quota exceeded: tokens"),
+        );
+        assert_eq!(error["error"]["type"], "insufficient_quota");
+        assert_eq!(error["error"]["message"], "Quota exceeded: tokens");
+        let error = oai_error(Some(401), "invalid_auth", &json!("Key/Password Invalid"));
+        assert_eq!(error["error"]["code"], "invalid_api_key");
+        let error = oai_error(Some(429), "rate_limit_error", &json!({"resetsAt": 1}));
+        assert_eq!(error["error"]["code"], "rate_limit_exceeded");
+        assert_eq!(error["error"]["message"], r#"{"resetsAt":1}"#);
+
+        let mut state = StreamState::new(true, Usage::default());
+        let error = events(&[json!({"type": "error",
+            "error": {"type": "overloaded_error", "message": "Overloaded"}})]);
+        let chunks = error
+            .into_iter()
+            .flat_map(|e| state.convert(e))
+            .collect::<Vec<_>>();
+        let error: Value = serde_json::from_str(&chunks[0]).unwrap();
+        assert_eq!(error["error"]["code"], "overloaded");
+        assert!(state.end().is_empty());
+    }
+
+    #[test]
+    fn stream_chunks_follow_the_openai_spec() {
+        let mut state = StreamState::new(true, Usage::default());
+        let chunks = events(&[
+            json!({"type": "message_start", "message": {
+                "id": "msg_1", "type": "message", "role": "assistant", "content": [],
+                "model": "claude-sonnet-4-5", "stop_reason": null, "stop_sequence": null,
+                "usage": {"input_tokens": 12, "output_tokens": 1}
+            }}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Hi"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "max_tokens"},
+                "usage": {"output_tokens": 5}}),
+            json!({"type": "message_stop"}),
+        ])
+        .into_iter()
+        .flat_map(|e| state.convert(e))
+        .collect::<Vec<_>>();
+        assert!(state.end().is_empty());
+
+        let [role, content, finish, usage, done] = &chunks[..] else {
+            panic!("unexpected chunks: {chunks:?}");
+        };
+        let role: Value = serde_json::from_str(role).unwrap();
+        assert_eq!(role["id"], "msg_1");
+        assert_eq!(role["object"], "chat.completion.chunk");
+        assert_eq!(role["model"], "claude-sonnet-4-5");
+        assert_eq!(role["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(role["choices"][0]["finish_reason"], Value::Null);
+        let content: Value = serde_json::from_str(content).unwrap();
+        assert_eq!(content["choices"][0]["delta"]["content"], "Hi");
+        let finish: Value = serde_json::from_str(finish).unwrap();
+        assert_eq!(finish["choices"][0]["delta"], json!({}));
+        assert_eq!(finish["choices"][0]["finish_reason"], "length");
+        let usage: Value = serde_json::from_str(usage).unwrap();
+        assert_eq!(usage["choices"], json!([]));
+        assert_eq!(usage["usage"]["total_tokens"], 17);
+        assert_eq!(done, "[DONE]");
+    }
+
+    #[test]
+    fn streamed_tool_calls_follow_the_openai_spec() {
+        let mut state = StreamState::new(false, Usage::default());
+        let chunks = events(&[
+            json!({"type": "message_start", "message": {
+                "id": "msg_2", "type": "message", "role": "assistant", "content": [],
+                "model": "claude-sonnet-4-5", "stop_reason": null, "stop_sequence": null,
+                "usage": {"input_tokens": 20, "output_tokens": 1}
+            }}),
+            json!({"type": "content_block_start", "index": 0,
+                "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Let me check."}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {
+                "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}
+            }}),
+            json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"city\": "}}),
+            json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "\"Paris\"}"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"},
+                "usage": {"output_tokens": 15}}),
+            json!({"type": "message_stop"}),
+        ])
+        .into_iter()
+        .flat_map(|e| state.convert(e))
+        .collect::<Vec<_>>();
+
+        let [_role, text, call, first, second, finish, done] = &chunks[..] else {
+            panic!("unexpected chunks: {chunks:?}");
+        };
+        let text: Value = serde_json::from_str(text).unwrap();
+        assert_eq!(text["choices"][0]["delta"]["content"], "Let me check.");
+        // block 1 of Claude is the first tool call of OpenAI
+        let call: Value = serde_json::from_str(call).unwrap();
+        assert_eq!(
+            call["choices"][0]["delta"]["tool_calls"],
+            json!([{"index": 0, "id": "toolu_1", "type": "function",
+                "function": {"name": "get_weather", "arguments": ""}}])
+        );
+        let mut arguments = String::new();
+        for chunk in [first, second] {
+            let chunk: Value = serde_json::from_str(chunk).unwrap();
+            let call = &chunk["choices"][0]["delta"]["tool_calls"][0];
+            assert_eq!(call["index"], 0);
+            assert!(call.get("id").is_none());
+            assert!(call["function"].get("name").is_none());
+            arguments.push_str(call["function"]["arguments"].as_str().unwrap());
+        }
+        assert_eq!(arguments, r#"{"city": "Paris"}"#);
+        let finish: Value = serde_json::from_str(finish).unwrap();
+        assert_eq!(finish["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(done, "[DONE]");
+    }
+
+    #[test]
+    fn nothing_follows_an_error() {
+        let mut state = StreamState::new(true, Usage::default());
+        let chunks = events(&[
+            json!({"type": "error",
+                "error": {"type": "overloaded_error", "message": "Overloaded"}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Hi"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"},
+                "usage": {"output_tokens": 5}}),
+            json!({"type": "message_stop"}),
+        ])
+        .into_iter()
+        .flat_map(|e| state.convert(e))
+        .collect::<Vec<_>>();
+        assert!(state.end().is_empty());
+
+        let [error] = &chunks[..] else {
+            panic!("unexpected chunks: {chunks:?}");
+        };
+        let error: Value = serde_json::from_str(error).unwrap();
+        assert_eq!(error["error"]["code"], "overloaded");
+    }
+
+    #[test]
+    fn truncated_streams_end_with_an_error() {
+        let mut state = StreamState::new(true, Usage::default());
+        let mut chunks = events(&[json!({"type": "content_block_delta", "index": 0,
+            "delta": {"type": "text_delta", "text": "Hi"}})])
+        .into_iter()
+        .flat_map(|e| state.convert(e))
+        .collect::<Vec<_>>();
+        chunks.extend(state.end());
+        assert!(!chunks.iter().any(|c| c == "[DONE]"));
+        let error: Value = serde_json::from_str(chunks.last().unwrap()).unwrap();
+        assert_eq!(error["error"]["type"], "server_error");
+        assert!(state.end().is_empty());
+    }
+}
@@ -9,7 +9,7 @@ pub use response::*;
 pub use stop_sequences::*;
 use strum::Display;
 
-use crate::types::claude::Usage;
+use crate::types::claude::{CreateMessageParams, Usage};
 
 /// Represents the format of the API response
 ///
@@ -28,6 +28,7 @@ pub enum ClaudeApiFormat {
 pub enum ClaudeContext {
     Web(ClaudeWebContext),
     Code(ClaudeCodeContext),
+    Gemini(GeminiContext),
 }
 
 impl ClaudeContext {
@@ -35,6 +36,7 @@ impl ClaudeContext {
         match self {
             ClaudeContext::Web(ctx) => ctx.stream,
             ClaudeContext::Code(ctx) => ctx.stream,
+            ClaudeContext::Gemini(ctx) => ctx.stream,
         }
     }
 
@@ -42,6 +44,15 @@ impl ClaudeContext {
         match self {
             ClaudeContext::Web(ctx) => ctx.api_format,
             ClaudeContext::Code(ctx) => ctx.api_format,
+            ClaudeContext::Gemini(ctx) => ctx.api_format,
+        }
+    }
+
+    pub fn include_usage(&self) -> bool {
+        match self {
+            ClaudeContext::Web(ctx) => ctx.include_usage,
+            ClaudeContext::Code(ctx) => ctx.include_usage,
+            ClaudeContext::Gemini(ctx) => ctx.include_usage,
         }
     }
 
@@ -56,13 +67,13 @@ impl ClaudeContext {
     pub fn stop_sequences(&self) -> &[String] {
         match self {
             ClaudeContext::Web(ctx) => &ctx.stop_sequences,
-            ClaudeContext::Code(_) => &[],
+            ClaudeContext::Code(_) | ClaudeContext::Gemini(_) => &[],
         }
     }
 
     pub fn system_prompt_hash(&self) -> Option<u64> {
         match self {
-            ClaudeContext::Web(_) => None,
+            ClaudeContext::Web(_) | ClaudeContext::Gemini(_) => None,
             ClaudeContext::Code(ctx) => ctx.system_prompt_hash,
         }
     }
@@ -71,13 +82,46 @@ impl ClaudeContext {
         match self {
             ClaudeContext::Web(ctx) => &ctx.usage,
             ClaudeContext::Code(ctx) => &ctx.usage,
+            ClaudeContext::Gemini(ctx) => &ctx.usage,
+        }
+    }
+
+    pub fn api_key(&self) -> Option<&str> {
+        match self {
+            ClaudeContext::Web(ctx) => ctx.api_key.as_deref(),
+            ClaudeContext::Code(ctx) => ctx.api_key.as_deref(),
+            ClaudeContext::Gemini(ctx) => ctx.api_key.as_deref(),
+        }
+    }
+
+    pub fn cookie_group(&self) -> Option<&str> {
+        match self {
+            ClaudeContext::Web(ctx) => ctx.cookie_group.as_deref(),
+            ClaudeContext::Code(ctx) => ctx.cookie_group.as_deref(),
+            ClaudeContext::Gemini(_) => None,
         }
     }
 
     pub fn anthropic_beta(&self) -> Option<&str> {
         match self {
-            ClaudeContext::Web(_) => None,
+            ClaudeContext::Web(_) | ClaudeContext::Gemini(_) => None,
             ClaudeContext::Code(ctx) => ctx.anthropic_beta.as_deref(),
         }
     }
+
+    /// Context for serving the same request through Claude Web
+    pub fn to_web(&self, params: &CreateMessageParams) -> Self {
+        if self.is_web() {
+            return self.to_owned();
+        }
+        ClaudeContext::Web(ClaudeWebContext {
+            stream: self.is_stream(),
+            api_format: self.api_format(),
+            include_usage: self.include_usage(),
+            stop_sequences: params.stop_sequences.to_owned().unwrap_or_default(),
+            usage: self.usage().to_owned(),
+            api_key: self.api_key().map(str::to_string),
+            cookie_group: self.cookie_group().map(str::to_string),
+        })
+    }
 }
@@ -15,7 +15,11 @@ use serde_json::{Value, json};
 use crate::{
     config::CLEWDR_CONFIG,
     error::ClewdrError,
-    middleware::claude::{ClaudeApiFormat, ClaudeContext},
+    middleware::{
+        AuthenticatedKey,
+        claude::{ClaudeApiFormat, ClaudeContext},
+    },
+    services::{key_usage::KEY_USAGE, rate_limit::TokenCharge},
     types::{
         claude::{
             ContentBlock, CreateMessageParams, Message, MessageContent, Role, Thinking, Usage,
@@ -51,12 +55,21 @@ pub struct ClaudeWebContext {
     pub(super) stream: bool,
     /// The API format being used (Claude or OpenAI)
     pub(super) api_format: ClaudeApiFormat,
+    /// Whether an OpenAI stream ends with a usage chunk
+    pub(super) include_usage: bool,
     /// The stop sequence used for the request
     pub(super) stop_sequences: Vec<String>,
     /// User information about input and output tokens
     pub(super) usage: Usage,
+    /// Client API key the request was authenticated with
+    pub(super) api_key: Option<String>,
+    /// Cookie group the request is bound to
+    pub(super) cookie_group: Option<String>,
 }
 
+/// Header binding a request to a cookie group, unless its API key is bound to one
+pub const COOKIE_GROUP_HEADER: &str = "x-clewdr-cookie-group";
+
 /// Predefined test message in Claude format for connection testing
 ///
 /// This is a standard test message sent by clients like SillyTavern
@@ -68,7 +81,19 @@ static TEST_MESSAGE_CLAUDE: LazyLock<Message> =
 /// Predefined test message in OpenAI format for connection testing
 static TEST_MESSAGE_OAI: LazyLock<Message> = LazyLock::new(|| Message::new_text(Role::User, "Hi"));
 
-struct NormalizeRequest(CreateMessageParams, ClaudeApiFormat);
+/// Request body in Claude format, with what the preprocessors need from the request
+struct NormalizeRequest {
+    body: CreateMessageParams,
+    format: ClaudeApiFormat,
+    /// Client API key the request was authenticated with
+    api_key: Option<String>,
+    /// Cookie group the request is bound to
+    cookie_group: Option<String>,
+    /// Whether an OpenAI stream ends with a usage chunk
+    include_usage: bool,
+    /// Rate limit charge of the request, `None` for token counting
+    charge: Option<TokenCharge>,
+}
 
 fn drop_empty_system(body: &mut CreateMessageParams) {
     let Some(system) = body.system.take() else {
@@ -191,17 +216,45 @@ where
 
     async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
         let uri = req.uri().to_string();
+        let api_key = req
+            .extensions()
+            .get::<AuthenticatedKey>()
+            .map(|k| k.0.to_owned());
+        // token counting requests are not charged, like their usage is not recorded
+        let charge = req
+            .extensions()
+            .get::<TokenCharge>()
+            .filter(|_| !uri.contains("count_tokens"))
+            .cloned();
+        let cookie_group = api_key
+            .as_ref()
+            .and_then(|k| k.cookie_group.to_owned())
+            .or_else(|| {
+                req.headers()
+                    .get(COOKIE_GROUP_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::trim)
+                    .filter(|g| !g.is_empty())
+                    .map(str::to_string)
+            });
         let format = if uri.contains("chat/completions") {
             ClaudeApiFormat::OpenAI
         } else {
             ClaudeApiFormat::Claude
         };
-        let Json(mut body) = match format {
+        let (Json(mut body), include_usage) = match format {
             ClaudeApiFormat::OpenAI => {
                 let Json(json) = Json::<OaiCreateMessageParams>::from_request(req, &()).await?;
-                Json(json.into())
+                let include_usage = json
+                    .stream_options
+                    .as_ref()
+                    .is_some_and(|o| o.include_usage);
+                (Json(json.into()), include_usage)
             }
-            ClaudeApiFormat::Claude => Json::<CreateMessageParams>::from_request(req, &()).await?,
+            ClaudeApiFormat::Claude => (
+                Json::<CreateMessageParams>::from_request(req, &()).await?,
+                false,
+            ),
         };
         if CLEWDR_CONFIG.load().sanitize_messages {
             // Trim whitespace and drop empty assistant turns when enabled.
@@ -212,7 +265,37 @@ where
             body.thinking.get_or_insert(Thinking::new(4096));
         }
         drop_empty_system(&mut body);
-        Ok(Self(body, format))
+        if let Some(ref key) = api_key {
+            let model = body.model.trim_end_matches("-1M");
+            if !key.allows_model(model) {
+                return Err(ClewdrError::Forbidden {
+                    msg: format!("key {} may not use model {}", key.ellipse(), model),
+                });
+            }
+        }
+
+        // Check for test messages and respond appropriately
+        if !body.stream.unwrap_or_default()
+            && (body.messages == vec![TEST_MESSAGE_CLAUDE.to_owned()]
+                || body.messages == vec![TEST_MESSAGE_OAI.to_owned()])
+        {
+            // Respond with a test message, without using the quota of the key
+            return Err(ClewdrError::TestMessage);
+        }
+
+        if let Some(ref key) = api_key
+            && !uri.contains("count_tokens")
+        {
+            KEY_USAGE.reserve_request(key)?;
+        }
+        Ok(Self {
+            body,
+            format,
+            api_key: api_key.map(|k| k.key),
+            cookie_group,
+            include_usage,
+            charge,
+        })
     }
 }
 
@@ -223,29 +306,33 @@ where
     type Rejection = ClewdrError;
 
     async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
-        let NormalizeRequest(body, format) = NormalizeRequest::from_request(req, &()).await?;
-
-        // Check for test messages and respond appropriately
-        if !body.stream.unwrap_or_default()
-            && (body.messages == vec![TEST_MESSAGE_CLAUDE.to_owned()]
-                || body.messages == vec![TEST_MESSAGE_OAI.to_owned()])
-        {
-            // Respond with a test message
-            return Err(ClewdrError::TestMessage);
-        }
+        let NormalizeRequest {
+            body,
+            format,
+            api_key,
+            cookie_group,
+            include_usage,
+            charge,
+        } = NormalizeRequest::from_request(req, &()).await?;
 
         // Determine streaming status and API format
         let stream = body.stream.unwrap_or_default();
 
         let input_tokens = body.count_tokens();
+        if let Some(charge) = charge {
+            charge.charge_input(input_tokens as u64);
+        }
         let info = ClaudeWebContext {
             stream,
             api_format: format,
+            include_usage,
             stop_sequences: body.stop_sequences.to_owned().unwrap_or_default(),
             usage: Usage {
                 input_tokens,
                 output_tokens: 0, // Placeholder for output token count
             },
+            api_key,
+            cookie_group,
         };
 
         Ok(Self(body, ClaudeContext::Web(info)))
@@ -258,12 +345,18 @@ pub struct ClaudeCodeContext {
     pub(super) stream: bool,
     /// The API format being used (Claude or OpenAI)
     pub(super) api_format: ClaudeApiFormat,
+    /// Whether an OpenAI stream ends with a usage chunk
+    pub(super) include_usage: bool,
     /// The hash of the system messages for caching purposes
     pub(super) system_prompt_hash: Option<u64>,
     /// Optional anthropic-beta header forwarded from client request
     pub(super) anthropic_beta: Option<String>,
     // Usage information for the request
     pub(super) usage: Usage,
+    /// Client API key the request was authenticated with
+    pub(super) api_key: Option<String>,
+    /// Cookie group the request is bound to
+    pub(super) cookie_group: Option<String>,
 }
 
 pub struct ClaudeCodePreprocess(pub CreateMessageParams, pub ClaudeContext);
@@ -276,22 +369,20 @@ where
 
     async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
         let anthropic_beta = extract_anthropic_beta_header(req.headers());
-        let NormalizeRequest(mut body, format) = NormalizeRequest::from_request(req, &()).await?;
+        let NormalizeRequest {
+            mut body,
+            format,
+            api_key,
+            cookie_group,
+            include_usage,
+            charge,
+        } = NormalizeRequest::from_request(req, &()).await?;
         // Handle thinking mode by modifying the model name
         if  body.temperature.is_some()
         {
             body.top_p = None; // temperature and top_p cannot be used together in Opus-4.x
         }
 
-        // Check for test messages and respond appropriately
-        if !body.stream.unwrap_or_default()
-            && (body.messages == vec![TEST_MESSAGE_CLAUDE.to_owned()]
-                || body.messages == vec![TEST_MESSAGE_OAI.to_owned()])
-        {
-            // Respond with a test message
-            return Err(ClewdrError::TestMessage);
-        }
-
         // Determine streaming status and API format
         let stream = body.stream.unwrap_or_default();
 
@@ -338,18 +429,80 @@ where
         });
 
         let input_tokens = body.count_tokens();
+        if let Some(charge) = charge {
+            charge.charge_input(input_tokens as u64);
+        }
 
         let info = ClaudeCodeContext {
             stream,
             api_format: format,
+            include_usage,
             system_prompt_hash,
             anthropic_beta,
             usage: Usage {
                 input_tokens,
                 output_tokens: 0, // Placeholder for output token count
             },
+            api_key,
+            cookie_group,
         };
 
         Ok(Self(body, ClaudeContext::Code(info)))
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct GeminiContext {
+    /// Whether the response should be streamed
+    pub(super) stream: bool,
+    /// The API format being used (Claude or OpenAI)
+    pub(super) api_format: ClaudeApiFormat,
+    /// Whether an OpenAI stream ends with a usage chunk
+    pub(super) include_usage: bool,
+    // Usage information for the request
+    pub(super) usage: Usage,
+    /// Client API key the request was authenticated with
+    pub(super) api_key: Option<String>,
+}
+
+/// Extractor for requests forwarded to the Gemini API
+///
+/// Accepts the same Claude and OpenAI request bodies as the other backends,
+/// translation to the Gemini format happens in the provider.
+pub struct GeminiPreprocess(pub CreateMessageParams, pub ClaudeContext);
+
+impl<S> FromRequest<S> for GeminiPreprocess
+where
+    S: Send + Sync,
+{
+    type Rejection = ClewdrError;
+
+    async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
+        let NormalizeRequest {
+            body,
+            format,
+            api_key,
+            include_usage,
+            charge,
+            ..
+        } = NormalizeRequest::from_request(req, &()).await?;
+
+        let stream = body.stream.unwrap_or_default();
+        let input_tokens = body.count_tokens();
+        if let Some(charge) = charge {
+            charge.charge_input(input_tokens as u64);
+        }
+        let info = GeminiContext {
+            stream,
+            api_format: format,
+            include_usage,
+            usage: Usage {
+                input_tokens,
+                output_tokens: 0, // Placeholder for output token count
+            },
+            api_key,
+        };
+
+        Ok(Self(body, ClaudeContext::Gemini(info)))
+    }
+}
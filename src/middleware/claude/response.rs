@@ -5,12 +5,13 @@ use axum::{
 };
 use eventsource_stream::Eventsource;
 use futures::TryStreamExt;
-use http::header::CONTENT_TYPE;
+use http::{HeaderMap, header::CONTENT_TYPE};
 use tracing::warn;
 
 use super::{ClaudeApiFormat, transform_stream};
 use crate::{
-    middleware::claude::{ClaudeContext, transforms_json},
+    error::ClaudeErrorBody,
+    middleware::claude::{ClaudeContext, oai_error, transforms_json},
     types::claude::{CreateMessageResponse, StreamEvent},
 };
 
@@ -33,6 +34,31 @@ where
     Ok(parsed)
 }
 
+/// Copies the `x-clewdr-*` headers of a response over to the one rebuilt from it
+pub(super) fn relay_clewdr_headers(from: &HeaderMap, mut to: Response) -> Response {
+    for (name, value) in from {
+        if name.as_str().starts_with("x-clewdr-") {
+            to.headers_mut().insert(name, value.to_owned());
+        }
+    }
+    to
+}
+
+/// Renders the errors of ClewdR in the OpenAI error format
+///
+/// Must be the outermost layer of an OpenAI route, so the rejections of the auth and
+/// request extractors are rendered too. Responses already carrying an OpenAI error, like
+/// the ones of the rate limiter, have no Claude error attached and are left as they are.
+pub async fn to_oai_error(resp: Response) -> Response {
+    let Some(error) = resp.extensions().get::<ClaudeErrorBody>() else {
+        return resp;
+    };
+    let status = resp.status();
+    let body = oai_error(Some(status.as_u16()), &error.r#type, &error.message);
+    let headers = resp.headers().to_owned();
+    relay_clewdr_headers(&headers, (status, Json(body)).into_response())
+}
+
 /// Transforms responses to ensure compatibility with the OpenAI API format
 ///
 /// This middleware function analyzes responses and transforms them when necessary
@@ -58,24 +84,35 @@ pub async fn to_oai(resp: Response) -> impl IntoResponse {
     if ClaudeApiFormat::Claude == cx.api_format() {
         return resp;
     }
+    let headers = resp.headers().to_owned();
+    let (include_usage, usage) = (cx.include_usage(), cx.usage().to_owned());
     if !cx.is_stream() {
         match parse_response::<CreateMessageResponse>(resp).await {
-            Ok(response) => return Json(transforms_json(response)).into_response(),
+            Ok(response) => {
+                let resp = Json(transforms_json(response)).into_response();
+                return relay_clewdr_headers(&headers, resp);
+            }
             Err(resp) => return resp,
         }
     }
     let stream = resp.into_body().into_data_stream().eventsource();
-    let stream = transform_stream(stream);
-    Sse::new(stream)
+    let stream = transform_stream(stream, include_usage, usage);
+    let resp = Sse::new(stream)
         .keep_alive(Default::default())
-        .into_response()
+        .into_response();
+    relay_clewdr_headers(&headers, resp)
 }
 
 pub async fn add_usage_info(resp: Response) -> impl IntoResponse {
     let Some(cx) = resp.extensions().get::<ClaudeContext>() else {
         return resp;
     };
+    // only web responses lack usage
+    if !cx.is_web() {
+        return resp;
+    }
     let (mut usage, stream) = (cx.usage().to_owned(), cx.is_stream());
+    let headers = resp.headers().to_owned();
     if !stream {
         let mut response = match parse_response::<CreateMessageResponse>(resp).await {
             Ok(response) => response,
@@ -84,7 +121,7 @@ pub async fn add_usage_info(resp: Response) -> impl IntoResponse {
         let output_tokens = response.count_tokens();
         usage.output_tokens = output_tokens;
         response.usage = Some(usage);
-        return Json(response).into_response();
+        return relay_clewdr_headers(&headers, Json(response).into_response());
     }
     let stream = resp
         .into_body()
@@ -122,9 +159,10 @@ pub async fn add_usage_info(resp: Response) -> impl IntoResponse {
             }
         });
 
-    Sse::new(stream)
+    let resp = Sse::new(stream)
         .keep_alive(Default::default())
-        .into_response()
+        .into_response();
+    relay_clewdr_headers(&headers, resp)
 }
 
 pub async fn check_overloaded(mut resp: Response) -> Response {
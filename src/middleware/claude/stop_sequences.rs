@@ -4,7 +4,7 @@ use eventsource_stream::{Event as SourceEvent, Eventsource};
 use futures::Stream;
 
 use crate::{
-    middleware::claude::ClaudeContext,
+    middleware::claude::{ClaudeContext, response::relay_clewdr_headers},
     types::claude::{ContentBlockDelta, MessageDeltaContent, StopReason, StreamEvent},
 };
 
@@ -93,11 +93,13 @@ pub async fn apply_stop_sequences(resp: Response) -> Response {
         return resp;
     }
 
+    let headers = resp.headers().to_owned();
     let stream = resp.into_body().into_data_stream().eventsource();
     let stream = stop_stream(f.stop_sequences().to_owned(), stream);
-    let mut resp = Sse::new(stream)
+    let resp = Sse::new(stream)
         .keep_alive(Default::default())
         .into_response();
+    let mut resp = relay_clewdr_headers(&headers, resp);
 
     resp.extensions_mut().insert(f);
     resp
@@ -6,7 +6,10 @@
 /// - Authentication: Verify API keys for different authentication methods (admin, OpenAI, Claude)
 /// - Request preprocessing: Normalize requests from different API formats
 /// - Response transformation: Convert between different response formats and handle streaming
+/// - Rate limiting: Enforce request, token and concurrency limits globally and per client
 mod auth;
 pub mod claude;
+mod rate_limit;
 
-pub use auth::{RequireAdminAuth, RequireBearerAuth, RequireFlexibleAuth};
+pub use auth::{AuthenticatedKey, RequireAdminAuth, RequireBearerAuth, RequireFlexibleAuth};
+pub use rate_limit::enforce_rate_limits;
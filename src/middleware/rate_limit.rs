@@ -0,0 +1,147 @@
+use std::net::SocketAddr;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{ConnectInfo, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use http::{StatusCode, header::RETRY_AFTER};
+use serde_json::json;
+use tracing::warn;
+
+use super::{AuthenticatedKey, claude::ClaudeApiFormat};
+use crate::{
+    config::CLEWDR_CONFIG,
+    middleware::claude::ClaudeContext,
+    services::rate_limit::{RATE_LIMITER, RateLimitPermit, RateLimited, RateLimits},
+};
+
+/// Last `output_tokens` count in a line of a Claude response
+fn output_tokens(line: &[u8]) -> Option<u64> {
+    const FIELD: &str = "\"output_tokens\":";
+    let line = String::from_utf8_lossy(line);
+    let rest = line[line.rfind(FIELD)? + FIELD.len()..].trim_start();
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// 429 response in the error shape of the requested API format
+fn rate_limited_response(format: ClaudeApiFormat, e: &RateLimited) -> Response {
+    let body = match format {
+        ClaudeApiFormat::Claude => json!({
+            "type": "error",
+            "error": {
+                "type": "rate_limit_error",
+                "message": e.message(),
+            },
+        }),
+        ClaudeApiFormat::OpenAI => json!({
+            "error": {
+                "message": e.message(),
+                "type": e.limit,
+                "param": null,
+                "code": "rate_limit_exceeded",
+            },
+        }),
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, e.retry_after.to_string())],
+        Json(body),
+    )
+        .into_response()
+}
+
+/// Settles the tokens of the response with the permit, which is held until the body ends
+///
+/// Input tokens are the ones counted and already charged by the preprocessing, output
+/// tokens are read from the `usage` of the Claude format response.
+fn track_response(response: Response, mut permit: RateLimitPermit) -> Response {
+    let input = response
+        .extensions()
+        .get::<ClaudeContext>()
+        .map(|cx| cx.usage().input_tokens as u64)
+        .unwrap_or_default();
+    permit.set_tokens(input);
+    let (parts, body) = response.into_parts();
+    let body = async_stream::stream! {
+        let mut data = body.into_data_stream();
+        let mut line = vec![];
+        let mut output = 0;
+        while let Some(chunk) = data.next().await {
+            if let Ok(ref bytes) = chunk {
+                for &b in bytes.iter() {
+                    if b != b'\n' {
+                        line.push(b);
+                        continue;
+                    }
+                    output = output_tokens(&line).unwrap_or(output);
+                    line.clear();
+                }
+                permit.set_tokens(input + output);
+            }
+            yield chunk;
+        }
+        output = output_tokens(&line).unwrap_or(output);
+        permit.set_tokens(input + output);
+    };
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Rejects requests over the configured global or per client limits with a 429
+///
+/// Clients are identified by their managed API key, or by their IP when they use the
+/// shared password. Must run after the authentication guard, and inside the response
+/// transformations so the Claude format usage is visible.
+pub async fn enforce_rate_limits(mut request: Request, next: Next) -> Response {
+    let (client, label) = match request.extensions().get::<AuthenticatedKey>() {
+        Some(AuthenticatedKey(key)) => (format!("key:{}", key.key), key.ellipse()),
+        None => {
+            let ip = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            (format!("ip:{ip}"), ip)
+        }
+    };
+    let path = request.uri().path();
+    let format = if path.ends_with("/chat/completions") || path.ends_with("/models") {
+        ClaudeApiFormat::OpenAI
+    } else {
+        ClaudeApiFormat::Claude
+    };
+    let config = CLEWDR_CONFIG.load();
+    let permit = match RATE_LIMITER.acquire(
+        &client,
+        &RateLimits::global(&config),
+        &RateLimits::client(&config),
+    ) {
+        Ok(permit) => permit,
+        Err(e) => {
+            warn!("[RATE LIMIT] {}: {}", label, e.message());
+            return rate_limited_response(format, &e);
+        }
+    };
+    drop(config);
+    request.extensions_mut().insert(permit.charge());
+    track_response(next.run(request).await, permit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_output_tokens() {
+        let line =
+            br#"data: {"type":"message_delta","usage":{"input_tokens":12,"output_tokens": 345}}"#;
+        assert_eq!(output_tokens(line), Some(345));
+        assert_eq!(output_tokens(b"event: message_delta"), None);
+    }
+}
@@ -0,0 +1,73 @@
+#[cfg(feature = "db")]
+mod sqlite;
+
+use std::sync::OnceLock;
+
+#[cfg(feature = "db")]
+pub use sqlite::SqliteStorage;
+
+use crate::{
+    config::{CLEWDR_CONFIG, CONFIG_PATH, ClewdrConfig, ClewdrCookie, CookieStatus, UselessCookie},
+    error::ClewdrError,
+};
+
+/// A change to the persisted cookie collections
+#[derive(Debug, Clone)]
+pub enum CookieChange {
+    /// Insert or update a valid or exhausted cookie
+    Upsert(CookieStatus),
+    /// Remove a valid or exhausted cookie
+    Delete(ClewdrCookie),
+    /// Insert or update a wasted cookie
+    UpsertWasted(UselessCookie),
+    /// Remove a wasted cookie
+    DeleteWasted(ClewdrCookie),
+}
+
+/// Backend persisting configuration and cookies
+#[async_trait::async_trait]
+pub trait StorageLayer: Send + Sync {
+    /// Persists the configuration
+    async fn save_config(&self, config: &ClewdrConfig) -> Result<(), ClewdrError>;
+
+    /// Persists changes to the cookie collections
+    ///
+    /// The in-memory configuration already reflects the changes when this is called.
+    async fn save_cookies(&self, changes: Vec<CookieChange>) -> Result<(), ClewdrError>;
+}
+
+/// Keeps everything in the TOML config file
+pub struct TomlStorage;
+
+#[async_trait::async_trait]
+impl StorageLayer for TomlStorage {
+    async fn save_config(&self, config: &ClewdrConfig) -> Result<(), ClewdrError> {
+        if let Some(parent) = CONFIG_PATH.parent()
+            && !parent.exists()
+        {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(tokio::fs::write(CONFIG_PATH.as_path(), toml::ser::to_string_pretty(config)?).await?)
+    }
+
+    async fn save_cookies(&self, _changes: Vec<CookieChange>) -> Result<(), ClewdrError> {
+        // cookies live in the config file, so it is rewritten as a whole
+        CLEWDR_CONFIG.load().save().await
+    }
+}
+
+static STORAGE: OnceLock<Box<dyn StorageLayer>> = OnceLock::new();
+
+/// Installs the storage backend
+///
+/// Must be called before anything is persisted, later calls are ignored.
+pub fn install_storage(storage: Box<dyn StorageLayer>) {
+    if STORAGE.set(storage).is_err() {
+        tracing::warn!("Storage backend already installed");
+    }
+}
+
+/// Storage backend in use, the TOML config file unless another one was installed
+pub fn storage() -> &'static dyn StorageLayer {
+    STORAGE.get_or_init(|| Box::new(TomlStorage)).as_ref()
+}
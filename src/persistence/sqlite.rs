@@ -0,0 +1,404 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+use super::{CookieChange, StorageLayer};
+use crate::{
+    config::{ClewdrConfig, ClewdrCookie, CookieStatus, UsageBreakdown, UselessCookie},
+    error::ClewdrError,
+};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS config (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS cookies (
+    cookie TEXT PRIMARY KEY,
+    data TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS cookie_usage (
+    cookie TEXT NOT NULL,
+    bucket TEXT NOT NULL,
+    total_input_tokens INTEGER NOT NULL,
+    total_output_tokens INTEGER NOT NULL,
+    sonnet_input_tokens INTEGER NOT NULL,
+    sonnet_output_tokens INTEGER NOT NULL,
+    opus_input_tokens INTEGER NOT NULL,
+    opus_output_tokens INTEGER NOT NULL,
+    PRIMARY KEY (cookie, bucket)
+);
+CREATE TABLE IF NOT EXISTS wasted_cookies (
+    cookie TEXT PRIMARY KEY,
+    data TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+"#;
+
+/// Meta key set once the TOML configuration has been imported
+const MIGRATED_KEY: &str = "migrated_from_toml";
+
+/// Config fields kept in their own tables instead of config rows
+const COOKIE_FIELDS: [&str; 2] = ["cookie_array", "wasted_cookie"];
+
+/// State loaded from the database at startup
+pub struct StoredState {
+    /// Config rows, keyed by field name
+    pub config: Map<String, Value>,
+    pub cookies: Vec<CookieStatus>,
+    pub wasted: Vec<UselessCookie>,
+}
+
+/// Embedded SQLite storage
+///
+/// Cookies, their usage buckets and wasted cookies are upserted one row at a time,
+/// configuration fields are stored as one row per field.
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+fn usage_buckets(c: &CookieStatus) -> [(&'static str, &UsageBreakdown); 5] {
+    [
+        ("session", &c.session_usage),
+        ("weekly", &c.weekly_usage),
+        ("weekly_sonnet", &c.weekly_sonnet_usage),
+        ("weekly_opus", &c.weekly_opus_usage),
+        ("lifetime", &c.lifetime_usage),
+    ]
+}
+
+fn usage_buckets_mut(c: &mut CookieStatus) -> [(&'static str, &mut UsageBreakdown); 5] {
+    [
+        ("session", &mut c.session_usage),
+        ("weekly", &mut c.weekly_usage),
+        ("weekly_sonnet", &mut c.weekly_sonnet_usage),
+        ("weekly_opus", &mut c.weekly_opus_usage),
+        ("lifetime", &mut c.lifetime_usage),
+    ]
+}
+
+fn config_rows(config: &ClewdrConfig) -> Result<Vec<(String, String)>, ClewdrError> {
+    let Value::Object(fields) = serde_json::to_value(config)? else {
+        return Ok(vec![]);
+    };
+    Ok(fields
+        .into_iter()
+        .filter(|(k, _)| !COOKIE_FIELDS.contains(&k.as_str()))
+        .map(|(k, v)| (k, v.to_string()))
+        .collect())
+}
+
+fn write_config(conn: &Connection, rows: &[(String, String)]) -> Result<(), ClewdrError> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO config (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )?;
+    for (key, value) in rows {
+        stmt.execute(params![key, value])?;
+    }
+    Ok(())
+}
+
+fn upsert_cookie(conn: &Connection, cookie: &CookieStatus) -> Result<(), ClewdrError> {
+    let now = Utc::now().timestamp();
+    // usage is stored in its own table
+    let mut data = cookie.to_owned();
+    for (_, usage) in usage_buckets_mut(&mut data) {
+        *usage = UsageBreakdown::default();
+    }
+    conn.prepare_cached(
+        "INSERT INTO cookies (cookie, data, updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(cookie) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at",
+    )?
+    .execute(params![&*cookie.cookie, serde_json::to_string(&data)?, now])?;
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO cookie_usage (
+            cookie, bucket, total_input_tokens, total_output_tokens,
+            sonnet_input_tokens, sonnet_output_tokens, opus_input_tokens, opus_output_tokens
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(cookie, bucket) DO UPDATE SET
+            total_input_tokens = excluded.total_input_tokens,
+            total_output_tokens = excluded.total_output_tokens,
+            sonnet_input_tokens = excluded.sonnet_input_tokens,
+            sonnet_output_tokens = excluded.sonnet_output_tokens,
+            opus_input_tokens = excluded.opus_input_tokens,
+            opus_output_tokens = excluded.opus_output_tokens",
+    )?;
+    for (bucket, u) in usage_buckets(cookie) {
+        stmt.execute(params![
+            &*cookie.cookie,
+            bucket,
+            u.total_input_tokens as i64,
+            u.total_output_tokens as i64,
+            u.sonnet_input_tokens as i64,
+            u.sonnet_output_tokens as i64,
+            u.opus_input_tokens as i64,
+            u.opus_output_tokens as i64,
+        ])?;
+    }
+    Ok(())
+}
+
+fn delete_cookie(conn: &Connection, cookie: &ClewdrCookie) -> Result<(), ClewdrError> {
+    conn.execute("DELETE FROM cookies WHERE cookie = ?1", [&**cookie])?;
+    conn.execute("DELETE FROM cookie_usage WHERE cookie = ?1", [&**cookie])?;
+    Ok(())
+}
+
+fn upsert_wasted(conn: &Connection, cookie: &UselessCookie) -> Result<(), ClewdrError> {
+    conn.prepare_cached(
+        "INSERT INTO wasted_cookies (cookie, data, updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(cookie) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at",
+    )?
+    .execute(params![
+        &*cookie.cookie,
+        serde_json::to_string(cookie)?,
+        Utc::now().timestamp()
+    ])?;
+    Ok(())
+}
+
+fn read_state(conn: &Connection) -> Result<StoredState, ClewdrError> {
+    let mut config = Map::new();
+    let mut stmt = conn.prepare("SELECT key, value FROM config")?;
+    let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+    for row in rows {
+        let (key, value) = row?;
+        match serde_json::from_str::<Value>(&value) {
+            // unset optional fields fall back to their defaults
+            Ok(Value::Null) => {}
+            Ok(value) => {
+                config.insert(key, value);
+            }
+            Err(e) => warn!("Skipping malformed config row {}: {}", key, e),
+        }
+    }
+
+    let mut cookies = HashMap::new();
+    let mut stmt = conn.prepare("SELECT cookie, data FROM cookies")?;
+    let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+    for row in rows {
+        let (key, data) = row?;
+        match serde_json::from_str::<CookieStatus>(&data) {
+            Ok(cookie) => {
+                cookies.insert(key, cookie);
+            }
+            Err(e) => warn!("Skipping malformed cookie row: {}", e),
+        }
+    }
+    let mut stmt = conn.prepare(
+        "SELECT cookie, bucket, total_input_tokens, total_output_tokens,
+                sonnet_input_tokens, sonnet_output_tokens, opus_input_tokens, opus_output_tokens
+         FROM cookie_usage",
+    )?;
+    let rows = stmt.query_map([], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            r.get::<_, String>(1)?,
+            UsageBreakdown {
+                total_input_tokens: r.get::<_, i64>(2)? as u64,
+                total_output_tokens: r.get::<_, i64>(3)? as u64,
+                sonnet_input_tokens: r.get::<_, i64>(4)? as u64,
+                sonnet_output_tokens: r.get::<_, i64>(5)? as u64,
+                opus_input_tokens: r.get::<_, i64>(6)? as u64,
+                opus_output_tokens: r.get::<_, i64>(7)? as u64,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (key, bucket, usage) = row?;
+        if let Some(cookie) = cookies.get_mut(&key)
+            && let Some((_, slot)) = usage_buckets_mut(cookie)
+                .into_iter()
+                .find(|(b, _)| *b == bucket)
+        {
+            *slot = usage;
+        }
+    }
+
+    let mut wasted = vec![];
+    let mut stmt = conn.prepare("SELECT data FROM wasted_cookies")?;
+    let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
+    for data in rows {
+        match serde_json::from_str::<UselessCookie>(&data?) {
+            Ok(cookie) => wasted.push(cookie),
+            Err(e) => warn!("Skipping malformed wasted cookie row: {}", e),
+        }
+    }
+
+    Ok(StoredState {
+        config,
+        cookies: cookies.into_values().collect(),
+        wasted,
+    })
+}
+
+fn migrate(conn: &mut Connection, config: &ClewdrConfig) -> Result<(), ClewdrError> {
+    let migrated = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = ?1",
+            [MIGRATED_KEY],
+            |r| r.get::<_, String>(0),
+        )
+        .optional()?
+        .is_some();
+    if migrated {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    write_config(&tx, &config_rows(config)?)?;
+    for cookie in config.cookie_array.iter() {
+        upsert_cookie(&tx, cookie)?;
+    }
+    for cookie in config.wasted_cookie.iter() {
+        upsert_wasted(&tx, cookie)?;
+    }
+    tx.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+        params![MIGRATED_KEY, Utc::now().timestamp().to_string()],
+    )?;
+    tx.commit()?;
+    info!(
+        "Imported config, {} cookies and {} wasted cookies into the database",
+        config.cookie_array.len(),
+        config.wasted_cookie.len()
+    );
+    Ok(())
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it and its tables if needed
+    pub fn open(path: &Path) -> Result<Self, ClewdrError> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+            && !parent.exists()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, ClewdrError> {
+        conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Loads the stored state
+    ///
+    /// On the first start with the database, `config` (as loaded from the TOML file)
+    /// is imported first. Later starts ignore the TOML content.
+    pub fn load_or_migrate(&self, config: &ClewdrConfig) -> Result<StoredState, ClewdrError> {
+        let mut conn = self.lock();
+        migrate(&mut conn, config)?;
+        read_state(&conn)
+    }
+
+    /// Runs `f` with the connection on the blocking thread pool
+    async fn with_conn<F, R>(&self, f: F) -> Result<R, ClewdrError>
+    where
+        F: FnOnce(&mut Connection) -> Result<R, ClewdrError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageLayer for SqliteStorage {
+    async fn save_config(&self, config: &ClewdrConfig) -> Result<(), ClewdrError> {
+        let rows = config_rows(config)?;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            write_config(&tx, &rows)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn save_cookies(&self, changes: Vec<CookieChange>) -> Result<(), ClewdrError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            for change in changes {
+                match change {
+                    CookieChange::Upsert(cookie) => upsert_cookie(&tx, &cookie)?,
+                    CookieChange::Delete(cookie) => delete_cookie(&tx, &cookie)?,
+                    CookieChange::UpsertWasted(cookie) => upsert_wasted(&tx, &cookie)?,
+                    CookieChange::DeleteWasted(cookie) => {
+                        tx.execute("DELETE FROM wasted_cookies WHERE cookie = ?1", [&*cookie])?;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Reason;
+
+    fn cookie(id: char) -> CookieStatus {
+        let value = format!("sk-ant-sid01-{}-AAAAAAAA", id.to_string().repeat(86));
+        CookieStatus::new(&value, None).unwrap()
+    }
+
+    #[test]
+    fn migrates_once_and_round_trips() {
+        let storage =
+            SqliteStorage::from_connection(Connection::open_in_memory().unwrap()).expect("schema");
+        let mut config = ClewdrConfig::default();
+        config.max_retries = 7;
+        let mut a = cookie('a');
+        a.weekly_opus_usage.opus_input_tokens = 42;
+        config.cookie_array.insert(a.to_owned());
+        config
+            .wasted_cookie
+            .insert(UselessCookie::new(cookie('b').cookie, Reason::Banned));
+
+        let state = storage.load_or_migrate(&config).unwrap();
+        assert_eq!(state.config["max_retries"], 7);
+        assert!(!state.config.contains_key("cookie_array"));
+        assert_eq!(state.cookies.len(), 1);
+        assert_eq!(state.cookies[0].weekly_opus_usage.opus_input_tokens, 42);
+        assert_eq!(state.wasted.len(), 1);
+
+        // the TOML content is only imported once
+        let state = storage.load_or_migrate(&ClewdrConfig::default()).unwrap();
+        assert_eq!(state.config["max_retries"], 7);
+
+        let conn = storage.lock();
+        delete_cookie(&conn, &a.cookie).unwrap();
+        assert!(read_state(&conn).unwrap().cookies.is_empty());
+    }
+}
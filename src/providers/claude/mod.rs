@@ -2,19 +2,30 @@ use std::{sync::Arc, time::Instant};
 
 use axum::response::Response;
 use colored::Colorize;
-use tracing::info;
+use http::HeaderValue;
+use tracing::{info, warn};
 
-use super::LLMProvider;
+use super::{LLMProvider, SharedClient};
 use crate::{
+    claude_api_state::ClaudeApiState,
     claude_code_state::ClaudeCodeState,
     claude_web_state::ClaudeWebState,
+    config::{CLEWDR_CONFIG, ClaudeBackend},
     error::ClewdrError,
     middleware::claude::{ClaudeApiFormat, ClaudeContext},
-    services::cookie_actor::CookieActorHandle,
+    services::{
+        audit::{AUDIT_LOG, AuditRequest},
+        cookie_actor::CookieActorHandle,
+        key_pool::KeyPoolHandle,
+        metrics::{METRICS, outcome},
+    },
     types::claude::CreateMessageParams,
     utils::{enabled, print_out_json},
 };
 
+/// Response header naming the backend that served a request
+pub const BACKEND_HEADER: &str = "x-clewdr-backend";
+
 #[derive(Clone, Copy)]
 pub enum ClaudeOperation {
     Messages,
@@ -26,6 +37,8 @@ pub struct ClaudeInvocation {
     pub params: CreateMessageParams,
     pub context: ClaudeContext,
     pub operation: ClaudeOperation,
+    /// Audit entry of the client request, shared by every backend trying to serve it
+    pub audit_id: Option<u64>,
 }
 
 impl ClaudeInvocation {
@@ -34,6 +47,7 @@ impl ClaudeInvocation {
             params,
             context,
             operation: ClaudeOperation::Messages,
+            audit_id: None,
         }
     }
 
@@ -42,6 +56,7 @@ impl ClaudeInvocation {
             params,
             context,
             operation: ClaudeOperation::CountTokens,
+            audit_id: None,
         }
     }
 }
@@ -53,36 +68,200 @@ pub struct ClaudeProviderResponse {
 
 struct ClaudeSharedState {
     cookie_actor_handle: CookieActorHandle,
+    api_key_pool_handle: KeyPoolHandle,
 }
 
 impl ClaudeSharedState {
-    fn new(cookie_actor_handle: CookieActorHandle) -> Self {
+    fn new(cookie_actor_handle: CookieActorHandle, api_key_pool_handle: KeyPoolHandle) -> Self {
         Self {
             cookie_actor_handle,
+            api_key_pool_handle,
         }
     }
 }
 
+/// Claude backends, serving the requests of the route they are handed to
 #[derive(Clone)]
 pub struct ClaudeProviders {
     web: Arc<ClaudeWebProvider>,
     code: Arc<ClaudeCodeProvider>,
+    api: Arc<ClaudeApiProvider>,
+    /// Backend requested by the route, Claude Code requests may fall back to others
+    route: ClaudeBackend,
 }
 
 impl ClaudeProviders {
-    pub fn new(cookie_actor_handle: CookieActorHandle) -> Self {
-        let shared = Arc::new(ClaudeSharedState::new(cookie_actor_handle));
+    pub fn new(cookie_actor_handle: CookieActorHandle, api_key_pool_handle: KeyPoolHandle) -> Self {
+        let shared = Arc::new(ClaudeSharedState::new(
+            cookie_actor_handle,
+            api_key_pool_handle,
+        ));
         let web = Arc::new(ClaudeWebProvider::new(shared.clone()));
         let code = Arc::new(ClaudeCodeProvider::new(shared.clone()));
-        Self { web, code }
+        let api = Arc::new(ClaudeApiProvider::new(shared.clone()));
+        Self {
+            web,
+            code,
+            api,
+            route: ClaudeBackend::Code,
+        }
+    }
+
+    /// Providers of a route served by `backend`
+    fn route(&self, backend: ClaudeBackend) -> Self {
+        Self {
+            route: backend,
+            ..self.to_owned()
+        }
+    }
+
+    pub fn web(&self) -> Self {
+        self.route(ClaudeBackend::Web)
+    }
+
+    pub fn code(&self) -> Self {
+        self.route(ClaudeBackend::Code)
+    }
+
+    pub fn api(&self) -> Self {
+        self.route(ClaudeBackend::Api)
+    }
+
+    async fn invoke_backend(
+        &self,
+        backend: ClaudeBackend,
+        request: ClaudeInvocation,
+    ) -> Result<ClaudeProviderResponse, ClewdrError> {
+        match backend {
+            ClaudeBackend::Code => self.code.invoke(request).await,
+            ClaudeBackend::Api => self.api.invoke(request).await,
+            ClaudeBackend::Web => {
+                let context = request.context.to_web(&request.params);
+                self.web
+                    .invoke(ClaudeInvocation { context, ..request })
+                    .await
+            }
+        }
     }
+}
+
+/// Whether a backend failed for lack of usable credentials, so another may serve the request
+fn is_exhausted(err: &ClewdrError) -> bool {
+    matches!(
+        err,
+        ClewdrError::TooManyRetries | ClewdrError::NoCookieAvailable | ClewdrError::NoKeyAvailable
+    )
+}
 
-    pub fn web(&self) -> Arc<ClaudeWebProvider> {
-        self.web.clone()
+/// Backends tried in order for a Claude Code request, Claude Code first and each backend once
+fn fallback_chain(fallback: &[ClaudeBackend]) -> Vec<ClaudeBackend> {
+    let mut chain = vec![ClaudeBackend::Code];
+    for backend in fallback.iter().copied() {
+        if !chain.contains(&backend) {
+            chain.push(backend);
+        }
     }
+    chain
+}
 
-    pub fn code(&self) -> Arc<ClaudeCodeProvider> {
-        self.code.clone()
+/// Tries the backends of `chain` in order, moving on only while they run out of credentials
+///
+/// # Returns
+/// * `Result<(ClaudeBackend, T), ClewdrError>` - The backend that served the request and its
+///   output, or the error that ended the chain
+async fn try_backends<T, Fut>(
+    chain: &[ClaudeBackend],
+    mut invoke: impl FnMut(ClaudeBackend) -> Fut,
+) -> Result<(ClaudeBackend, T), ClewdrError>
+where
+    Fut: Future<Output = Result<T, ClewdrError>>,
+{
+    let mut last_err = ClewdrError::NoCookieAvailable;
+    for (i, backend) in chain.iter().copied().enumerate() {
+        if i > 0 {
+            warn!(
+                "[FALLBACK] {} -> {}: {}",
+                chain[i - 1].to_string().yellow(),
+                backend.to_string().green(),
+                last_err
+            );
+        }
+        match invoke(backend).await {
+            Ok(output) => return Ok((backend, output)),
+            Err(e) if is_exhausted(&e) => last_err = e,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_err)
+}
+
+/// Serves the requests of the route, Claude Code requests falling back to the backends in
+/// `code_fallback` when every cookie fails or is rate limited
+///
+/// A client request opens one audit entry and records one request metric, whichever
+/// backends were tried.
+#[async_trait::async_trait]
+impl LLMProvider for ClaudeProviders {
+    type Request = ClaudeInvocation;
+    type Output = ClaudeProviderResponse;
+
+    async fn invoke(&self, mut request: Self::Request) -> Result<Self::Output, ClewdrError> {
+        if matches!(request.operation, ClaudeOperation::CountTokens) {
+            return self.code.invoke(request).await;
+        }
+        let chain = match self.route {
+            ClaudeBackend::Code => fallback_chain(&CLEWDR_CONFIG.load().code_fallback),
+            backend => vec![backend],
+        };
+        let endpoint: &'static str = self.route.into();
+        let params = &request.params;
+        let stream = request.context.is_stream();
+        let format_display = match request.context.api_format() {
+            ClaudeApiFormat::Claude => ClaudeApiFormat::Claude.to_string().green(),
+            ClaudeApiFormat::OpenAI => ClaudeApiFormat::OpenAI.to_string().yellow(),
+        };
+        info!(
+            "[REQ] stream: {}, msgs: {}, model: {}, think: {}, format: {}",
+            enabled(stream),
+            params.messages.len().to_string().green(),
+            params.model.green(),
+            enabled(params.thinking.is_some()),
+            format_display
+        );
+        let model = params.model.to_owned();
+        let format = request.context.api_format().to_string();
+        let audit_id = AUDIT_LOG.start(
+            AuditRequest {
+                endpoint,
+                format: format.to_owned(),
+                model: &model,
+                stream,
+                api_key: request.context.api_key(),
+            },
+            params,
+        );
+        request.audit_id = audit_id;
+        let stopwatch = Instant::now();
+        let result = try_backends(&chain, |backend| {
+            self.invoke_backend(backend, request.to_owned())
+        })
+        .await;
+        let elapsed = stopwatch.elapsed();
+        let status = outcome(&result);
+        METRICS.record_request(endpoint, &model, &format, status, elapsed);
+        AUDIT_LOG.finish(audit_id, status, elapsed);
+        let (backend, mut output) = result?;
+        AUDIT_LOG.record_backend(audit_id, backend.into());
+        output
+            .response
+            .headers_mut()
+            .insert(BACKEND_HEADER, HeaderValue::from_static(backend.into()));
+        output.response = AUDIT_LOG.capture_response(audit_id, output.response).await;
+        info!(
+            "[FIN] elapsed: {}s",
+            format!("{}", elapsed.as_secs_f32()).green()
+        );
+        Ok(output)
     }
 }
 
@@ -104,40 +283,25 @@ impl LLMProvider for ClaudeWebProvider {
 
     async fn invoke(&self, request: Self::Request) -> Result<Self::Output, ClewdrError> {
         let mut state = ClaudeWebState::new(self.shared.cookie_actor_handle.clone());
-        let stream = request.context.is_stream();
         state.api_format = request.context.api_format();
-        state.stream = stream;
+        state.stream = request.context.is_stream();
         state.usage = request.context.usage().to_owned();
+        state.api_key = request.context.api_key().map(str::to_string);
+        state.cookie_group = request.context.cookie_group().map(str::to_string);
+        state.audit_id = request.audit_id;
         let ClaudeInvocation {
             params,
             context,
             operation,
+            ..
         } = request;
         if !matches!(operation, ClaudeOperation::Messages) {
             return Err(ClewdrError::BadRequest {
                 msg: "Unsupported operation for Claude Web",
             });
         }
-        let format_display = match context.api_format() {
-            ClaudeApiFormat::Claude => ClaudeApiFormat::Claude.to_string().green(),
-            ClaudeApiFormat::OpenAI => ClaudeApiFormat::OpenAI.to_string().yellow(),
-        };
-        info!(
-            "[REQ] stream: {}, msgs: {}, model: {}, think: {}, format: {}",
-            enabled(stream),
-            params.messages.len().to_string().green(),
-            params.model.green(),
-            enabled(params.thinking.is_some()),
-            format_display
-        );
         print_out_json(&params, "claude_web_client_req.json");
-        let stopwatch = Instant::now();
         let response = state.try_chat(params).await?;
-        let elapsed = stopwatch.elapsed();
-        info!(
-            "[FIN] elapsed: {}s",
-            format!("{}", elapsed.as_secs_f32()).green()
-        );
         Ok(ClaudeProviderResponse { context, response })
     }
 }
@@ -165,32 +329,19 @@ impl LLMProvider for ClaudeCodeProvider {
         state.system_prompt_hash = request.context.system_prompt_hash();
         state.anthropic_beta_header = request.context.anthropic_beta().map(str::to_string);
         state.usage = request.context.usage().to_owned();
+        state.api_key = request.context.api_key().map(str::to_string);
+        state.cookie_group = request.context.cookie_group().map(str::to_string);
+        state.audit_id = request.audit_id;
         let ClaudeInvocation {
             params,
             context,
             operation,
+            ..
         } = request;
         match operation {
             ClaudeOperation::Messages => {
-                let format_display = match context.api_format() {
-                    ClaudeApiFormat::Claude => ClaudeApiFormat::Claude.to_string().green(),
-                    ClaudeApiFormat::OpenAI => ClaudeApiFormat::OpenAI.to_string().yellow(),
-                };
-                info!(
-                    "[REQ] stream: {}, msgs: {}, model: {}, format: {}",
-                    enabled(state.stream),
-                    params.messages.len().to_string().green(),
-                    params.model.green(),
-                    format_display
-                );
                 print_out_json(&params, "claude_code_client_req.json");
-                let stopwatch = Instant::now();
                 let response = state.try_chat(params).await?;
-                let elapsed = stopwatch.elapsed();
-                info!(
-                    "[FIN] elapsed: {}s",
-                    format!("{}", elapsed.as_secs_f32()).green()
-                );
                 Ok(ClaudeProviderResponse { context, response })
             }
             ClaudeOperation::CountTokens => {
@@ -212,6 +363,108 @@ impl LLMProvider for ClaudeCodeProvider {
     }
 }
 
-pub fn build_providers(cookie_actor_handle: CookieActorHandle) -> ClaudeProviders {
-    ClaudeProviders::new(cookie_actor_handle)
+#[derive(Clone)]
+pub struct ClaudeApiProvider {
+    shared: Arc<ClaudeSharedState>,
+    client: Arc<SharedClient>,
+}
+
+impl ClaudeApiProvider {
+    fn new(shared: Arc<ClaudeSharedState>) -> Self {
+        Self {
+            shared,
+            client: Arc::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LLMProvider for ClaudeApiProvider {
+    type Request = ClaudeInvocation;
+    type Output = ClaudeProviderResponse;
+
+    async fn invoke(&self, request: Self::Request) -> Result<Self::Output, ClewdrError> {
+        let client = self.client.get("Failed to build Anthropic API client")?;
+        let mut state = ClaudeApiState::new(self.shared.api_key_pool_handle.clone(), client);
+        state.api_format = request.context.api_format();
+        state.stream = request.context.is_stream();
+        state.anthropic_beta_header = request.context.anthropic_beta().map(str::to_string);
+        state.usage = request.context.usage().to_owned();
+        state.api_key = request.context.api_key().map(str::to_string);
+        state.audit_id = request.audit_id;
+        let ClaudeInvocation {
+            params,
+            context,
+            operation,
+            ..
+        } = request;
+        if !matches!(operation, ClaudeOperation::Messages) {
+            return Err(ClewdrError::BadRequest {
+                msg: "Unsupported operation for Claude API",
+            });
+        }
+        print_out_json(&params, "claude_api_client_req.json");
+        let response = state.try_chat(params).await?;
+        Ok(ClaudeProviderResponse { context, response })
+    }
+}
+
+pub fn build_providers(
+    cookie_actor_handle: CookieActorHandle,
+    api_key_pool_handle: KeyPoolHandle,
+) -> ClaudeProviders {
+    ClaudeProviders::new(cookie_actor_handle, api_key_pool_handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_fallback_chain() {
+        assert_eq!(fallback_chain(&[]), [ClaudeBackend::Code]);
+        assert_eq!(
+            fallback_chain(&[
+                ClaudeBackend::Api,
+                ClaudeBackend::Code,
+                ClaudeBackend::Web,
+                ClaudeBackend::Api,
+            ]),
+            [ClaudeBackend::Code, ClaudeBackend::Api, ClaudeBackend::Web]
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_only_when_exhausted() {
+        let chain = [ClaudeBackend::Code, ClaudeBackend::Web, ClaudeBackend::Api];
+
+        let mut tried = vec![];
+        let result = try_backends(&chain, |backend| {
+            tried.push(backend);
+            async move {
+                match backend {
+                    ClaudeBackend::Code => Err(ClewdrError::NoCookieAvailable),
+                    ClaudeBackend::Web => Err(ClewdrError::TooManyRetries),
+                    ClaudeBackend::Api => Ok("api"),
+                }
+            }
+        })
+        .await;
+        assert!(matches!(result, Ok((ClaudeBackend::Api, "api"))));
+        assert_eq!(tried, chain);
+
+        let mut tried = vec![];
+        let result = try_backends(&chain, |backend| {
+            tried.push(backend);
+            async move {
+                match backend {
+                    ClaudeBackend::Code => Err(ClewdrError::NoCookieAvailable),
+                    _ => Err::<(), _>(ClewdrError::BadRequest { msg: "invalid" }),
+                }
+            }
+        })
+        .await;
+        assert!(matches!(result, Err(ClewdrError::BadRequest { .. })));
+        assert_eq!(tried, [ClaudeBackend::Code, ClaudeBackend::Web]);
+    }
 }
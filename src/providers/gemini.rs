@@ -0,0 +1,98 @@
+use std::{sync::Arc, time::Instant};
+
+use colored::Colorize;
+use tracing::info;
+
+use super::{
+    LLMProvider, SharedClient,
+    claude::{ClaudeInvocation, ClaudeOperation, ClaudeProviderResponse},
+};
+use crate::{
+    error::ClewdrError,
+    gemini_state::GeminiState,
+    middleware::claude::ClaudeApiFormat,
+    services::{
+        audit::{AUDIT_LOG, AuditRequest},
+        key_pool::KeyPoolHandle,
+        metrics::{METRICS, outcome},
+    },
+    utils::{enabled, print_out_json},
+};
+
+/// Serves normalized Claude requests from the Gemini API
+#[derive(Clone)]
+pub struct GeminiProvider {
+    key_pool_handle: KeyPoolHandle,
+    client: Arc<SharedClient>,
+}
+
+impl GeminiProvider {
+    pub fn new(key_pool_handle: KeyPoolHandle) -> Self {
+        Self {
+            key_pool_handle,
+            client: Arc::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LLMProvider for GeminiProvider {
+    type Request = ClaudeInvocation;
+    type Output = ClaudeProviderResponse;
+
+    async fn invoke(&self, request: Self::Request) -> Result<Self::Output, ClewdrError> {
+        let client = self.client.get("Failed to build Gemini client")?;
+        let mut state = GeminiState::new(self.key_pool_handle.clone(), client);
+        state.api_format = request.context.api_format();
+        state.stream = request.context.is_stream();
+        state.usage = request.context.usage().to_owned();
+        state.api_key = request.context.api_key().map(str::to_string);
+        let ClaudeInvocation {
+            params,
+            context,
+            operation,
+            ..
+        } = request;
+        if !matches!(operation, ClaudeOperation::Messages) {
+            return Err(ClewdrError::BadRequest {
+                msg: "Unsupported operation for Gemini",
+            });
+        }
+        let format_display = match context.api_format() {
+            ClaudeApiFormat::Claude => ClaudeApiFormat::Claude.to_string().green(),
+            ClaudeApiFormat::OpenAI => ClaudeApiFormat::OpenAI.to_string().yellow(),
+        };
+        info!(
+            "[REQ] stream: {}, msgs: {}, model: {}, format: {}",
+            enabled(state.stream),
+            params.messages.len().to_string().green(),
+            params.model.green(),
+            format_display
+        );
+        print_out_json(&params, "gemini_client_req.json");
+        let model = params.model.to_owned();
+        let format = context.api_format().to_string();
+        state.audit_id = AUDIT_LOG.start(
+            AuditRequest {
+                endpoint: "gemini",
+                format: format.to_owned(),
+                model: &model,
+                stream: state.stream,
+                api_key: context.api_key(),
+            },
+            &params,
+        );
+        let stopwatch = Instant::now();
+        let result = state.try_chat(params).await;
+        let elapsed = stopwatch.elapsed();
+        let status = outcome(&result);
+        METRICS.record_request("gemini", &model, &format, status, elapsed);
+        AUDIT_LOG.finish(state.audit_id, status, elapsed);
+        let response = AUDIT_LOG.capture_response(state.audit_id, result?).await;
+        info!(
+            "[FIN] elapsed: {}s",
+            format!("{}", elapsed.as_secs_f32()).green()
+        );
+        Ok(ClaudeProviderResponse { context, response })
+    }
+}
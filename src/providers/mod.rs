@@ -1,8 +1,15 @@
+use std::sync::{Mutex, PoisonError};
+
 use async_trait::async_trait;
+use snafu::ResultExt;
 
-use crate::error::ClewdrError;
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::{ClewdrError, WreqSnafu},
+};
 
 pub mod claude;
+pub mod gemini;
 
 #[async_trait]
 pub trait LLMProvider: Send + Sync {
@@ -11,3 +18,34 @@ pub trait LLMProvider: Send + Sync {
 
     async fn invoke(&self, request: Self::Request) -> Result<Self::Output, ClewdrError>;
 }
+
+/// HTTP client shared by the requests of a provider
+///
+/// The client is built on first use and rebuilt when the `proxy` setting changes.
+#[derive(Default)]
+pub struct SharedClient {
+    cached: Mutex<Option<(Option<String>, wreq::Client)>>,
+}
+
+impl SharedClient {
+    /// Returns the client for the current proxy setting
+    ///
+    /// # Arguments
+    /// * `msg` - Error message used when the client cannot be built
+    pub fn get(&self, msg: &'static str) -> Result<wreq::Client, ClewdrError> {
+        let config = CLEWDR_CONFIG.load();
+        let mut cached = self.cached.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((proxy, client)) = cached.as_ref()
+            && *proxy == config.proxy
+        {
+            return Ok(client.to_owned());
+        }
+        let mut client = wreq::Client::builder();
+        if let Some(proxy) = config.wreq_proxy.to_owned() {
+            client = client.proxy(proxy);
+        }
+        let client = client.build().context(WreqSnafu { msg })?;
+        *cached = Some((config.proxy.to_owned(), client.to_owned()));
+        Ok(client)
+    }
+}
@@ -1,28 +1,42 @@
+use std::sync::Arc;
+
 use axum::{
     Router,
     extract::DefaultBodyLimit,
     http::Method,
-    middleware::{from_extractor, map_response},
+    middleware::{from_extractor, from_fn, map_response},
     routing::{delete, get, post},
 };
 use tower::ServiceBuilder;
-use axum::extract::DefaultBodyLimit;
 use tower_http::{compression::CompressionLayer, cors::CorsLayer};
 
 use crate::{
     api::*,
     middleware::{
         RequireAdminAuth, RequireBearerAuth, RequireFlexibleAuth,
-        claude::{add_usage_info, apply_stop_sequences, check_overloaded, to_oai},
+        claude::{add_usage_info, apply_stop_sequences, check_overloaded, to_oai, to_oai_error},
+        enforce_rate_limits,
+    },
+    providers::{claude::ClaudeProviders, gemini::GeminiProvider},
+    services::{
+        cookie_actor::CookieActorHandle,
+        health_check::spawn_health_checker,
+        key_pool::{KeyPoolHandle, KeyPoolKind},
+        key_usage::KEY_USAGE,
+        proxy_pool::PROXY_POOL,
+        response_cache::spawn_disk_sweeper,
+        token_refresh::spawn_token_refresher,
+        utilization::spawn_utilization_poller,
     },
-    providers::claude::ClaudeProviders,
-    services::cookie_actor::CookieActorHandle,
 };
 
 /// RouterBuilder for the application
 pub struct RouterBuilder {
     claude_providers: ClaudeProviders,
     cookie_actor_handle: CookieActorHandle,
+    gemini_provider: Arc<GeminiProvider>,
+    gemini_key_pool: KeyPoolHandle,
+    anthropic_key_pool: KeyPoolHandle,
     inner: Router,
 }
 
@@ -36,10 +50,29 @@ impl RouterBuilder {
         let cookie_handle = CookieActorHandle::start()
             .await
             .expect("Failed to start CookieActor");
-        let claude_providers = crate::providers::claude::build_providers(cookie_handle.clone());
+        let anthropic_key_pool = KeyPoolHandle::start(KeyPoolKind::Anthropic)
+            .await
+            .expect("Failed to start Anthropic KeyPoolActor");
+        let claude_providers = crate::providers::claude::build_providers(
+            cookie_handle.clone(),
+            anthropic_key_pool.clone(),
+        );
+        let gemini_key_pool = KeyPoolHandle::start(KeyPoolKind::Gemini)
+            .await
+            .expect("Failed to start Gemini KeyPoolActor");
+        let gemini_provider = Arc::new(GeminiProvider::new(gemini_key_pool.clone()));
+        KEY_USAGE.spawn_flusher();
+        spawn_health_checker(cookie_handle.clone());
+        spawn_token_refresher(cookie_handle.clone());
+        spawn_utilization_poller(cookie_handle.clone());
+        PROXY_POOL.spawn_health_checker();
+        spawn_disk_sweeper();
         RouterBuilder {
             claude_providers,
             cookie_actor_handle: cookie_handle,
+            gemini_provider,
+            gemini_key_pool,
+            anthropic_key_pool,
             inner: Router::new(),
         }
     }
@@ -51,8 +84,11 @@ impl RouterBuilder {
             .route_claude_code_endpoints()
             .route_claude_web_endpoints()
             .route_admin_endpoints()
+            .route_metrics_endpoint()
             .route_claude_web_oai_endpoints()
             .route_claude_code_oai_endpoints()
+            .route_gemini_endpoints()
+            .route_claude_api_endpoints()
             .setup_static_serving()
             .with_tower_trace()
             .with_cors();
@@ -73,7 +109,8 @@ impl RouterBuilder {
                     .layer(CompressionLayer::new())
                     .layer(map_response(add_usage_info))
                     .layer(map_response(apply_stop_sequences))
-                    .layer(map_response(check_overloaded)),
+                    .layer(map_response(check_overloaded))
+                    .layer(from_fn(enforce_rate_limits)),
             )
             .with_state(self.claude_providers.web());
         self.inner = self.inner.merge(router);
@@ -91,9 +128,14 @@ impl RouterBuilder {
             .layer(
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireFlexibleAuth>())
-                    .layer(CompressionLayer::new()),
+                    .layer(CompressionLayer::new())
+                    // only act on responses served by the web fallback
+                    .layer(map_response(add_usage_info))
+                    .layer(map_response(apply_stop_sequences))
+                    .layer(map_response(check_overloaded))
+                    .layer(from_fn(enforce_rate_limits)),
             )
-            .with_state(self.claude_providers.code());
+            .with_state(self.claude_providers.to_owned());
         self.inner = self.inner.merge(router);
         self
     }
@@ -102,6 +144,11 @@ impl RouterBuilder {
     fn route_admin_endpoints(mut self) -> Self {
         let cookie_router = Router::new()
             .route("/cookies", get(api_get_cookies))
+            .route("/cookies/import", post(api_import_cookies))
+            .route("/cookies/export", get(api_export_cookies))
+            .route("/oauth/authorize", get(api_oauth_authorize))
+            .route("/oauth/code", post(api_oauth_code))
+            .route("/oauth/refresh", post(api_oauth_refresh))
             .route(
                 "/cookie",
                 delete(api_delete_cookie)
@@ -109,13 +156,40 @@ impl RouterBuilder {
                     .put(api_put_cookie),
             )
             .with_state(self.cookie_actor_handle.to_owned());
+        let gemini_key_router = Router::new()
+            .route(
+                "/gemini/keys",
+                get(api_get_pool_keys)
+                    .post(api_post_pool_key)
+                    .delete(api_delete_pool_key),
+            )
+            .with_state(self.gemini_key_pool.to_owned());
+        let anthropic_key_router = Router::new()
+            .route(
+                "/anthropic/keys",
+                get(api_get_pool_keys)
+                    .post(api_post_pool_key)
+                    .delete(api_delete_pool_key),
+            )
+            .with_state(self.anthropic_key_pool.to_owned());
         let admin_router = Router::new()
             .route("/auth", get(api_auth))
-            .route("/config", get(api_get_config).post(api_post_config));
+            .route("/config", get(api_get_config).post(api_post_config))
+            .route(
+                "/keys",
+                get(api_get_keys)
+                    .post(api_post_key)
+                    .put(api_put_key)
+                    .delete(api_delete_key),
+            )
+            .route("/logs", get(api_get_logs))
+            .route("/cache", delete(api_purge_cache));
         let router = Router::new()
             .nest(
                 "/api",
                 cookie_router
+                    .merge(gemini_key_router)
+                    .merge(anthropic_key_router)
                     .merge(admin_router)
                     .layer(from_extractor::<RequireAdminAuth>()),
             )
@@ -124,6 +198,15 @@ impl RouterBuilder {
         self
     }
 
+    /// Sets up the Prometheus metrics endpoint
+    fn route_metrics_endpoint(mut self) -> Self {
+        let router = Router::new()
+            .route("/metrics", get(api_metrics))
+            .with_state(self.cookie_actor_handle.to_owned());
+        self.inner = self.inner.merge(router);
+        self
+    }
+
     /// Sets up routes for OpenAI compatible endpoints
     fn route_claude_web_oai_endpoints(mut self) -> Self {
         let router = Router::new()
@@ -131,11 +214,13 @@ impl RouterBuilder {
             .route("/v1/models", get(api_get_models))
             .layer(
                 ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
                     .layer(from_extractor::<RequireBearerAuth>())
                     .layer(CompressionLayer::new())
                     .layer(map_response(to_oai))
                     .layer(map_response(apply_stop_sequences))
-                    .layer(map_response(check_overloaded)),
+                    .layer(map_response(check_overloaded))
+                    .layer(from_fn(enforce_rate_limits)),
             )
             .with_state(self.claude_providers.web());
         self.inner = self.inner.merge(router);
@@ -149,15 +234,72 @@ impl RouterBuilder {
             .route("/code/v1/models", get(api_get_models))
             .layer(
                 ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
                     .layer(from_extractor::<RequireBearerAuth>())
                     .layer(CompressionLayer::new())
-                    .layer(map_response(to_oai)),
+                    .layer(map_response(to_oai))
+                    .layer(map_response(apply_stop_sequences))
+                    .layer(map_response(check_overloaded))
+                    .layer(from_fn(enforce_rate_limits)),
             )
-            .with_state(self.claude_providers.code());
+            .with_state(self.claude_providers.to_owned());
         self.inner = self.inner.merge(router);
         self
     }
 
+    /// Sets up routes for the Gemini backend, in Claude and OpenAI format
+    fn route_gemini_endpoints(mut self) -> Self {
+        let router = Router::new()
+            .route("/gemini/v1/messages", post(api_gemini))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(from_extractor::<RequireFlexibleAuth>())
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(enforce_rate_limits)),
+            )
+            .with_state(self.gemini_provider.to_owned());
+        let oai_router = Router::new()
+            .route("/gemini/v1/chat/completions", post(api_gemini))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
+                    .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(CompressionLayer::new())
+                    .layer(map_response(to_oai))
+                    .layer(from_fn(enforce_rate_limits)),
+            )
+            .with_state(self.gemini_provider.to_owned());
+        self.inner = self.inner.merge(router).merge(oai_router);
+        self
+    }
+
+    /// Sets up routes for the Anthropic API key backend, in Claude and OpenAI format
+    fn route_claude_api_endpoints(mut self) -> Self {
+        let router = Router::new()
+            .route("/api/v1/messages", post(api_claude_api))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(from_extractor::<RequireFlexibleAuth>())
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(enforce_rate_limits)),
+            )
+            .with_state(self.claude_providers.api());
+        let oai_router = Router::new()
+            .route("/api/v1/chat/completions", post(api_claude_api))
+            .route("/api/v1/models", get(api_get_models))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
+                    .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(CompressionLayer::new())
+                    .layer(map_response(to_oai))
+                    .layer(from_fn(enforce_rate_limits)),
+            )
+            .with_state(self.claude_providers.api());
+        self.inner = self.inner.merge(router).merge(oai_router);
+        self
+    }
+
     /// Sets up static file serving
     fn setup_static_serving(mut self) -> Self {
         #[cfg(feature = "embed-resource")]
@@ -0,0 +1,415 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        LazyLock, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{body::Body, response::Response};
+use chrono::Utc;
+use futures::StreamExt;
+use http::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::CLEWDR_CONFIG;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Process wide request audit log
+pub static AUDIT_LOG: LazyLock<AuditLog> = LazyLock::new(AuditLog::default);
+
+/// One proxied request
+#[derive(Debug, Serialize, Clone)]
+pub struct AuditEntry {
+    pub id: u64,
+    /// Unix timestamp (UTC) at which the request arrived
+    pub timestamp: i64,
+    /// Client API key, shortened
+    pub api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_label: Option<String>,
+    #[serde(skip)]
+    key: Option<String>,
+    pub endpoint: &'static str,
+    /// Backend that served the request, which differs from the endpoint after a fallback
+    pub backend: Option<String>,
+    pub format: String,
+    pub model: String,
+    pub stream: bool,
+    /// Cookie or upstream key used by the last attempt, shortened
+    pub cookie: Option<String>,
+    pub retries: usize,
+    /// `pending` until the response starts, then `success` or the error name
+    pub status: String,
+    /// Time until the response started
+    pub latency_ms: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_body: Option<String>,
+}
+
+/// Request attributes known when a provider picks up a request
+pub struct AuditRequest<'a> {
+    pub endpoint: &'static str,
+    pub format: String,
+    pub model: &'a str,
+    pub stream: bool,
+    pub api_key: Option<&'a str>,
+}
+
+/// Filters and pagination of `/api/logs`
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct AuditQuery {
+    /// Full key, shortened key or label
+    pub key: Option<String>,
+    pub endpoint: Option<String>,
+    /// Matches model names containing this string
+    pub model: Option<String>,
+    pub status: Option<String>,
+    /// Unix timestamp, inclusive
+    pub since: Option<i64>,
+    /// Unix timestamp, exclusive
+    pub until: Option<i64>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, e: &AuditEntry) -> bool {
+        self.key.as_ref().is_none_or(|k| {
+            e.key.as_ref() == Some(k)
+                || e.api_key.as_ref() == Some(k)
+                || e.key_label.as_ref() == Some(k)
+        }) && self
+            .endpoint
+            .as_ref()
+            .is_none_or(|v| e.endpoint == v.as_str())
+            && self
+                .model
+                .as_ref()
+                .is_none_or(|v| e.model.contains(v.as_str()))
+            && self.status.as_ref().is_none_or(|v| e.status == *v)
+            && self.since.is_none_or(|v| e.timestamp >= v)
+            && self.until.is_none_or(|v| e.timestamp < v)
+    }
+}
+
+/// A page of audit entries, newest first
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Cuts `text` to at most `limit` bytes on a char boundary
+fn truncate(text: String, limit: usize) -> String {
+    let total = text.len();
+    truncate_to(text, total, limit)
+}
+
+/// Cuts the start of a `total` bytes long text at `limit` bytes
+fn truncate_to(mut text: String, total: usize, limit: usize) -> String {
+    if total <= limit {
+        return text;
+    }
+    let mut end = limit.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text.push_str(&format!("...[truncated {} bytes]", total - end));
+    text
+}
+
+/// Drops entries beyond `max_entries` or older than `retention_secs` (0 keeps them)
+fn prune_entries(
+    entries: &mut VecDeque<AuditEntry>,
+    max_entries: usize,
+    retention_secs: i64,
+    now: i64,
+) {
+    while entries.len() > max_entries {
+        entries.pop_front();
+    }
+    if retention_secs > 0 {
+        while entries
+            .front()
+            .is_some_and(|e| e.timestamp < now - retention_secs)
+        {
+            entries.pop_front();
+        }
+    }
+}
+
+/// In-memory ring buffer of recent requests
+///
+/// Entries are created when a provider picks up a request and completed as the
+/// request progresses, tokens are only known once the response has been consumed.
+#[derive(Default)]
+pub struct AuditLog {
+    entries: Mutex<VecDeque<AuditEntry>>,
+    next_id: AtomicU64,
+}
+
+impl AuditLog {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<AuditEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Opens an entry, returns its id or `None` when auditing is disabled
+    pub fn start(&self, request: AuditRequest<'_>, body: &impl Serialize) -> Option<u64> {
+        let config = CLEWDR_CONFIG.load();
+        if !config.audit_log {
+            return None;
+        }
+        let key = request
+            .api_key
+            .and_then(|k| config.api_keys.iter().find(|a| a.key == k));
+        let request_body = config
+            .audit_log_bodies
+            .then(|| serde_json::to_string(body).ok())
+            .flatten()
+            .map(|b| truncate(b, config.audit_body_limit));
+        let now = Utc::now().timestamp();
+        let mut entry = AuditEntry {
+            id: 0,
+            timestamp: now,
+            api_key: key.map(|k| k.ellipse()),
+            key_label: key.map(|k| k.label.to_owned()).filter(|l| !l.is_empty()),
+            key: request.api_key.map(str::to_string),
+            endpoint: request.endpoint,
+            backend: None,
+            format: request.format,
+            model: request.model.to_string(),
+            stream: request.stream,
+            cookie: None,
+            retries: 0,
+            status: "pending".to_string(),
+            latency_ms: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            request_body,
+            response_body: None,
+        };
+        let mut entries = self.lock();
+        // allocated under the lock so entries stay sorted by id for `update`
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        entry.id = id;
+        entries.push_back(entry);
+        prune_entries(
+            &mut entries,
+            config.audit_max_entries,
+            config.audit_retention_hours as i64 * 3600,
+            now,
+        );
+        Some(id)
+    }
+
+    fn update(&self, id: Option<u64>, f: impl FnOnce(&mut AuditEntry)) {
+        let Some(id) = id else {
+            return;
+        };
+        let mut entries = self.lock();
+        // ids are increasing, entries may only have been pruned from the front
+        if let Ok(i) = entries.binary_search_by_key(&id, |e| e.id) {
+            f(&mut entries[i]);
+        }
+    }
+
+    /// Records the attempt number and the shortened credential it was dispatched to
+    pub fn record_attempt(&self, id: Option<u64>, attempt: usize, credential: String) {
+        self.update(id, |e| {
+            e.retries = attempt;
+            e.cookie = Some(credential);
+        });
+    }
+
+    /// Records the outcome and latency once the response started
+    pub fn finish(&self, id: Option<u64>, status: &str, elapsed: Duration) {
+        self.update(id, |e| {
+            e.status = status.to_string();
+            e.latency_ms = elapsed.as_millis() as u64;
+        });
+    }
+
+    pub fn record_backend(&self, id: Option<u64>, backend: &str) {
+        self.update(id, |e| e.backend = Some(backend.to_string()));
+    }
+
+    pub fn record_tokens(&self, id: Option<u64>, input: u64, output: u64) {
+        self.update(id, |e| {
+            e.input_tokens = input;
+            e.output_tokens = output;
+        });
+    }
+
+    /// Records the body of a response when body logging is enabled
+    ///
+    /// Event streams are copied as they are sent, up to `audit_body_limit` bytes, and attached
+    /// to the entry once the stream ends.
+    pub async fn capture_response(&'static self, id: Option<u64>, response: Response) -> Response {
+        let limit = {
+            let config = CLEWDR_CONFIG.load();
+            if id.is_none() || !config.audit_log_bodies {
+                return response;
+            }
+            config.audit_body_limit
+        };
+        let stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/event-stream"));
+        let (parts, body) = response.into_parts();
+        if !stream {
+            let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    warn!("Failed to read response body for the audit log: {}", e);
+                    return Response::from_parts(parts, Body::empty());
+                }
+            };
+            let text = truncate(String::from_utf8_lossy(&bytes).into_owned(), limit);
+            self.update(id, |e| e.response_body = Some(text));
+            return Response::from_parts(parts, Body::from(bytes));
+        }
+        let body = async_stream::stream! {
+            let mut data = body.into_data_stream();
+            let mut recorded = vec![];
+            let mut total = 0;
+            while let Some(chunk) = data.next().await {
+                if let Ok(ref bytes) = chunk {
+                    total += bytes.len();
+                    // a few bytes past the limit let the cut land on a char boundary
+                    let room = (limit + 3).saturating_sub(recorded.len());
+                    recorded.extend_from_slice(&bytes[..bytes.len().min(room)]);
+                }
+                yield chunk;
+            }
+            let text = truncate_to(String::from_utf8_lossy(&recorded).into_owned(), total, limit);
+            self.update(id, |e| e.response_body = Some(text));
+        };
+        Response::from_parts(parts, Body::from_stream(body))
+    }
+
+    /// Drops entries that fell out of the configured retention
+    pub fn prune(&self) {
+        let config = CLEWDR_CONFIG.load();
+        prune_entries(
+            &mut self.lock(),
+            config.audit_max_entries,
+            config.audit_retention_hours as i64 * 3600,
+            Utc::now().timestamp(),
+        );
+    }
+
+    /// Returns matching entries, newest first
+    pub fn query(&self, query: &AuditQuery) -> AuditPage {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let entries = self.lock();
+        let matched = entries
+            .iter()
+            .rev()
+            .filter(|e| query.matches(e))
+            .collect::<Vec<_>>();
+        AuditPage {
+            total: matched.len(),
+            offset: query.offset,
+            limit,
+            entries: matched
+                .into_iter()
+                .skip(query.offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u64, timestamp: i64, model: &str) -> AuditEntry {
+        AuditEntry {
+            id,
+            timestamp,
+            api_key: None,
+            key_label: None,
+            key: Some("sk-clewdr-test".to_string()),
+            endpoint: "code",
+            backend: None,
+            format: "Claude".to_string(),
+            model: model.to_string(),
+            stream: false,
+            cookie: None,
+            retries: 0,
+            status: "success".to_string(),
+            latency_ms: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            request_body: None,
+            response_body: None,
+        }
+    }
+
+    #[test]
+    fn prunes_and_pages_newest_first() {
+        let log = AuditLog::default();
+        {
+            let mut entries = log.lock();
+            entries.extend((1..=5).map(|i| entry(i, i as i64 * 100, "claude-sonnet-4-5")));
+            entries.push_back(entry(6, 600, "claude-opus-4-1"));
+            // the size limit drops entries 1 and 2, the retention entry 3
+            prune_entries(&mut entries, 4, 350, 651);
+            assert_eq!(entries.front().map(|e| e.id), Some(4));
+        }
+
+        let page = log.query(&AuditQuery {
+            model: Some("sonnet".to_string()),
+            limit: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 2);
+        assert_eq!(
+            page.entries.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![5, 4]
+        );
+
+        let page = log.query(&AuditQuery {
+            key: Some("sk-clewdr-test".to_string()),
+            since: Some(600),
+            ..Default::default()
+        });
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].model, "claude-opus-4-1");
+    }
+
+    #[test]
+    fn truncates_on_char_boundary() {
+        let text = truncate("héllo".to_string(), 2);
+        assert_eq!(text, "h...[truncated 5 bytes]");
+    }
+
+    #[test]
+    fn truncates_a_partly_recorded_stream() {
+        // only the start of a 12 bytes stream was recorded
+        let recorded = String::from_utf8_lossy(&"héllo world".as_bytes()[..8]).into_owned();
+        assert_eq!(truncate_to(recorded, 12, 2), "h...[truncated 11 bytes]");
+        let recorded = "héllo".to_string();
+        assert_eq!(truncate_to(recorded, 6, 6), "héllo");
+    }
+}
@@ -1,16 +1,24 @@
-use std::collections::{HashSet, VecDeque};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
 
 use chrono::Utc;
 use colored::Colorize;
 use moka::sync::Cache;
 use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
+use rand::Rng;
 use serde::Serialize;
 use snafu::{GenerateImplicitData, Location};
 use tracing::{error, info, warn};
 
 use crate::{
-    config::{CLEWDR_CONFIG, ClewdrConfig, CookieStatus, Reason, UsageBreakdown, UselessCookie},
+    config::{
+        CLEWDR_CONFIG, ClewdrConfig, CookieStatus, CookieStrategy, ModelFamily, Reason, TokenInfo,
+        UsageBreakdown, UselessCookie,
+    },
     error::ClewdrError,
+    persistence::{self, CookieChange},
 };
 
 const INTERVAL: u64 = 300;
@@ -24,23 +32,59 @@ pub struct CookieStatusInfo {
     pub invalid: Vec<UselessCookie>,
 }
 
+/// Parameters of a cookie request
+#[derive(Debug, Clone, Default)]
+pub struct CookieRequest {
+    /// Hash of the system prompt, used to stick to the same cookie for cache hits
+    pub cache_hash: Option<u64>,
+    /// Model family of the request, cookies on cooldown for it are skipped
+    pub family: ModelFamily,
+    /// Cookie group the request is bound to, cookies of other groups are skipped
+    pub group: Option<String>,
+    /// Whether the request needs a claude.ai session, accounts added through OAuth are skipped
+    pub session: bool,
+}
+
+/// Reply of a cookie request, the cookie and the id of its lease
+type LeaseReply = RpcReplyPort<Result<(CookieStatus, u64), ClewdrError>>;
+
 /// Messages that the CookieActor can handle
 #[derive(Debug)]
 enum CookieActorMessage {
-    /// Return a Cookie
-    Return(CookieStatus, Option<Reason>),
+    /// Update a Cookie, optionally with the reason it failed
+    Update(CookieStatus, Option<Reason>),
+    /// Record the outcome of a health check on a Cookie
+    Checked(CookieStatus),
+    /// Store the token of a Cookie obtained or refreshed outside of a lease
+    Token(CookieStatus),
+    /// Store the utilization snapshot of a Cookie
+    Utilization(CookieStatus),
+    /// Release a lease, freeing a concurrent use of its Cookie
+    Release(u64),
     /// Submit a new Cookie
     Submit(CookieStatus),
+    /// Import a batch of Cookies, replying whether each one was added
+    Import(Vec<CookieStatus>, RpcReplyPort<Vec<bool>>),
     /// Check for timed out Cookies
     CheckReset,
-    /// Request to get a Cookie
-    Request(Option<u64>, RpcReplyPort<Result<CookieStatus, ClewdrError>>),
+    /// Request to lease a Cookie
+    Request(CookieRequest, LeaseReply),
+    /// Fail queued requests that waited too long
+    ExpireWaiters,
     /// Get all Cookie status information
     GetStatus(RpcReplyPort<CookieStatusInfo>),
     /// Delete a Cookie
     Delete(CookieStatus, RpcReplyPort<Result<(), ClewdrError>>),
-    /// Update 1M support flags on an existing cookie
-    Update1mSupport(CookieStatus, RpcReplyPort<Result<(), ClewdrError>>),
+    /// Update the settings and metadata of an existing cookie
+    UpdateSettings(CookieStatus, RpcReplyPort<Result<(), ClewdrError>>),
+}
+
+/// A request queued until a cookie has a free slot
+#[derive(Debug)]
+struct Waiter {
+    request: CookieRequest,
+    reply: LeaseReply,
+    deadline: Instant,
 }
 
 /// CookieActor state - manages collections of cookies
@@ -50,6 +94,93 @@ struct CookieActorState {
     exhausted: HashSet<CookieStatus>,
     invalid: HashSet<UselessCookie>,
     moka: Cache<u64, CookieStatus>,
+    /// Cookies of the leases in flight, by lease id
+    leases: HashMap<u64, CookieStatus>,
+    next_lease: u64,
+    /// Requests waiting for a free slot, in arrival order
+    waiting: VecDeque<Waiter>,
+}
+
+impl CookieActorState {
+    /// Number of leases in flight for the cookie
+    fn in_flight(&self, cookie: &CookieStatus) -> usize {
+        self.leases.values().filter(|c| *c == cookie).count()
+    }
+
+    /// Whether the cookie can take another concurrent request
+    fn has_free_slot(&self, cookie: &CookieStatus) -> bool {
+        let max = CLEWDR_CONFIG.load().cookie_max_concurrency;
+        max == 0 || self.in_flight(cookie) < max
+    }
+}
+
+/// Whether `token` expires later than `than`, a missing token being the oldest
+fn is_newer(token: &Option<TokenInfo>, than: &Option<TokenInfo>) -> bool {
+    match (token, than) {
+        (Some(token), Some(than)) => token.expires_at >= than.expires_at,
+        (token, than) => token.is_some() || than.is_none(),
+    }
+}
+
+/// Picks the index of the cookie to dispatch from `valid` according to `strategy`
+///
+/// Only cookies accepted by `available` are considered. Returns `None` when no
+/// cookie can be picked.
+fn select_cookie(
+    valid: &[CookieStatus],
+    strategy: CookieStrategy,
+    available: impl Fn(&CookieStatus) -> bool,
+) -> Option<usize> {
+    fn tokens(u: &UsageBreakdown) -> i64 {
+        (u.total_input_tokens + u.total_output_tokens) as i64
+    }
+    let candidates = valid
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| available(c))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return None;
+    }
+    let min_index_by_key = |key: fn(&CookieStatus) -> i64| {
+        candidates
+            .iter()
+            .min_by_key(|(_, c)| key(c))
+            .map(|(i, _)| *i)
+    };
+    match strategy {
+        CookieStrategy::RoundRobin => Some(candidates[0].0),
+        CookieStrategy::LeastRecentlyUsed => {
+            min_index_by_key(|c| c.last_used_at.unwrap_or(i64::MIN))
+        }
+        CookieStrategy::LowestSessionUsage => min_index_by_key(|c| tokens(&c.session_usage)),
+        CookieStrategy::LowestWeeklyUsage => min_index_by_key(|c| tokens(&c.weekly_usage)),
+        CookieStrategy::Weighted => {
+            let total = candidates
+                .iter()
+                .map(|(_, c)| c.weight() as u64)
+                .sum::<u64>();
+            if total == 0 {
+                // every weight is zero, fall back to a uniform pick
+                let pick = rand::rng().random_range(0..candidates.len());
+                return Some(candidates[pick].0);
+            }
+            let mut point = rand::rng().random_range(0..total);
+            candidates.iter().find_map(|(i, c)| {
+                let weight = c.weight() as u64;
+                if point < weight {
+                    Some(*i)
+                } else {
+                    point -= weight;
+                    None
+                }
+            })
+        }
+        CookieStrategy::Random => {
+            let pick = rand::rng().random_range(0..candidates.len());
+            Some(candidates[pick].0)
+        }
+    }
 }
 
 /// Cookie actor that handles cookie distribution, collection, and status tracking using Ractor
@@ -57,7 +188,10 @@ struct CookieActor;
 
 impl CookieActor {
     /// Saves the current state of cookies to the configuration
-    fn save(state: &CookieActorState) {
+    ///
+    /// `changes` lists the cookies touched since the last save,
+    /// so the storage backend can persist them incrementally
+    fn save(state: &CookieActorState, changes: Vec<CookieChange>) {
         CLEWDR_CONFIG.rcu(|config| {
             let mut config = ClewdrConfig::clone(config);
             config.cookie_array = state
@@ -70,16 +204,29 @@ impl CookieActor {
             config
         });
 
-        // Persist config file/DB config row only（不再全量重写 cookies）
+        if CLEWDR_CONFIG.load().no_fs {
+            return;
+        }
         tokio::spawn(async move {
-            let result = CLEWDR_CONFIG.load().save().await;
+            let result = persistence::storage().save_cookies(changes).await;
             match result {
-                Ok(_) => info!("Configuration saved successfully"),
-                Err(e) => error!("Save task panicked: {}", e),
+                Ok(_) => info!("Cookies saved successfully"),
+                Err(e) => error!("Failed to save cookies: {}", e),
             }
         });
     }
 
+    /// Upserts of every valid and exhausted cookie
+    fn upsert_all(state: &CookieActorState) -> Vec<CookieChange> {
+        state
+            .valid
+            .iter()
+            .chain(state.exhausted.iter())
+            .cloned()
+            .map(CookieChange::Upsert)
+            .collect()
+    }
+
     /// Logs the current state of cookie collections
     fn log(state: &CookieActorState) {
         info!(
@@ -92,6 +239,10 @@ impl CookieActor {
 
     /// Checks and resets cookies that have passed their reset time
     fn reset(state: &mut CookieActorState) {
+        let now = Utc::now().timestamp();
+        for cookie in state.valid.iter_mut() {
+            cookie.clear_expired_family_cooldowns(now);
+        }
         let mut reset_cookies = Vec::new();
         state.exhausted.retain(|cookie| {
             let reset_cookie = cookie.clone().reset();
@@ -106,9 +257,15 @@ impl CookieActor {
             return;
         }
         // 将重置的 cookies 放回 valid，并进行增量 upsert
+        let changes = reset_cookies
+            .iter()
+            .cloned()
+            .map(CookieChange::Upsert)
+            .collect();
         for c in reset_cookies.into_iter() {
             state.valid.push_back(c.clone());
         }
+        Self::save(state, changes);
         Self::log(state);
     }
 
@@ -181,91 +338,276 @@ impl CookieActor {
         changed
     }
 
-    /// Dispatches a cookie for use
+    /// Dispatches a cookie for use and leases it
+    ///
+    /// Requests carrying a cache hash stick to the cookie they used before, others
+    /// are assigned one according to the configured [`CookieStrategy`]. Cookies on
+    /// cooldown for the requested model family are skipped, and so are cookies over the
+    /// utilization thresholds or already serving `cookie_max_concurrency` requests.
+    ///
+    /// # Returns
+    /// * `Ok(Some(_))` - The cookie and the id of its lease
+    /// * `Ok(None)` - Every usable cookie is saturated, the request has to wait
+    /// * `Err(_)` - No cookie can serve the request
     fn dispatch(
-        &self,
         state: &mut CookieActorState,
-        hash: Option<u64>,
-    ) -> Result<CookieStatus, ClewdrError> {
+        request: &CookieRequest,
+    ) -> Result<Option<(CookieStatus, u64)>, ClewdrError> {
         Self::reset(state);
-        if let Some(hash) = hash
+        let now = Utc::now().timestamp();
+        let config = CLEWDR_CONFIG.load();
+        let usable = |c: &CookieStatus| {
+            c.in_group(request.group.as_deref())
+                && (!request.session || c.cookie.is_session())
+                && c.available_for(request.family, now)
+                && c.under_thresholds(request.family, &config, now)
+        };
+        if !state.valid.iter().any(usable) {
+            return Err(ClewdrError::NoCookieAvailable);
+        }
+        let free = state
+            .valid
+            .iter()
+            .filter(|c| usable(c) && state.has_free_slot(c))
+            .cloned()
+            .collect::<HashSet<_>>();
+        if free.is_empty() {
+            return Ok(None);
+        }
+        let available = |c: &CookieStatus| free.contains(c);
+        if let Some(hash) = request.cache_hash
             && let Some(cookie) = state.moka.get(&hash)
-            && let Some(cookie) = state.valid.iter().find(|&c| c == &cookie)
+            && let Some(cookie) = state
+                .valid
+                .iter_mut()
+                .find(|c| **c == cookie && available(&**c))
         {
+            cookie.last_used_at = Some(now);
+            let cookie = cookie.clone();
             // renew moka cache
             state.moka.insert(hash, cookie.clone());
-            return Ok(cookie.clone());
+            return Ok(Some(Self::lease(state, cookie)));
         }
-        let cookie = state
-            .valid
-            .pop_front()
+        let strategy = config.cookie_strategy;
+        let index = select_cookie(state.valid.make_contiguous(), strategy, available)
             .ok_or(ClewdrError::NoCookieAvailable)?;
-        state.valid.push_back(cookie.clone());
-        if let Some(hash) = hash {
+        let cookie = if strategy == CookieStrategy::RoundRobin {
+            // rotate the picked cookie to the back of the queue
+            let mut cookie = state
+                .valid
+                .remove(index)
+                .ok_or(ClewdrError::NoCookieAvailable)?;
+            cookie.last_used_at = Some(now);
+            state.valid.push_back(cookie.clone());
+            cookie
+        } else {
+            let cookie = &mut state.valid[index];
+            cookie.last_used_at = Some(now);
+            cookie.clone()
+        };
+        if let Some(hash) = request.cache_hash {
             state.moka.insert(hash, cookie.clone());
         }
-        Ok(cookie)
+        Ok(Some(Self::lease(state, cookie)))
+    }
+
+    fn lease(state: &mut CookieActorState, cookie: CookieStatus) -> (CookieStatus, u64) {
+        let id = state.next_lease;
+        state.next_lease += 1;
+        state.leases.insert(id, cookie.clone());
+        (cookie, id)
+    }
+
+    /// Sends a lease to its requester, the lease is released if the requester is gone
+    fn reply(state: &mut CookieActorState, reply: LeaseReply, lease: (CookieStatus, u64)) {
+        let id = lease.1;
+        if reply.send(Ok(lease)).is_err() {
+            state.leases.remove(&id);
+        }
+    }
+
+    /// Hands out leases to queued requests, in arrival order, while cookies have free slots
+    fn serve_waiters(state: &mut CookieActorState) {
+        let mut pending = VecDeque::with_capacity(state.waiting.len());
+        while let Some(waiter) = state.waiting.pop_front() {
+            if waiter.reply.is_closed() {
+                continue;
+            }
+            match Self::dispatch(state, &waiter.request) {
+                Ok(Some(lease)) => Self::reply(state, waiter.reply, lease),
+                Ok(None) => pending.push_back(waiter),
+                Err(e) => {
+                    let _ = waiter.reply.send(Err(e));
+                }
+            }
+        }
+        state.waiting = pending;
+    }
+
+    /// Fails queued requests whose deadline has passed
+    fn expire_waiters(state: &mut CookieActorState) {
+        let now = Instant::now();
+        let (expired, waiting) = state
+            .waiting
+            .drain(..)
+            .partition::<VecDeque<_>, _>(|w| w.deadline <= now);
+        state.waiting = waiting;
+        for waiter in expired {
+            warn!("Timed out waiting for a free cookie");
+            let _ = waiter.reply.send(Err(ClewdrError::NoCookieAvailable));
+        }
     }
 
     /// Collects a returned cookie and processes it based on the return reason
     fn collect(state: &mut CookieActorState, mut cookie: CookieStatus, reason: Option<Reason>) {
         let Some(reason) = reason else {
             if let Some(existing) = state.valid.iter_mut().find(|c| **c == cookie) {
-                *existing = cookie;
-                Self::save(state);
+                // a lease taken before a refresh must not bring back the replaced token
+                if !is_newer(&cookie.token, &existing.token) {
+                    cookie.token = existing.token.to_owned();
+                }
+                let fetched_at = |c: &CookieStatus| c.utilization.as_ref().map(|u| u.fetched_at);
+                if fetched_at(existing) > fetched_at(&cookie) {
+                    cookie.utilization = existing.utilization.to_owned();
+                }
+                // settings are only changed by the admin, maybe while the cookie was leased
+                cookie.weight = existing.weight;
+                cookie.label = existing.label.take();
+                cookie.notes = existing.notes.take();
+                cookie.owner = existing.owner.take();
+                cookie.group = existing.group.take();
+                *existing = cookie.clone();
+                Self::save(state, vec![CookieChange::Upsert(cookie)]);
             }
             return;
         };
+        // also drops a stale exhausted entry, e.g. of a cookie found banned by a health check
         let mut find_remove = |cookie: &CookieStatus| {
             state.valid.retain(|c| c != cookie);
+            state.exhausted.remove(cookie);
         };
-        match reason {
+        let changes = match reason {
             Reason::NormalPro => {
                 return;
             }
-            Reason::TooManyRequest(i) => {
-                find_remove(&cookie);
-                cookie.reset_time = Some(i);
-                cookie.reset_window_usage();
-                if !state.exhausted.insert(cookie) {
+            Reason::TooManyRequestFamily(family, i) => {
+                // only this family is limited, keep the cookie for the others
+                let Some(existing) = state.valid.iter_mut().find(|c| **c == cookie) else {
                     return;
-                }
+                };
+                existing.set_family_cooldown(family, i);
+                vec![CookieChange::Upsert(existing.clone())]
             }
-            Reason::Restricted(i) => {
-                find_remove(&cookie);
-                cookie.reset_time = Some(i);
-                cookie.reset_window_usage();
-                if !state.exhausted.insert(cookie) {
+            Reason::TooManyRequest(i) | Reason::Restricted(i) => {
+                // only the cooldown is taken from the returned snapshot, the stored entry
+                // may hold a token refreshed in the meantime
+                let stored = match state.valid.iter().position(|c| *c == cookie) {
+                    Some(index) => state.valid.remove(index),
+                    None => state.exhausted.take(&cookie),
+                };
+                let Some(mut stored) = stored else {
                     return;
-                }
+                };
+                stored.reset_time = Some(i);
+                stored.reset_window_usage();
+                state.exhausted.insert(stored.clone());
+                vec![CookieChange::Upsert(stored)]
             }
             Reason::Free => {
                 find_remove(&cookie);
                 let mut removed = cookie.clone();
                 removed.reset_window_usage();
-                if !state
-                    .invalid
-                    .insert(UselessCookie::new(removed.cookie.clone(), reason))
-                {
+                let useless = UselessCookie::new(removed.cookie.clone(), reason);
+                if !state.invalid.insert(useless.clone()) {
                     return;
                 }
+                vec![
+                    CookieChange::Delete(cookie.cookie),
+                    CookieChange::UpsertWasted(useless),
+                ]
             }
             _ => {
                 find_remove(&cookie);
                 let mut removed = cookie.clone();
                 removed.reset_window_usage();
-                if !state
-                    .invalid
-                    .insert(UselessCookie::new(removed.cookie.clone(), reason))
-                {
+                let useless = UselessCookie::new(removed.cookie.clone(), reason);
+                if !state.invalid.insert(useless.clone()) {
                     return;
                 }
+                vec![
+                    CookieChange::Delete(cookie.cookie),
+                    CookieChange::UpsertWasted(useless),
+                ]
             }
-        }
-        Self::save(state);
+        };
+        Self::save(state, changes);
         Self::log(state);
     }
 
+    /// Records the health check fields and the token of a checked cookie, unless the actor
+    /// already holds a newer token
+    ///
+    /// Only these fields are taken from the checked snapshot, usage recorded by requests
+    /// in the meantime is kept.
+    fn record_check(state: &mut CookieActorState, cookie: CookieStatus) {
+        let merge = |existing: &mut CookieStatus| {
+            existing.last_checked_at = cookie.last_checked_at;
+            existing.last_error = cookie.last_error.to_owned();
+            // a check started before a refresh must not bring back the replaced token
+            if cookie.token.is_some() && is_newer(&cookie.token, &existing.token) {
+                existing.token = cookie.token.to_owned();
+            }
+        };
+        let updated = if let Some(existing) = state.valid.iter_mut().find(|c| **c == cookie) {
+            merge(existing);
+            existing.clone()
+        } else if let Some(mut existing) = state.exhausted.take(&cookie) {
+            merge(&mut existing);
+            state.exhausted.insert(existing.clone());
+            existing
+        } else {
+            return;
+        };
+        Self::save(state, vec![CookieChange::Upsert(updated)]);
+    }
+
+    /// Stores the token of a cookie, unless the actor already holds a newer one
+    fn store_token(state: &mut CookieActorState, cookie: CookieStatus) {
+        let update = |existing: &mut CookieStatus| {
+            if !is_newer(&cookie.token, &existing.token) {
+                return false;
+            }
+            existing.token = cookie.token.to_owned();
+            true
+        };
+        let updated = if let Some(existing) = state.valid.iter_mut().find(|c| **c == cookie) {
+            if !update(existing) {
+                return;
+            }
+            existing.clone()
+        } else if let Some(mut existing) = state.exhausted.take(&cookie) {
+            let changed = update(&mut existing);
+            state.exhausted.insert(existing.clone());
+            if !changed {
+                return;
+            }
+            existing
+        } else {
+            return;
+        };
+        Self::save(state, vec![CookieChange::Upsert(updated)]);
+    }
+
+    /// Stores the utilization snapshot of a cookie, in memory only as it is polled again
+    fn store_utilization(state: &mut CookieActorState, cookie: CookieStatus) {
+        if let Some(existing) = state.valid.iter_mut().find(|c| **c == cookie) {
+            existing.utilization = cookie.utilization;
+        } else if let Some(mut existing) = state.exhausted.take(&cookie) {
+            existing.utilization = cookie.utilization;
+            state.exhausted.insert(existing);
+        }
+    }
+
     /// Accepts a new cookie into the valid collection
     fn accept(state: &mut CookieActorState, cookie: CookieStatus) {
         if CLEWDR_CONFIG.load().cookie_array.contains(&cookie)
@@ -278,11 +620,45 @@ impl CookieActor {
             warn!("Cookie already exists");
             return;
         }
-        state.valid.push_back(cookie);
-        Self::save(state);
+        state.valid.push_back(cookie.clone());
+        Self::save(state, vec![CookieChange::Upsert(cookie)]);
         Self::log(state);
     }
 
+    /// Imports cookies that are not known yet, in valid or, while on cooldown, in exhausted
+    ///
+    /// # Returns
+    /// * `Vec<bool>` - Whether each cookie was added, false for duplicates
+    fn import(state: &mut CookieActorState, cookies: Vec<CookieStatus>) -> Vec<bool> {
+        let now = Utc::now().timestamp();
+        let mut changes = vec![];
+        let added = cookies
+            .into_iter()
+            .map(|mut cookie| {
+                let useless = UselessCookie::new(cookie.cookie.clone(), Reason::Null);
+                if state.valid.contains(&cookie)
+                    || state.exhausted.contains(&cookie)
+                    || state.invalid.contains(&useless)
+                {
+                    return false;
+                }
+                if cookie.reset_time.is_some_and(|t| t > now) {
+                    state.exhausted.insert(cookie.clone());
+                } else {
+                    cookie.reset_time = None;
+                    state.valid.push_back(cookie.clone());
+                }
+                changes.push(CookieChange::Upsert(cookie));
+                true
+            })
+            .collect();
+        if !changes.is_empty() {
+            Self::save(state, changes);
+            Self::log(state);
+        }
+        added
+    }
+
     /// Creates a report of all cookie statuses
     fn report(state: &CookieActorState) -> CookieStatusInfo {
         CookieStatusInfo {
@@ -303,7 +679,13 @@ impl CookieActor {
         found |= state.exhausted.remove(&cookie) | state.invalid.remove(&useless);
 
         if found {
-            Self::save(state);
+            Self::save(
+                state,
+                vec![
+                    CookieChange::Delete(cookie.cookie.clone()),
+                    CookieChange::DeleteWasted(cookie.cookie),
+                ],
+            );
             Self::log(state);
             Ok(())
         } else {
@@ -313,34 +695,44 @@ impl CookieActor {
         }
     }
 
-    /// Updates 1M support flags for an existing cookie in valid/exhausted collections
-    fn update_1m_support(
+    /// Updates the settings and metadata of an existing cookie in valid/exhausted collections
+    ///
+    /// 1M support flags are always copied, weight and metadata only when given, an empty
+    /// string clears a metadata field.
+    fn update_settings(
         state: &mut CookieActorState,
         cookie: CookieStatus,
     ) -> Result<(), ClewdrError> {
-        if let Some(existing) = state.valid.iter_mut().find(|c| **c == cookie) {
+        let apply = |existing: &mut CookieStatus| {
             existing.supports_claude_1m_sonnet = cookie.supports_claude_1m_sonnet;
             existing.supports_claude_1m_opus = cookie.supports_claude_1m_opus;
-            Self::save(state);
+            if cookie.weight.is_some() {
+                existing.weight = cookie.weight;
+            }
+            for (field, value) in [
+                (&mut existing.label, &cookie.label),
+                (&mut existing.notes, &cookie.notes),
+                (&mut existing.owner, &cookie.owner),
+                (&mut existing.group, &cookie.group),
+            ] {
+                if let Some(value) = value {
+                    let value = value.trim();
+                    *field = (!value.is_empty()).then(|| value.to_string());
+                }
+            }
+        };
+        if let Some(existing) = state.valid.iter_mut().find(|c| **c == cookie) {
+            apply(existing);
+            let changes = vec![CookieChange::Upsert(existing.clone())];
+            Self::save(state, changes);
             return Ok(());
         }
 
-        if !state.exhausted.is_empty() {
-            let mut updated = false;
-            let mut new_exhausted = HashSet::with_capacity(state.exhausted.len());
-            for mut existing in state.exhausted.drain() {
-                if existing == cookie {
-                    existing.supports_claude_1m_sonnet = cookie.supports_claude_1m_sonnet;
-                    existing.supports_claude_1m_opus = cookie.supports_claude_1m_opus;
-                    updated = true;
-                }
-                new_exhausted.insert(existing);
-            }
-            state.exhausted = new_exhausted;
-            if updated {
-                Self::save(state);
-                return Ok(());
-            }
+        if let Some(mut existing) = state.exhausted.take(&cookie) {
+            apply(&mut existing);
+            state.exhausted.insert(existing.clone());
+            Self::save(state, vec![CookieChange::Upsert(existing)]);
+            return Ok(());
         }
 
         Err(ClewdrError::UnexpectedNone {
@@ -399,6 +791,9 @@ impl Actor for CookieActor {
             exhausted,
             invalid,
             moka,
+            leases: HashMap::new(),
+            next_lease: 0,
+            waiting: VecDeque::new(),
         };
 
         CookieActor::log(&state);
@@ -407,32 +802,68 @@ impl Actor for CookieActor {
 
     async fn handle(
         &self,
-        _myself: ActorRef<Self::Msg>,
+        myself: ActorRef<Self::Msg>,
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         match message {
-            CookieActorMessage::Return(cookie, reason) => {
+            CookieActorMessage::Update(cookie, reason) => {
                 Self::collect(state, cookie, reason);
             }
+            CookieActorMessage::Checked(cookie) => {
+                Self::record_check(state, cookie);
+            }
+            CookieActorMessage::Token(cookie) => {
+                Self::store_token(state, cookie);
+            }
+            CookieActorMessage::Utilization(cookie) => {
+                Self::store_utilization(state, cookie);
+            }
+            CookieActorMessage::Release(id) => {
+                if state.leases.remove(&id).is_some() {
+                    Self::serve_waiters(state);
+                }
+            }
             CookieActorMessage::Submit(cookie) => {
                 Self::accept(state, cookie);
+                Self::serve_waiters(state);
+            }
+            CookieActorMessage::Import(cookies, reply_port) => {
+                let added = Self::import(state, cookies);
+                Self::serve_waiters(state);
+                reply_port.send(added)?;
             }
             CookieActorMessage::CheckReset => {
                 let changed = Self::refresh_usage_windows(state);
                 if changed {
-                    Self::save(state);
+                    Self::save(state, Self::upsert_all(state));
                 }
                 Self::reset(state);
+                Self::serve_waiters(state);
             }
-            CookieActorMessage::Request(cache_hash, reply_port) => {
-                let result = self.dispatch(state, cache_hash);
-                reply_port.send(result)?;
+            CookieActorMessage::Request(request, reply_port) => {
+                match Self::dispatch(state, &request) {
+                    Ok(Some(lease)) => Self::reply(state, reply_port, lease),
+                    Ok(None) => {
+                        let timeout =
+                            Duration::from_secs(CLEWDR_CONFIG.load().cookie_queue_timeout);
+                        state.waiting.push_back(Waiter {
+                            request,
+                            reply: reply_port,
+                            deadline: Instant::now() + timeout,
+                        });
+                        myself.send_after(timeout, || CookieActorMessage::ExpireWaiters);
+                    }
+                    Err(e) => reply_port.send(Err(e))?,
+                }
+            }
+            CookieActorMessage::ExpireWaiters => {
+                Self::expire_waiters(state);
             }
             CookieActorMessage::GetStatus(reply_port) => {
                 let changed = Self::refresh_usage_windows(state);
                 if changed {
-                    Self::save(state);
+                    Self::save(state, Self::upsert_all(state));
                 }
                 let status_info = Self::report(state);
                 reply_port.send(status_info)?;
@@ -441,8 +872,8 @@ impl Actor for CookieActor {
                 let result = Self::delete(state, cookie.clone());
                 reply_port.send(result)?;
             }
-            CookieActorMessage::Update1mSupport(cookie, reply_port) => {
-                let result = Self::update_1m_support(state, cookie);
+            CookieActorMessage::UpdateSettings(cookie, reply_port) => {
+                let result = Self::update_settings(state, cookie);
                 reply_port.send(result)?;
             }
         }
@@ -454,11 +885,29 @@ impl Actor for CookieActor {
         _myself: ActorRef<Self::Msg>,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
-        CookieActor::save(state);
+        CookieActor::save(state, CookieActor::upsert_all(state));
         Ok(())
     }
 }
 
+/// A cookie leased for one request
+///
+/// The lease takes one of the `cookie_max_concurrency` slots of the cookie until it is
+/// dropped. Cookie updates are still sent with [`CookieActorHandle::return_cookie`].
+pub struct CookieLease {
+    pub cookie: CookieStatus,
+    id: u64,
+    actor_ref: ActorRef<CookieActorMessage>,
+}
+
+impl Drop for CookieLease {
+    fn drop(&mut self) {
+        if let Err(e) = ractor::cast!(self.actor_ref, CookieActorMessage::Release(self.id)) {
+            error!("Failed to release cookie lease: {}", e);
+        }
+    }
+}
+
 /// Handle for interacting with the CookieActor
 #[derive(Clone)]
 pub struct CookieActorHandle {
@@ -493,23 +942,31 @@ impl CookieActorHandle {
         });
     }
 
-    /// Request a cookie from the cookie actor
-    pub async fn request(&self, cache_hash: Option<u64>) -> Result<CookieStatus, ClewdrError> {
-        ractor::call!(self.actor_ref, CookieActorMessage::Request, cache_hash).map_err(|e| {
-            ClewdrError::RactorError {
-                loc: Location::generate(),
-                msg: format!("Failed to communicate with CookieActor for request operation: {e}"),
-            }
-        })?
+    /// Lease a cookie from the cookie actor
+    ///
+    /// When every usable cookie is saturated, waits up to `cookie_queue_timeout` seconds
+    /// for one to be released.
+    pub async fn request(&self, request: CookieRequest) -> Result<CookieLease, ClewdrError> {
+        let (cookie, id) = ractor::call!(self.actor_ref, CookieActorMessage::Request, request)
+            .map_err(|e| ClewdrError::RactorError {
+            loc: Location::generate(),
+            msg: format!("Failed to communicate with CookieActor for request operation: {e}"),
+        })??;
+        Ok(CookieLease {
+            cookie,
+            id,
+            actor_ref: self.actor_ref.clone(),
+        })
     }
 
-    /// Return a cookie to the cookie actor
+    /// Send an updated cookie to the cookie actor
+    /// Optionally provides a reason the cookie failed (e.g., rate limited, invalid)
     pub async fn return_cookie(
         &self,
         cookie: CookieStatus,
         reason: Option<Reason>,
     ) -> Result<(), ClewdrError> {
-        ractor::cast!(self.actor_ref, CookieActorMessage::Return(cookie, reason)).map_err(|e| {
+        ractor::cast!(self.actor_ref, CookieActorMessage::Update(cookie, reason)).map_err(|e| {
             ClewdrError::RactorError {
                 loc: Location::generate(),
                 msg: format!("Failed to communicate with CookieActor for return operation: {e}"),
@@ -517,6 +974,38 @@ impl CookieActorHandle {
         })
     }
 
+    /// Record the outcome of a health check on a cookie
+    pub async fn record_check(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
+        ractor::cast!(self.actor_ref, CookieActorMessage::Checked(cookie)).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with CookieActor for check operation: {e}"),
+            }
+        })
+    }
+
+    /// Store a token obtained or refreshed outside of a lease
+    pub async fn store_token(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
+        ractor::cast!(self.actor_ref, CookieActorMessage::Token(cookie)).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with CookieActor for token operation: {e}"),
+            }
+        })
+    }
+
+    /// Store the utilization snapshot of a cookie
+    pub async fn record_utilization(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
+        ractor::cast!(self.actor_ref, CookieActorMessage::Utilization(cookie)).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!(
+                    "Failed to communicate with CookieActor for utilization operation: {e}"
+                ),
+            }
+        })
+    }
+
     /// Submit a new cookie to the cookie actor
     pub async fn submit(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
         ractor::cast!(self.actor_ref, CookieActorMessage::Submit(cookie)).map_err(|e| {
@@ -527,6 +1016,19 @@ impl CookieActorHandle {
         })
     }
 
+    /// Import a batch of cookies, skipping the ones already known
+    ///
+    /// # Returns
+    /// * `Vec<bool>` - Whether each cookie was added
+    pub async fn import(&self, cookies: Vec<CookieStatus>) -> Result<Vec<bool>, ClewdrError> {
+        ractor::call!(self.actor_ref, CookieActorMessage::Import, cookies).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with CookieActor for import operation: {e}"),
+            }
+        })
+    }
+
     /// Get status information about all cookies
     pub async fn get_status(&self) -> Result<CookieStatusInfo, ClewdrError> {
         ractor::call!(self.actor_ref, CookieActorMessage::GetStatus).map_err(|e| {
@@ -549,9 +1051,9 @@ impl CookieActorHandle {
         })?
     }
 
-    /// Update 1M support flags on an existing cookie
-    pub async fn update_cookie_1m_support(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
-        ractor::call!(self.actor_ref, CookieActorMessage::Update1mSupport, cookie).map_err(|e| {
+    /// Update the settings and metadata of an existing cookie
+    pub async fn update_cookie_settings(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
+        ractor::call!(self.actor_ref, CookieActorMessage::UpdateSettings, cookie).map_err(|e| {
             ClewdrError::RactorError {
                 loc: Location::generate(),
                 msg: format!("Failed to communicate with CookieActor for update operation: {e}"),
@@ -559,3 +1061,258 @@ impl CookieActorHandle {
         })?
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ractor::concurrency::{OneshotReceiver, oneshot};
+
+    use super::*;
+    use crate::config::init_test_config;
+
+    fn all(_: &CookieStatus) -> bool {
+        true
+    }
+
+    fn cookie(id: char) -> CookieStatus {
+        let value = format!("sk-ant-sid01-{}-AAAAAAAA", id.to_string().repeat(86));
+        CookieStatus::new(&value, None).unwrap()
+    }
+
+    fn state(cookies: &[CookieStatus]) -> CookieActorState {
+        CookieActorState {
+            valid: cookies.iter().cloned().collect(),
+            exhausted: HashSet::new(),
+            invalid: HashSet::new(),
+            moka: Cache::new(16),
+            leases: HashMap::new(),
+            next_lease: 0,
+            waiting: VecDeque::new(),
+        }
+    }
+
+    type LeaseReceiver = OneshotReceiver<Result<(CookieStatus, u64), ClewdrError>>;
+
+    fn waiter(deadline: Instant) -> (Waiter, LeaseReceiver) {
+        let (tx, rx) = oneshot();
+        let waiter = Waiter {
+            request: CookieRequest::default(),
+            reply: tx.into(),
+            deadline,
+        };
+        (waiter, rx)
+    }
+
+    fn leased(rx: &mut LeaseReceiver) -> Option<u64> {
+        rx.try_recv().ok()?.ok().map(|(_, id)| id)
+    }
+
+    #[test]
+    fn selects_by_strategy() {
+        let mut a = cookie('a');
+        let mut b = cookie('b');
+        let mut c = cookie('c');
+        a.last_used_at = Some(200);
+        b.last_used_at = Some(100);
+        a.session_usage.total_input_tokens = 10;
+        b.session_usage.total_input_tokens = 50;
+        c.session_usage.total_output_tokens = 30;
+        b.weekly_usage.total_output_tokens = 1;
+        let valid = [a, b, c];
+
+        assert_eq!(
+            select_cookie(&valid, CookieStrategy::RoundRobin, all),
+            Some(0)
+        );
+        // never used cookies come first
+        assert_eq!(
+            select_cookie(&valid, CookieStrategy::LeastRecentlyUsed, all),
+            Some(2)
+        );
+        assert_eq!(
+            select_cookie(&valid, CookieStrategy::LowestSessionUsage, all),
+            Some(0)
+        );
+        assert_eq!(
+            select_cookie(&valid, CookieStrategy::LowestWeeklyUsage, all),
+            Some(0)
+        );
+        assert_eq!(select_cookie(&[], CookieStrategy::Random, all), None);
+    }
+
+    #[test]
+    fn weighted_skips_zero_weight() {
+        let mut a = cookie('a');
+        let mut b = cookie('b');
+        a.weight = Some(0);
+        b.weight = Some(3);
+        let valid = [a, b];
+        for _ in 0..50 {
+            assert_eq!(
+                select_cookie(&valid, CookieStrategy::Weighted, all),
+                Some(1)
+            );
+        }
+    }
+
+    #[test]
+    fn skips_cookies_limited_for_family() {
+        let mut a = cookie('a');
+        let b = cookie('b');
+        let now = Utc::now().timestamp();
+        a.add_and_bucket_usage(10, 5, ModelFamily::Opus);
+        a.set_family_cooldown(ModelFamily::Opus, now + 3600);
+        assert_eq!(a.weekly_opus_usage.opus_output_tokens, 5);
+        let valid = [a, b];
+
+        let opus = |c: &CookieStatus| c.available_for(ModelFamily::Opus, now);
+        let sonnet = |c: &CookieStatus| c.available_for(ModelFamily::Sonnet, now);
+        assert_eq!(
+            select_cookie(&valid, CookieStrategy::RoundRobin, opus),
+            Some(1)
+        );
+        assert_eq!(
+            select_cookie(&valid, CookieStrategy::RoundRobin, sonnet),
+            Some(0)
+        );
+
+        let mut a = valid[0].clone();
+        assert!(a.clear_expired_family_cooldowns(now + 3600));
+        assert!(a.available_for(ModelFamily::Opus, now));
+    }
+
+    #[test]
+    fn returned_cookie_keeps_edited_settings() {
+        init_test_config();
+        let mut state = state(&[cookie('j')]);
+        // snapshot held by a request while the admin edits the cookie
+        let mut returned = state.valid[0].clone();
+        state.valid[0].label = Some("edited".to_string());
+        state.valid[0].group = Some("team-a".to_string());
+        state.valid[0].weight = Some(3);
+        returned.add_and_bucket_usage(10, 5, ModelFamily::Sonnet);
+
+        CookieActor::collect(&mut state, returned, None);
+        let stored = &state.valid[0];
+        assert_eq!(stored.label.as_deref(), Some("edited"));
+        assert_eq!(stored.group.as_deref(), Some("team-a"));
+        assert_eq!(stored.weight, Some(3));
+        assert_eq!(stored.session_usage.total_input_tokens, 10);
+    }
+
+    #[test]
+    fn stale_snapshots_keep_the_stored_token() {
+        init_test_config();
+        let token = |refresh: &str, expires_at: i64| {
+            let mut token = TokenInfo::from_refresh_token(refresh.to_string());
+            token.expires_at = chrono::DateTime::from_timestamp(expires_at, 0).unwrap();
+            Some(token)
+        };
+        let mut stale = cookie('k');
+        stale.token = token("old", 1_000);
+        let mut state = state(&[stale.clone()]);
+        state.valid[0].token = token("new", 2_000);
+
+        CookieActor::record_check(&mut state, stale.clone());
+        assert_eq!(state.valid[0].token, token("new", 2_000));
+
+        CookieActor::collect(&mut state, stale, Some(Reason::TooManyRequest(i64::MAX)));
+        let limited = state.exhausted.iter().next().unwrap();
+        assert_eq!(limited.reset_time, Some(i64::MAX));
+        assert_eq!(limited.token, token("new", 2_000));
+    }
+
+    #[test]
+    fn caps_leases_and_serves_waiters_in_order() {
+        init_test_config();
+        let mut state = state(&[cookie('c')]);
+        let request = CookieRequest::default();
+        let (_, first) = CookieActor::dispatch(&mut state, &request)
+            .unwrap()
+            .unwrap();
+        assert!(
+            CookieActor::dispatch(&mut state, &request)
+                .unwrap()
+                .is_none()
+        );
+
+        let later = Instant::now() + Duration::from_secs(60);
+        let (w1, mut rx1) = waiter(later);
+        let (w2, mut rx2) = waiter(later);
+        state.waiting.extend([w1, w2]);
+        CookieActor::serve_waiters(&mut state);
+        assert_eq!(state.waiting.len(), 2);
+
+        state.leases.remove(&first);
+        CookieActor::serve_waiters(&mut state);
+        assert_eq!(leased(&mut rx1), Some(1));
+        assert_eq!(leased(&mut rx2), None);
+        assert_eq!(state.waiting.len(), 1);
+    }
+
+    #[test]
+    fn expires_waiters_past_their_deadline() {
+        init_test_config();
+        let mut state = state(&[cookie('d')]);
+        let (expired, mut expired_rx) = waiter(Instant::now());
+        let (pending, mut pending_rx) = waiter(Instant::now() + Duration::from_secs(60));
+        state.waiting.extend([expired, pending]);
+
+        CookieActor::expire_waiters(&mut state);
+        assert!(matches!(
+            expired_rx.try_recv(),
+            Ok(Err(ClewdrError::NoCookieAvailable))
+        ));
+        assert!(pending_rx.try_recv().is_err());
+        assert_eq!(state.waiting.len(), 1);
+    }
+
+    #[test]
+    fn releases_leases_of_gone_requesters() {
+        init_test_config();
+        let mut state = state(&[cookie('e')]);
+        let lease = CookieActor::dispatch(&mut state, &CookieRequest::default())
+            .unwrap()
+            .unwrap();
+        let (tx, rx) = oneshot();
+        drop(rx);
+        CookieActor::reply(&mut state, tx.into(), lease);
+        assert!(state.leases.is_empty());
+
+        // queued requests that went away are skipped
+        let lease = CookieActor::dispatch(&mut state, &CookieRequest::default())
+            .unwrap()
+            .unwrap();
+        let later = Instant::now() + Duration::from_secs(60);
+        let (gone, gone_rx) = waiter(later);
+        let (live, mut live_rx) = waiter(later);
+        drop(gone_rx);
+        state.waiting.extend([gone, live]);
+        state.leases.remove(&lease.1);
+        CookieActor::serve_waiters(&mut state);
+        assert!(leased(&mut live_rx).is_some());
+        assert!(state.waiting.is_empty());
+        assert_eq!(state.leases.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_lease_frees_its_slot() {
+        init_test_config();
+        let handle = CookieActorHandle::start().await.unwrap();
+        handle.submit(cookie('f')).await.unwrap();
+        let lease = handle.request(CookieRequest::default()).await.unwrap();
+        let queued = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.request(CookieRequest::default()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!queued.is_finished());
+
+        drop(lease);
+        let lease = tokio::time::timeout(Duration::from_millis(500), queued)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.cookie, cookie('f'));
+    }
+}
@@ -0,0 +1,174 @@
+use chrono::Utc;
+use tracing::{error, info, warn};
+
+use crate::{
+    claude_code_state::ClaudeCodeState,
+    claude_web_state::ClaudeWebState,
+    config::{CLEWDR_CONFIG, CookieStatus},
+    error::ClewdrError,
+    services::{
+        cookie_actor::CookieActorHandle,
+        periodic::{run_all, spawn_periodic},
+    },
+};
+
+/// Spawns the job that periodically checks every valid and exhausted cookie
+///
+/// Cookies are checked ahead of user requests, so banned, disabled, free or rate
+/// limited cookies are moved out of rotation without wasting a retry. The concurrency
+/// is read from the config before every round, like the interval.
+pub fn spawn_health_checker(handle: CookieActorHandle) {
+    spawn_periodic(
+        || CLEWDR_CONFIG.load().cookie_health_check_interval,
+        move || {
+            let handle = handle.to_owned();
+            async move { check_all(&handle).await }
+        },
+    );
+}
+
+/// Runs one round of checks over the valid and exhausted cookies
+async fn check_all(handle: &CookieActorHandle) {
+    let status = match handle.get_status().await {
+        Ok(status) => status,
+        Err(e) => {
+            error!("[HEALTH] Failed to get cookie status: {}", e);
+            return;
+        }
+    };
+    let concurrency = CLEWDR_CONFIG.load().cookie_health_check_concurrency;
+    let cookies = status.valid.into_iter().chain(status.exhausted);
+    let (checked, failed) = run_all(
+        cookies.map(|cookie| check_cookie(handle, cookie)),
+        concurrency,
+    )
+    .await;
+    info!("[HEALTH] checked {} cookies, {} failed", checked, failed);
+}
+
+/// Checks one cookie and records the outcome
+///
+/// # Returns
+/// * `bool` - Whether the cookie passed the check
+async fn check_cookie(handle: &CookieActorHandle, mut cookie: CookieStatus) -> bool {
+    let result = probe(handle, &mut cookie).await;
+    record(handle, cookie, result).await
+}
+
+/// Records the outcome of a check and reports invalid cookies to the actor
+///
+/// # Returns
+/// * `bool` - Whether the cookie passed the check
+async fn record(
+    handle: &CookieActorHandle,
+    mut cookie: CookieStatus,
+    result: Result<(), ClewdrError>,
+) -> bool {
+    cookie.last_checked_at = Some(Utc::now().timestamp());
+    cookie.last_error = result.as_ref().err().map(ToString::to_string);
+    if let Err(e) = handle.record_check(cookie.to_owned()).await {
+        error!("[HEALTH] Failed to record check: {}", e);
+    }
+    let Err(e) = result else {
+        return true;
+    };
+    warn!("[HEALTH] {}: {}", cookie.cookie.ellipse(), e);
+    if let ClewdrError::InvalidCookie { reason } = e
+        && let Err(e) = handle.return_cookie(cookie, Some(reason)).await
+    {
+        error!("[HEALTH] Failed to return cookie: {}", e);
+    }
+    false
+}
+
+/// Bootstraps the cookie on claude.ai, then makes sure it holds a usable Claude Code token
+///
+/// Accounts added through OAuth have no session to bootstrap, only their token is checked.
+/// A token obtained or refreshed along the way is stored in `cookie`.
+pub async fn probe(
+    handle: &CookieActorHandle,
+    cookie: &mut CookieStatus,
+) -> Result<(), ClewdrError> {
+    if cookie.cookie.is_session() {
+        let mut web = ClaudeWebState::from_cookie(handle.to_owned(), cookie.to_owned())?;
+        web.bootstrap().await?;
+    }
+
+    let mut code = ClaudeCodeState::from_cookie(handle.to_owned(), cookie.to_owned())?;
+    code.ensure_token().await?;
+    if let Some(token) = code.cookie.and_then(|c| c.token) {
+        cookie.token = Some(token);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ModelFamily, Reason, init_test_config};
+
+    fn cookie(id: char) -> CookieStatus {
+        let value = format!("sk-ant-sid01-{}-AAAAAAAA", id.to_string().repeat(86));
+        CookieStatus::new(&value, None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_probe_moves_the_cookie() {
+        init_test_config();
+        let handle = CookieActorHandle::start().await.unwrap();
+        handle.submit(cookie('g')).await.unwrap();
+        handle.submit(cookie('h')).await.unwrap();
+
+        let limited = Err(Reason::TooManyRequest(i64::MAX).into());
+        assert!(!record(&handle, cookie('g'), limited).await);
+        let banned = Err(Reason::Banned.into());
+        assert!(!record(&handle, cookie('h'), banned).await);
+
+        let status = handle.get_status().await.unwrap();
+        assert!(
+            !status
+                .valid
+                .iter()
+                .any(|c| *c == cookie('g') || *c == cookie('h'))
+        );
+        let exhausted = status
+            .exhausted
+            .iter()
+            .find(|c| **c == cookie('g'))
+            .unwrap();
+        assert_eq!(exhausted.reset_time, Some(i64::MAX));
+        assert!(exhausted.last_checked_at.is_some());
+        assert!(
+            status
+                .invalid
+                .iter()
+                .any(|c| c.cookie == cookie('h').cookie)
+        );
+    }
+
+    #[tokio::test]
+    async fn record_check_keeps_concurrent_usage() {
+        init_test_config();
+        let handle = CookieActorHandle::start().await.unwrap();
+        handle.submit(cookie('i')).await.unwrap();
+        // snapshot taken by the check before a request records its usage
+        let status = handle.get_status().await.unwrap();
+        let checked = status
+            .valid
+            .into_iter()
+            .find(|c| *c == cookie('i'))
+            .unwrap();
+        let mut used = checked.to_owned();
+        used.add_and_bucket_usage(100, 50, ModelFamily::Sonnet);
+        handle.return_cookie(used, None).await.unwrap();
+
+        assert!(record(&handle, checked, Ok(())).await);
+
+        let status = handle.get_status().await.unwrap();
+        let stored = status.valid.iter().find(|c| **c == cookie('i')).unwrap();
+        assert!(stored.last_checked_at.is_some());
+        assert_eq!(stored.last_error, None);
+        assert_eq!(stored.session_usage.total_input_tokens, 100);
+        assert_eq!(stored.session_usage.total_output_tokens, 50);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+
+use chrono::Utc;
+use colored::Colorize;
+use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
+use snafu::{GenerateImplicitData, Location};
+use tracing::{error, info};
+
+use crate::{
+    config::{CLEWDR_CONFIG, ClewdrConfig, PooledKey, Reason},
+    error::ClewdrError,
+};
+
+/// Upstream served by a key pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPoolKind {
+    Gemini,
+    Anthropic,
+}
+
+impl KeyPoolKind {
+    /// Keys of this pool in the configuration
+    fn keys(self, config: &ClewdrConfig) -> &Vec<PooledKey> {
+        match self {
+            KeyPoolKind::Gemini => &config.gemini_keys,
+            KeyPoolKind::Anthropic => &config.anthropic_keys,
+        }
+    }
+
+    fn keys_mut(self, config: &mut ClewdrConfig) -> &mut Vec<PooledKey> {
+        match self {
+            KeyPoolKind::Gemini => &mut config.gemini_keys,
+            KeyPoolKind::Anthropic => &mut config.anthropic_keys,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            KeyPoolKind::Gemini => "Gemini",
+            KeyPoolKind::Anthropic => "Anthropic",
+        }
+    }
+}
+
+/// Messages that the KeyPoolActor can handle
+#[derive(Debug)]
+enum KeyPoolMessage {
+    /// Request to get a key
+    Request(RpcReplyPort<Result<PooledKey, ClewdrError>>),
+    /// Return a key, with the reason upstream rejected it if any
+    Return(PooledKey, Option<Reason>),
+    /// Submit a new key
+    Submit(PooledKey, RpcReplyPort<Result<(), ClewdrError>>),
+    /// Delete a key
+    Delete(String, RpcReplyPort<Result<(), ClewdrError>>),
+    /// Get all keys
+    GetStatus(RpcReplyPort<Vec<PooledKey>>),
+}
+
+/// KeyPoolActor state, keys in dispatch order
+#[derive(Debug)]
+struct KeyPoolState {
+    keys: VecDeque<PooledKey>,
+}
+
+/// Key pool actor that hands out upstream API keys round-robin
+///
+/// Rate limited keys are skipped until their cooldown passes, keys rejected by
+/// upstream are kept for inspection but never dispatched again.
+struct KeyPoolActor {
+    kind: KeyPoolKind,
+}
+
+impl KeyPoolActor {
+    /// Saves the current keys to the configuration
+    fn save(&self, state: &KeyPoolState) {
+        let kind = self.kind;
+        CLEWDR_CONFIG.rcu(|config| {
+            let mut config = ClewdrConfig::clone(config);
+            *kind.keys_mut(&mut config) = state.keys.iter().cloned().collect();
+            config
+        });
+
+        if CLEWDR_CONFIG.load().no_fs {
+            return;
+        }
+        tokio::spawn(async move {
+            let result = CLEWDR_CONFIG.load().save().await;
+            match result {
+                Ok(_) => info!("{} keys saved successfully", kind.name()),
+                Err(e) => error!("Failed to save {} keys: {}", kind.name(), e),
+            }
+        });
+    }
+
+    /// Logs the current state of the pool
+    fn log(&self, state: &KeyPoolState) {
+        let now = Utc::now().timestamp();
+        let available = state.keys.iter().filter(|k| k.available(now)).count();
+        let invalid = state.keys.iter().filter(|k| k.invalid.is_some()).count();
+        info!(
+            "{} keys - Available: {}, Cooling down: {}, Invalid: {}",
+            self.kind.name(),
+            available.to_string().green(),
+            (state.keys.len() - available - invalid)
+                .to_string()
+                .yellow(),
+            invalid.to_string().red(),
+        );
+    }
+
+    /// Dispatches the next available key and rotates it to the back of the queue
+    fn dispatch(state: &mut KeyPoolState) -> Result<PooledKey, ClewdrError> {
+        let now = Utc::now().timestamp();
+        let index = state
+            .keys
+            .iter()
+            .position(|k| k.available(now))
+            .ok_or(ClewdrError::NoKeyAvailable)?;
+        let mut key = state
+            .keys
+            .remove(index)
+            .ok_or(ClewdrError::NoKeyAvailable)?;
+        key.cooldown_until = None;
+        key.last_used_at = Some(now);
+        state.keys.push_back(key.clone());
+        Ok(key)
+    }
+
+    /// Collects a returned key and marks it according to the return reason
+    fn collect(&self, state: &mut KeyPoolState, key: PooledKey, reason: Option<Reason>) {
+        let Some(reason) = reason else {
+            return;
+        };
+        let Some(existing) = state.keys.iter_mut().find(|k| **k == key) else {
+            return;
+        };
+        match reason {
+            Reason::TooManyRequest(i) | Reason::TooManyRequestFamily(_, i) => {
+                existing.cooldown_until = Some(i);
+            }
+            reason => existing.invalid = Some(reason),
+        }
+        self.save(state);
+        self.log(state);
+    }
+
+    /// Accepts a new key into the pool
+    fn accept(&self, state: &mut KeyPoolState, key: PooledKey) -> Result<(), ClewdrError> {
+        if key.key.trim().is_empty() {
+            return Err(ClewdrError::BadRequest {
+                msg: "Key must not be empty",
+            });
+        }
+        if state.keys.contains(&key) {
+            return Err(ClewdrError::BadRequest {
+                msg: "Key already exists",
+            });
+        }
+        state.keys.push_back(key);
+        self.save(state);
+        self.log(state);
+        Ok(())
+    }
+
+    /// Deletes a key from the pool
+    fn delete(&self, state: &mut KeyPoolState, key: String) -> Result<(), ClewdrError> {
+        let len = state.keys.len();
+        state.keys.retain(|k| k.key != key);
+        if state.keys.len() == len {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "Delete operation did not find the key",
+            });
+        }
+        self.save(state);
+        self.log(state);
+        Ok(())
+    }
+}
+
+impl Actor for KeyPoolActor {
+    type Msg = KeyPoolMessage;
+    type State = KeyPoolState;
+    type Arguments = ();
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        _arguments: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let keys = VecDeque::from_iter(self.kind.keys(&CLEWDR_CONFIG.load()).iter().cloned());
+        let state = KeyPoolState { keys };
+        self.log(&state);
+        Ok(state)
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            KeyPoolMessage::Request(reply_port) => {
+                reply_port.send(Self::dispatch(state))?;
+            }
+            KeyPoolMessage::Return(key, reason) => {
+                self.collect(state, key, reason);
+            }
+            KeyPoolMessage::Submit(key, reply_port) => {
+                reply_port.send(self.accept(state, key))?;
+            }
+            KeyPoolMessage::Delete(key, reply_port) => {
+                reply_port.send(self.delete(state, key))?;
+            }
+            KeyPoolMessage::GetStatus(reply_port) => {
+                reply_port.send(state.keys.iter().cloned().collect())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Handle for interacting with a KeyPoolActor
+#[derive(Clone)]
+pub struct KeyPoolHandle {
+    actor_ref: ActorRef<KeyPoolMessage>,
+}
+
+impl KeyPoolHandle {
+    /// Create a new KeyPoolActor serving the keys of `kind` and return a handle to it
+    pub async fn start(kind: KeyPoolKind) -> Result<Self, ractor::SpawnErr> {
+        let (actor_ref, _join_handle) = Actor::spawn(None, KeyPoolActor { kind }, ()).await?;
+        Ok(Self { actor_ref })
+    }
+
+    fn ractor_error(operation: &str, e: impl std::fmt::Display) -> ClewdrError {
+        ClewdrError::RactorError {
+            loc: Location::generate(),
+            msg: format!("Failed to communicate with KeyPoolActor for {operation} operation: {e}"),
+        }
+    }
+
+    /// Request a key from the pool
+    pub async fn request(&self) -> Result<PooledKey, ClewdrError> {
+        ractor::call!(self.actor_ref, KeyPoolMessage::Request)
+            .map_err(|e| Self::ractor_error("request", e))?
+    }
+
+    /// Return a key to the pool
+    pub async fn return_key(
+        &self,
+        key: PooledKey,
+        reason: Option<Reason>,
+    ) -> Result<(), ClewdrError> {
+        ractor::cast!(self.actor_ref, KeyPoolMessage::Return(key, reason))
+            .map_err(|e| Self::ractor_error("return", e))
+    }
+
+    /// Submit a new key to the pool
+    pub async fn submit(&self, key: PooledKey) -> Result<(), ClewdrError> {
+        ractor::call!(self.actor_ref, KeyPoolMessage::Submit, key)
+            .map_err(|e| Self::ractor_error("submit", e))?
+    }
+
+    /// Delete a key from the pool
+    pub async fn delete_key(&self, key: String) -> Result<(), ClewdrError> {
+        ractor::call!(self.actor_ref, KeyPoolMessage::Delete, key)
+            .map_err(|e| Self::ractor_error("delete", e))?
+    }
+
+    /// Get all keys of the pool
+    pub async fn get_status(&self) -> Result<Vec<PooledKey>, ClewdrError> {
+        ractor::call!(self.actor_ref, KeyPoolMessage::GetStatus)
+            .map_err(|e| Self::ractor_error("get status", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_and_skips_unavailable_keys() {
+        let now = Utc::now().timestamp();
+        let mut limited = PooledKey::new("key-b");
+        limited.cooldown_until = Some(now + 60);
+        let mut invalid = PooledKey::new("key-c");
+        invalid.invalid = Some(Reason::Null);
+        let mut state = KeyPoolState {
+            keys: VecDeque::from([PooledKey::new("key-a"), limited, invalid]),
+        };
+
+        assert_eq!(KeyPoolActor::dispatch(&mut state).unwrap().key, "key-a");
+        assert_eq!(KeyPoolActor::dispatch(&mut state).unwrap().key, "key-a");
+        state.keys[0].cooldown_until = Some(now - 1);
+        let key = KeyPoolActor::dispatch(&mut state).unwrap();
+        assert_eq!(key.key, "key-b");
+        assert_eq!(key.cooldown_until, None);
+
+        state.keys.retain(|k| k.invalid.is_some());
+        assert!(matches!(
+            KeyPoolActor::dispatch(&mut state),
+            Err(ClewdrError::NoKeyAvailable)
+        ));
+    }
+}
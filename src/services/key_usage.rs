@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        LazyLock, Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use tracing::error;
+
+use crate::{
+    config::{ApiKey, CLEWDR_CONFIG, ClewdrConfig, KeyUsage, ModelFamily},
+    error::ClewdrError,
+    services::{audit::AUDIT_LOG, metrics::METRICS, periodic::spawn_periodic},
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Error of a request over the `window` quota of the key
+fn quota_exceeded(key: &ApiKey, window: &str) -> ClewdrError {
+    ClewdrError::QuotaExceeded {
+        msg: format!("{window} quota of key {} is exhausted", key.ellipse()),
+    }
+}
+
+/// Usage counters of client API keys
+pub static KEY_USAGE: LazyLock<KeyUsageTracker> = LazyLock::new(KeyUsageTracker::default);
+
+/// Records the token usage of a finished response
+///
+/// Usage is counted for the client key, the token metrics and the audit entry.
+pub fn record_usage(
+    endpoint: &str,
+    family: ModelFamily,
+    api_key: Option<&str>,
+    audit_id: Option<u64>,
+    input: u64,
+    output: u64,
+) {
+    if input == 0 && output == 0 {
+        return;
+    }
+    KEY_USAGE.record_tokens(api_key, input, output);
+    METRICS.record_tokens(endpoint, family, input, output);
+    AUDIT_LOG.record_tokens(audit_id, input, output);
+}
+
+/// Tracks request and token usage per client API key
+///
+/// Counters are kept in memory and periodically written back into the
+/// `api_keys` section of the configuration, so quotas survive restarts
+/// without rewriting the config file on every request.
+#[derive(Default)]
+pub struct KeyUsageTracker {
+    usage: Mutex<HashMap<String, KeyUsage>>,
+    dirty: AtomicBool,
+}
+
+impl KeyUsageTracker {
+    fn with_usage<R>(&self, key: &str, f: impl FnOnce(&mut KeyUsage) -> R) -> R {
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = usage.entry(key.to_string()).or_insert_with(|| {
+            // seed from the persisted counters
+            CLEWDR_CONFIG
+                .load()
+                .api_keys
+                .iter()
+                .find(|k| k.key == key)
+                .map(|k| k.usage.to_owned())
+                .unwrap_or_default()
+        });
+        f(entry)
+    }
+
+    /// Fails if the daily or monthly quota of the key is exhausted
+    pub fn check_quota(&self, key: &ApiKey) -> Result<(), ClewdrError> {
+        let now = Utc::now();
+        let exceeded = self.with_usage(&key.key, |usage| {
+            usage.roll(now);
+            usage.exceeded(&key.quota)
+        });
+        match exceeded {
+            Some(window) => Err(quota_exceeded(key, window)),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a request of the key, unless its daily or monthly quota is exhausted
+    ///
+    /// The check and the count are done under the same lock, so concurrent requests
+    /// cannot all pass the check and overshoot the quota.
+    pub fn reserve_request(&self, key: &ApiKey) -> Result<(), ClewdrError> {
+        let now = Utc::now();
+        let exceeded = self.with_usage(&key.key, |usage| {
+            usage.roll(now);
+            let exceeded = usage.exceeded(&key.quota);
+            if exceeded.is_none() {
+                usage.record_request(now);
+            }
+            exceeded
+        });
+        if let Some(window) = exceeded {
+            return Err(quota_exceeded(key, window));
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn record_tokens(&self, key: Option<&str>, input: u64, output: u64) {
+        let Some(key) = key else {
+            return;
+        };
+        if input == 0 && output == 0 {
+            return;
+        }
+        self.with_usage(key, |usage| usage.record_tokens(Utc::now(), input, output));
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Current usage of a key
+    pub fn usage(&self, key: &str) -> KeyUsage {
+        self.with_usage(key, |usage| {
+            usage.roll(Utc::now());
+            usage.to_owned()
+        })
+    }
+
+    /// Forgets the counters of a deleted key
+    pub fn remove(&self, key: &str) {
+        self.usage
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(key);
+    }
+
+    /// Writes in-memory counters back into the configuration
+    pub async fn flush(&self) -> Result<(), ClewdrError> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let snapshot = self
+            .usage
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        CLEWDR_CONFIG.rcu(|old| {
+            let mut config = ClewdrConfig::clone(old);
+            for key in config.api_keys.iter_mut() {
+                if let Some(usage) = snapshot.get(&key.key) {
+                    key.usage = usage.to_owned();
+                }
+            }
+            config
+        });
+        CLEWDR_CONFIG.load().save().await
+    }
+
+    /// Spawns a task periodically persisting usage counters
+    pub fn spawn_flusher(&'static self) {
+        spawn_periodic(
+            || FLUSH_INTERVAL.as_secs(),
+            move || async move {
+                if let Err(e) = self.flush().await {
+                    error!("Failed to persist API key usage: {}", e);
+                }
+            },
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{LazyLock, Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{config::ModelFamily, error::ClewdrError, services::cookie_actor::CookieStatusInfo};
+
+/// Upper bounds (seconds) of the request latency histogram buckets
+const LATENCY_BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// Process wide metrics registry
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Status label of a finished request, `success` or the error variant name
+pub fn outcome<T>(result: &Result<T, ClewdrError>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(e) => e.into(),
+    }
+}
+
+/// Escapes a label value for the Prometheus text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+fn write_labels(out: &mut String, names: &[&str], values: &[String], extra: Option<(&str, &str)>) {
+    let pairs = names
+        .iter()
+        .zip(values)
+        .map(|(n, v)| (*n, v.as_str()))
+        .chain(extra)
+        .map(|(n, v)| format!("{n}=\"{}\"", escape(v)))
+        .collect::<Vec<_>>();
+    if !pairs.is_empty() {
+        out.push('{');
+        out.push_str(&pairs.join(","));
+        out.push('}');
+    }
+}
+
+/// A counter partitioned by a fixed set of labels
+struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    const fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn inc_by(&self, labels: &[&str], v: u64) {
+        debug_assert_eq!(labels.len(), self.labels.len());
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        *self
+            .values
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(key)
+            .or_default() += v;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        let values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, v) in values.iter() {
+            out.push_str(self.name);
+            write_labels(out, self.labels, key, None);
+            let _ = writeln!(out, " {v}");
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// A histogram partitioned by a fixed set of labels
+struct HistogramVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, Histogram>>,
+}
+
+impl HistogramVec {
+    const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        bounds: &'static [f64],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            bounds,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn observe(&self, labels: &[&str], v: f64) {
+        debug_assert_eq!(labels.len(), self.labels.len());
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        let mut values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        let h = values.entry(key).or_insert_with(|| Histogram {
+            buckets: vec![0; self.bounds.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        for (bucket, bound) in h.buckets.iter_mut().zip(self.bounds) {
+            if v <= *bound {
+                *bucket += 1;
+            }
+        }
+        h.sum += v;
+        h.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        let values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, h) in values.iter() {
+            for (bucket, bound) in h.buckets.iter().zip(self.bounds) {
+                let _ = write!(out, "{}_bucket", self.name);
+                write_labels(out, self.labels, key, Some(("le", &bound.to_string())));
+                let _ = writeln!(out, " {bucket}");
+            }
+            let _ = write!(out, "{}_bucket", self.name);
+            write_labels(out, self.labels, key, Some(("le", "+Inf")));
+            let _ = writeln!(out, " {}", h.count);
+            let _ = write!(out, "{}_sum", self.name);
+            write_labels(out, self.labels, key, None);
+            let _ = writeln!(out, " {}", h.sum);
+            let _ = write!(out, "{}_count", self.name);
+            write_labels(out, self.labels, key, None);
+            let _ = writeln!(out, " {}", h.count);
+        }
+    }
+}
+
+/// Counters and histograms exported on `/metrics`
+pub struct Metrics {
+    requests: CounterVec,
+    request_duration: HistogramVec,
+    retries: CounterVec,
+    upstream_errors: CounterVec,
+    tokens: CounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            requests: CounterVec::new(
+                "clewdr_requests_total",
+                "Requests handled, by endpoint, model family, API format and outcome",
+                &["endpoint", "family", "format", "status"],
+            ),
+            request_duration: HistogramVec::new(
+                "clewdr_request_duration_seconds",
+                "Time until the upstream response started, by endpoint, model family and API format",
+                &["endpoint", "family", "format"],
+                LATENCY_BUCKETS,
+            ),
+            retries: CounterVec::new(
+                "clewdr_retries_total",
+                "Retries with another cookie after a failed attempt",
+                &["endpoint"],
+            ),
+            upstream_errors: CounterVec::new(
+                "clewdr_upstream_errors_total",
+                "Error responses received from Claude, by HTTP status code",
+                &["code"],
+            ),
+            tokens: CounterVec::new(
+                "clewdr_tokens_total",
+                "Tokens consumed, by endpoint, model family and direction",
+                &["endpoint", "family", "direction"],
+            ),
+        }
+    }
+}
+
+impl Metrics {
+    /// Records a finished request
+    ///
+    /// `status` is `success` or the name of the error that ended the request.
+    /// Models are labelled by family to keep the label set bounded.
+    pub fn record_request(
+        &self,
+        endpoint: &str,
+        model: &str,
+        format: &str,
+        status: &str,
+        elapsed: Duration,
+    ) {
+        let family: &'static str = ModelFamily::from_model(model).into();
+        self.requests.inc_by(&[endpoint, family, format, status], 1);
+        self.request_duration
+            .observe(&[endpoint, family, format], elapsed.as_secs_f64());
+    }
+
+    pub fn record_retry(&self, endpoint: &str) {
+        self.retries.inc_by(&[endpoint], 1);
+    }
+
+    pub fn record_upstream_error(&self, code: u16) {
+        self.upstream_errors.inc_by(&[&code.to_string()], 1);
+    }
+
+    pub fn record_tokens(&self, endpoint: &str, family: ModelFamily, input: u64, output: u64) {
+        let family: &'static str = family.into();
+        if input > 0 {
+            self.tokens.inc_by(&[endpoint, family, "input"], input);
+        }
+        if output > 0 {
+            self.tokens.inc_by(&[endpoint, family, "output"], output);
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format
+    ///
+    /// Cookie pool gauges are taken from `cookies` when available.
+    pub fn render(&self, cookies: Option<&CookieStatusInfo>) -> String {
+        let mut out = String::new();
+        self.requests.render(&mut out);
+        self.request_duration.render(&mut out);
+        self.retries.render(&mut out);
+        self.upstream_errors.render(&mut out);
+        self.tokens.render(&mut out);
+        if let Some(cookies) = cookies {
+            out.push_str("# HELP clewdr_cookies Cookies in the pool, by state\n");
+            out.push_str("# TYPE clewdr_cookies gauge\n");
+            for (state, count) in [
+                ("valid", cookies.valid.len()),
+                ("exhausted", cookies.exhausted.len()),
+                ("invalid", cookies.invalid.len()),
+            ] {
+                let _ = writeln!(out, "clewdr_cookies{{state=\"{state}\"}} {count}");
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_histograms() {
+        let metrics = Metrics::default();
+        metrics.record_request(
+            "web",
+            "claude-sonnet-4-5",
+            "OpenAI",
+            "success",
+            Duration::from_millis(300),
+        );
+        metrics.record_upstream_error(429);
+        metrics.record_tokens("code", ModelFamily::Opus, 10, 0);
+        let cookies = CookieStatusInfo {
+            valid: vec![],
+            exhausted: vec![],
+            invalid: vec![],
+        };
+        let text = metrics.render(Some(&cookies));
+
+        assert!(text.contains(
+            "clewdr_requests_total{endpoint=\"web\",family=\"sonnet\",format=\"OpenAI\",status=\"success\"} 1"
+        ));
+        assert!(text.contains(
+            "clewdr_request_duration_seconds_bucket{endpoint=\"web\",family=\"sonnet\",format=\"OpenAI\",le=\"0.25\"} 0"
+        ));
+        assert!(text.contains(
+            "clewdr_request_duration_seconds_bucket{endpoint=\"web\",family=\"sonnet\",format=\"OpenAI\",le=\"0.5\"} 1"
+        ));
+        assert!(text.contains("clewdr_upstream_errors_total{code=\"429\"} 1"));
+        assert!(text.contains(
+            "clewdr_tokens_total{endpoint=\"code\",family=\"opus\",direction=\"input\"} 10"
+        ));
+        assert!(!text.contains("direction=\"output\""));
+        assert!(text.contains("clewdr_cookies{state=\"valid\"} 0"));
+    }
+}
@@ -1,3 +1,14 @@
+pub mod audit;
 pub mod cookie_actor;
+pub mod health_check;
+pub mod key_pool;
+pub mod key_usage;
+pub mod metrics;
+pub mod periodic;
+pub mod proxy_pool;
+pub mod rate_limit;
+pub mod response_cache;
+pub mod token_refresh;
 #[cfg(feature = "portable")]
 pub mod update;
+pub mod utilization;
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+
+/// Time between two reads of the interval while a job is disabled
+const DISABLED_POLL: Duration = Duration::from_secs(60);
+
+/// Spawns a job run every `interval()` seconds, 0 disabling it
+///
+/// The interval is read before every round, so it follows hot reloads of the config.
+pub fn spawn_periodic<I, J, F>(interval: I, mut job: J)
+where
+    I: Fn() -> u64 + Send + 'static,
+    J: FnMut() -> F + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let secs = interval();
+            if secs == 0 {
+                tokio::time::sleep(DISABLED_POLL).await;
+                continue;
+            }
+            job().await;
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+        }
+    });
+}
+
+/// Runs the tasks, at most `concurrency` at a time
+///
+/// # Returns
+/// * `(usize, usize)` - Number of tasks run and of tasks that failed
+pub async fn run_all<F>(tasks: impl IntoIterator<Item = F>, concurrency: usize) -> (usize, usize)
+where
+    F: Future<Output = bool>,
+{
+    let results = futures::stream::iter(tasks)
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let failed = results.iter().filter(|ok| !**ok).count();
+    (results.len(), failed)
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        LazyLock, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::future::join_all;
+use rand::Rng;
+use tracing::{info, warn};
+use wreq::Proxy;
+
+use crate::{
+    config::{CLEWDR_CONFIG, ClewdrCookie, ProxyStrategy, UpstreamProxy},
+    services::periodic::spawn_periodic,
+};
+
+/// Time a proxy has to answer a check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Process wide pool of outbound proxies
+pub static PROXY_POOL: LazyLock<ProxyPool> = LazyLock::new(ProxyPool::default);
+
+/// Picks the outbound proxy of each cookie among the configured `proxies`
+///
+/// Proxies are read from the config on every pick, so they can hot reload. Proxies that
+/// failed their last check are benched, and only used when every proxy is benched.
+#[derive(Default)]
+pub struct ProxyPool {
+    /// URLs of the benched proxies
+    benched: Mutex<HashSet<String>>,
+    /// Position of the next proxy of the round robin strategy
+    next: AtomicUsize,
+}
+
+impl ProxyPool {
+    /// Proxy the requests of the cookie go through
+    ///
+    /// # Returns
+    /// * `Option<Proxy>` - A proxy of the pool, or the global `proxy` when the pool is empty
+    pub fn pick(&self, cookie: &ClewdrCookie) -> Option<Proxy> {
+        let config = CLEWDR_CONFIG.load();
+        if config.proxies.is_empty() {
+            return config.wreq_proxy.to_owned();
+        }
+        let candidates = {
+            let benched = self.benched.lock().unwrap_or_else(PoisonError::into_inner);
+            let healthy = config
+                .proxies
+                .iter()
+                .filter(|p| !benched.contains(&p.url))
+                .collect::<Vec<_>>();
+            if healthy.is_empty() {
+                config.proxies.iter().collect()
+            } else {
+                healthy
+            }
+        };
+        let proxy = match config.proxy_strategy {
+            ProxyStrategy::Sticky => sticky(&candidates, cookie),
+            ProxyStrategy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            ProxyStrategy::Random => candidates[rand::rng().random_range(0..candidates.len())],
+        };
+        proxy.wreq_proxy.to_owned()
+    }
+
+    /// Checks every proxy of the pool, benching the failing ones and restoring the others
+    async fn check_all(&self) {
+        let proxies = CLEWDR_CONFIG.load().proxies.to_owned();
+        let endpoint = CLEWDR_CONFIG.load().endpoint();
+        let results = join_all(proxies.iter().map(|p| check(p, endpoint.as_str()))).await;
+        let mut benched = self.benched.lock().unwrap_or_else(PoisonError::into_inner);
+        // forget the proxies removed from the config
+        benched.retain(|url| proxies.iter().any(|p| p.url == *url));
+        for (proxy, ok) in proxies.iter().zip(results) {
+            if ok {
+                if benched.remove(&proxy.url) {
+                    info!("[PROXY] {} is back", proxy.name());
+                }
+            } else if benched.insert(proxy.url.to_owned()) {
+                warn!("[PROXY] {} benched", proxy.name());
+            }
+        }
+    }
+
+    /// Spawns the job that periodically checks the proxies of the pool
+    pub fn spawn_health_checker(&'static self) {
+        spawn_periodic(
+            || {
+                let config = CLEWDR_CONFIG.load();
+                if config.proxies.is_empty() {
+                    0
+                } else {
+                    config.proxy_health_check_interval
+                }
+            },
+            move || self.check_all(),
+        );
+    }
+}
+
+/// Proxy with the highest hash with the cookie, so a cookie keeps its proxy and only the
+/// cookies of a benched proxy move
+fn sticky<'a>(candidates: &[&'a UpstreamProxy], cookie: &ClewdrCookie) -> &'a UpstreamProxy {
+    candidates
+        .iter()
+        .copied()
+        .max_by_key(|p| {
+            let mut hasher = DefaultHasher::new();
+            cookie.hash(&mut hasher);
+            p.url.hash(&mut hasher);
+            hasher.finish()
+        })
+        .expect("candidates are never empty")
+}
+
+/// Whether the endpoint answers through the proxy, whatever the status
+async fn check(proxy: &UpstreamProxy, endpoint: &str) -> bool {
+    let Some(ref wreq_proxy) = proxy.wreq_proxy else {
+        return false;
+    };
+    let client = match wreq::Client::builder()
+        .proxy(wreq_proxy.to_owned())
+        .timeout(CHECK_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("[PROXY] Failed to build client for {}: {}", proxy.name(), e);
+            return false;
+        }
+    };
+    match client.get(endpoint).send().await {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("[PROXY] {} failed its check: {}", proxy.name(), e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(url: &str) -> UpstreamProxy {
+        UpstreamProxy {
+            url: url.to_string(),
+            label: None,
+            wreq_proxy: None,
+        }
+    }
+
+    #[test]
+    fn sticky_only_moves_cookies_of_a_removed_proxy() {
+        let proxies = (0..4)
+            .map(|i| proxy(&format!("http://proxy-{i}:8080")))
+            .collect::<Vec<_>>();
+        let all = proxies.iter().collect::<Vec<_>>();
+        let cookies = (0..32).map(|_| ClewdrCookie::oauth()).collect::<Vec<_>>();
+        let before = cookies
+            .iter()
+            .map(|c| sticky(&all, c).url.to_owned())
+            .collect::<Vec<_>>();
+        assert!(before.iter().collect::<HashSet<_>>().len() > 1);
+
+        let benched = &proxies[0].url;
+        let rest = all[1..].to_vec();
+        for (cookie, url) in cookies.iter().zip(before) {
+            let after = &sticky(&rest, cookie).url;
+            if url != *benched {
+                assert_eq!(*after, url);
+            }
+        }
+    }
+}
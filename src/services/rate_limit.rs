@@ -0,0 +1,375 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::config::ClewdrConfig;
+
+/// Idle time after which the counters of a client are forgotten
+const CLIENT_IDLE: Duration = Duration::from_secs(60);
+
+/// Process wide request limiter
+pub static RATE_LIMITER: LazyLock<RateLimiter> = LazyLock::new(RateLimiter::default);
+
+/// Limits of one scope, 0 disables a limit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimits {
+    pub requests_per_minute: u64,
+    pub tokens_per_minute: u64,
+    pub concurrency: usize,
+}
+
+impl RateLimits {
+    /// Limits shared by all clients
+    pub fn global(config: &ClewdrConfig) -> Self {
+        Self {
+            requests_per_minute: config.global_rate_limit_rpm,
+            tokens_per_minute: config.global_rate_limit_tpm,
+            concurrency: config.global_rate_limit_concurrency,
+        }
+    }
+
+    /// Limits of each API key, or of each IP for password users
+    pub fn client(config: &ClewdrConfig) -> Self {
+        Self {
+            requests_per_minute: config.rate_limit_rpm,
+            tokens_per_minute: config.rate_limit_tpm,
+            concurrency: config.rate_limit_concurrency,
+        }
+    }
+}
+
+/// A request rejected by the limiter
+#[derive(Debug, Clone)]
+pub struct RateLimited {
+    /// `global` or `client`
+    pub scope: &'static str,
+    /// `requests`, `tokens` or `concurrency`
+    pub limit: &'static str,
+    /// Seconds after which the request may succeed
+    pub retry_after: u64,
+}
+
+impl RateLimited {
+    pub fn message(&self) -> String {
+        let what = match self.limit {
+            "concurrency" => "concurrent request",
+            "tokens" => "tokens per minute",
+            _ => "requests per minute",
+        };
+        format!(
+            "{} {} limit reached, retry after {}s",
+            self.scope, what, self.retry_after
+        )
+    }
+}
+
+/// Token bucket refilled continuously with its per minute capacity
+#[derive(Debug)]
+struct Bucket {
+    level: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(now: Instant) -> Self {
+        Self {
+            level: f64::MAX,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, per_minute: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.level = (self.level + elapsed * per_minute as f64 / 60.0).min(per_minute as f64);
+        self.updated = now;
+    }
+
+    /// Seconds until the bucket holds `need`, 0 if it already does
+    fn wait(&self, need: f64, per_minute: u64) -> u64 {
+        if self.level >= need {
+            return 0;
+        }
+        ((need - self.level) * 60.0 / per_minute as f64)
+            .ceil()
+            .max(1.0) as u64
+    }
+}
+
+/// Counters of one scope
+#[derive(Debug)]
+struct Counters {
+    requests: Bucket,
+    tokens: Bucket,
+    in_flight: usize,
+}
+
+impl Counters {
+    fn new(now: Instant) -> Self {
+        Self {
+            requests: Bucket::full(now),
+            tokens: Bucket::full(now),
+            in_flight: 0,
+        }
+    }
+
+    /// Limit that would be exceeded by a new request, with its wait in seconds
+    fn check(&mut self, limits: &RateLimits, now: Instant) -> Option<(&'static str, u64)> {
+        if limits.concurrency > 0 && self.in_flight >= limits.concurrency {
+            return Some(("concurrency", 1));
+        }
+        if limits.requests_per_minute > 0 {
+            self.requests.refill(limits.requests_per_minute, now);
+            let wait = self.requests.wait(1.0, limits.requests_per_minute);
+            if wait > 0 {
+                return Some(("requests", wait));
+            }
+        }
+        if limits.tokens_per_minute > 0 {
+            self.tokens.refill(limits.tokens_per_minute, now);
+            // tokens are charged once counted, so only a bucket in debt blocks
+            let wait = self
+                .tokens
+                .wait(f64::MIN_POSITIVE, limits.tokens_per_minute);
+            if wait > 0 {
+                return Some(("tokens", wait));
+            }
+        }
+        None
+    }
+
+    fn admit(&mut self, limits: &RateLimits) {
+        self.in_flight += 1;
+        if limits.requests_per_minute > 0 {
+            self.requests.level -= 1.0;
+        }
+    }
+
+    fn charge(&mut self, tokens: u64) {
+        // a bucket still at f64::MAX has never been limited
+        if self.tokens.level < f64::MAX {
+            self.tokens.level -= tokens as f64;
+        }
+    }
+
+    fn release(&mut self, tokens: u64) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        self.charge(tokens);
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.in_flight == 0
+            && self.tokens.level >= 0.0
+            && now.saturating_duration_since(self.requests.updated.max(self.tokens.updated))
+                >= CLIENT_IDLE
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    global: Counters,
+    clients: HashMap<String, Counters>,
+}
+
+/// Requests per minute, tokens per minute and concurrency limits, globally and per client
+///
+/// Limits are read from the config on every request, so they can hot reload.
+#[derive(Clone)]
+pub struct RateLimiter {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                global: Counters::new(Instant::now()),
+                clients: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Admits a request of the client if neither the global nor its own limits are exceeded
+    ///
+    /// # Arguments
+    /// * `client` - API key or IP of the client
+    /// * `global` - Limits shared by all clients
+    /// * `limits` - Limits of the client
+    ///
+    /// # Returns
+    /// * `Result<RateLimitPermit, RateLimited>` - Permit held until the response is complete
+    pub fn acquire(
+        &self,
+        client: &str,
+        global: &RateLimits,
+        limits: &RateLimits,
+    ) -> Result<RateLimitPermit, RateLimited> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((limit, retry_after)) = state.global.check(global, now) {
+            return Err(RateLimited {
+                scope: "global",
+                limit,
+                retry_after,
+            });
+        }
+        let counters = state
+            .clients
+            .entry(client.to_string())
+            .or_insert_with(|| Counters::new(now));
+        if let Some((limit, retry_after)) = counters.check(limits, now) {
+            return Err(RateLimited {
+                scope: "client",
+                limit,
+                retry_after,
+            });
+        }
+        counters.admit(limits);
+        state.global.admit(global);
+        state.clients.retain(|_, counters| !counters.is_idle(now));
+        Ok(RateLimitPermit {
+            charge: TokenCharge {
+                state: self.state.to_owned(),
+                client: client.to_string(),
+                charged: Arc::default(),
+            },
+            tokens: 0,
+        })
+    }
+}
+
+/// Charges the input tokens of an admitted request as soon as they are counted
+///
+/// Handed to the request handlers through the request extensions, so requests in flight
+/// already count against the tokens per minute limit.
+#[derive(Clone)]
+pub struct TokenCharge {
+    state: Arc<Mutex<State>>,
+    client: String,
+    /// Tokens charged before the request completed
+    charged: Arc<AtomicU64>,
+}
+
+impl TokenCharge {
+    /// Charges the input tokens of the request to the global and client buckets
+    pub fn charge_input(&self, tokens: u64) {
+        self.charged.fetch_add(tokens, Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.global.charge(tokens);
+        if let Some(counters) = state.clients.get_mut(&self.client) {
+            counters.charge(tokens);
+        }
+    }
+}
+
+/// An admitted request, releases its concurrency slot and settles its tokens when dropped
+pub struct RateLimitPermit {
+    charge: TokenCharge,
+    tokens: u64,
+}
+
+impl RateLimitPermit {
+    /// Handle charging the input tokens of the request once they are counted
+    pub fn charge(&self) -> TokenCharge {
+        self.charge.to_owned()
+    }
+
+    /// Sets the input and output tokens of the request, the part not charged yet is
+    /// settled on release
+    pub fn set_tokens(&mut self, tokens: u64) {
+        self.tokens = tokens;
+    }
+}
+
+impl Drop for RateLimitPermit {
+    fn drop(&mut self) {
+        let tokens = self
+            .tokens
+            .saturating_sub(self.charge.charged.load(Ordering::Relaxed));
+        let mut state = self
+            .charge
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        state.global.release(tokens);
+        if let Some(counters) = state.clients.get_mut(&self.charge.client) {
+            counters.release(tokens);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_requests_concurrency_and_tokens() {
+        let limiter = RateLimiter::default();
+        let global = RateLimits::default();
+        let limits = RateLimits {
+            requests_per_minute: 2,
+            tokens_per_minute: 100,
+            concurrency: 1,
+        };
+
+        let mut permit = limiter.acquire("a", &global, &limits).unwrap();
+        let e = limiter.acquire("a", &global, &limits).unwrap_err();
+        assert_eq!(e.limit, "concurrency");
+        // other clients have their own counters
+        drop(limiter.acquire("b", &global, &limits).unwrap());
+
+        permit.set_tokens(250);
+        drop(permit);
+        let e = limiter.acquire("a", &global, &limits).unwrap_err();
+        assert_eq!((e.scope, e.limit), ("client", "tokens"));
+        assert!(e.retry_after >= 90);
+    }
+
+    #[test]
+    fn charges_input_tokens_at_admission() {
+        let limiter = RateLimiter::default();
+        let global = RateLimits::default();
+        let limits = RateLimits {
+            tokens_per_minute: 100,
+            ..Default::default()
+        };
+
+        let mut permit = limiter.acquire("a", &global, &limits).unwrap();
+        permit.charge().charge_input(150);
+        // the next request is held back while the first one is still running
+        let e = limiter.acquire("a", &global, &limits).unwrap_err();
+        assert_eq!(e.limit, "tokens");
+        assert!((30..=31).contains(&e.retry_after));
+
+        // only the output tokens are left to settle
+        permit.set_tokens(150 + 60);
+        drop(permit);
+        let e = limiter.acquire("a", &global, &limits).unwrap_err();
+        assert!((66..=67).contains(&e.retry_after));
+    }
+
+    #[test]
+    fn refills_requests_over_a_minute() {
+        let limiter = RateLimiter::default();
+        let global = RateLimits {
+            requests_per_minute: 1,
+            ..Default::default()
+        };
+        drop(
+            limiter
+                .acquire("a", &global, &RateLimits::default())
+                .unwrap(),
+        );
+        let e = limiter
+            .acquire("b", &global, &RateLimits::default())
+            .unwrap_err();
+        assert_eq!((e.scope, e.limit), ("global", "requests"));
+        assert!((59..=60).contains(&e.retry_after));
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{body::Body, response::Response};
+use chrono::Utc;
+use futures::StreamExt;
+use http::{HeaderValue, header::CONTENT_TYPE};
+use moka::{Expiry, sync::Cache};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    config::{CLEWDR_CONFIG, CONFIG_PATH},
+    error::ClewdrError,
+    middleware::claude::ClaudeContext,
+    providers::{
+        LLMProvider,
+        claude::{BACKEND_HEADER, ClaudeInvocation, ClaudeProviderResponse},
+    },
+    services::{
+        audit::{AUDIT_LOG, AuditRequest},
+        metrics::METRICS,
+        periodic::spawn_periodic,
+    },
+    types::claude::CreateMessageParams,
+};
+
+/// Response header telling whether a cacheable response was served from the cache
+pub const CACHE_HEADER: &str = "x-clewdr-cache";
+
+/// Time between two sweeps of the disk tier
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Status of the requests served from the cache, in the audit log and the metrics
+const CACHE_HIT: &str = "cache_hit";
+
+/// Process wide cache of deterministic responses
+pub static RESPONSE_CACHE: LazyLock<ResponseCache> = LazyLock::new(ResponseCache::default);
+
+/// A successful response in Claude format, before any format conversion
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    /// Unix timestamp (UTC) at which the response was stored
+    created_at: i64,
+    /// Whether the body holds SSE events rather than a JSON message
+    stream: bool,
+    /// Backend that served the response, as reported in `x-clewdr-backend`
+    #[serde(default)]
+    backend: Option<String>,
+    /// Whether the response was served with a Claude Web context, whose responses
+    /// still need their usage filled in
+    #[serde(default)]
+    web: bool,
+    body: String,
+}
+
+impl CachedResponse {
+    fn to_response(&self) -> Response {
+        let content_type = if self.stream {
+            "text/event-stream"
+        } else {
+            "application/json"
+        };
+        let mut response = Response::new(Body::from(self.body.to_owned()));
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        headers.insert(CACHE_HEADER, HeaderValue::from_static("HIT"));
+        if let Some(backend) = self
+            .backend
+            .as_deref()
+            .and_then(|b| HeaderValue::from_str(b).ok())
+        {
+            headers.insert(BACKEND_HEADER, backend);
+        }
+        response
+    }
+
+    /// Context of the request the response is served for, as it was when the response was stored
+    fn context(&self, invocation: &ClaudeInvocation) -> ClaudeContext {
+        if self.web {
+            invocation.context.to_web(&invocation.params)
+        } else {
+            invocation.context.to_owned()
+        }
+    }
+}
+
+type MemoryCache = Cache<u64, Arc<CachedResponse>>;
+
+/// Expires memory entries `ttl` seconds after their response was stored, so entries loaded
+/// from the disk tier only get the TTL they have left
+struct RemainingTtl(u64);
+
+impl RemainingTtl {
+    fn at(&self, value: &CachedResponse, now: i64) -> Duration {
+        let left = value.created_at.saturating_add(self.0 as i64) - now;
+        Duration::from_secs(left.max(0) as u64)
+    }
+}
+
+impl Expiry<u64, Arc<CachedResponse>> for RemainingTtl {
+    fn expire_after_create(
+        &self,
+        _key: &u64,
+        value: &Arc<CachedResponse>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.at(value, Utc::now().timestamp()))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &u64,
+        value: &Arc<CachedResponse>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(self.at(value, Utc::now().timestamp()))
+    }
+}
+
+/// Cache of responses to identical requests with `temperature: 0`
+///
+/// Entries live in memory, and optionally on disk next to the config file so they
+/// survive restarts.
+#[derive(Default)]
+pub struct ResponseCache {
+    /// TTL and size the memory tier was built with, and the tier itself
+    memory: Mutex<Option<(u64, u64, MemoryCache)>>,
+}
+
+impl ResponseCache {
+    /// Memory tier, rebuilt empty when its TTL or size changed in the config
+    fn memory(&self) -> MemoryCache {
+        let config = CLEWDR_CONFIG.load();
+        let (ttl, size) = (config.response_cache_ttl, config.response_cache_size);
+        let mut memory = self.memory.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((t, s, cache)) = memory.as_ref()
+            && *t == ttl
+            && *s == size
+        {
+            return cache.clone();
+        }
+        let cache = Cache::builder()
+            .max_capacity(size)
+            .expire_after(RemainingTtl(ttl))
+            .build();
+        *memory = Some((ttl, size, cache.clone()));
+        cache
+    }
+
+    /// Cache key of a request, `None` unless caching is enabled and the request is deterministic
+    pub fn key(&self, backend: &str, params: &CreateMessageParams) -> Option<u64> {
+        if !CLEWDR_CONFIG.load().response_cache {
+            return None;
+        }
+        request_hash(backend, params)
+    }
+
+    /// Returns the cached response of the key
+    async fn get(&self, key: Option<u64>) -> Option<Arc<CachedResponse>> {
+        let key = key?;
+        let memory = self.memory();
+        let cached = match memory.get(&key) {
+            Some(cached) => cached,
+            None => {
+                let cached = Arc::new(read_disk(key).await?);
+                memory.insert(key, cached.to_owned());
+                cached
+            }
+        };
+        info!("[CACHE] hit: {:016x}", key);
+        Some(cached)
+    }
+
+    /// Stores a successful response under the key once its body is complete
+    ///
+    /// Non stream bodies are read at once, stream bodies are recorded while they are
+    /// forwarded and only stored when they end with `message_stop`. The serving backend
+    /// and the kind of `context` are stored along, so a hit is post-processed the same way.
+    async fn store(
+        &'static self,
+        key: Option<u64>,
+        context: &ClaudeContext,
+        mut response: Response,
+    ) -> Response {
+        let Some(key) = key else {
+            return response;
+        };
+        response
+            .headers_mut()
+            .insert(CACHE_HEADER, HeaderValue::from_static("MISS"));
+        if !response.status().is_success() {
+            return response;
+        }
+        let stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/event-stream"));
+        let entry = CachedResponse {
+            created_at: 0,
+            stream,
+            backend: response
+                .headers()
+                .get(BACKEND_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            web: context.is_web(),
+            body: String::new(),
+        };
+        let (parts, body) = response.into_parts();
+        if !stream {
+            let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    warn!("Failed to read response body for the cache: {}", e);
+                    return Response::from_parts(parts, Body::empty());
+                }
+            };
+            self.insert(key, entry, bytes.to_vec());
+            return Response::from_parts(parts, Body::from(bytes));
+        }
+        let body = async_stream::stream! {
+            let mut data = body.into_data_stream();
+            let mut recorded = vec![];
+            let mut complete = true;
+            while let Some(chunk) = data.next().await {
+                match chunk {
+                    Ok(ref bytes) => recorded.extend_from_slice(bytes),
+                    Err(_) => complete = false,
+                }
+                yield chunk;
+            }
+            if complete {
+                self.insert(key, entry, recorded);
+            }
+        };
+        Response::from_parts(parts, Body::from_stream(body))
+    }
+
+    /// Stores `entry` with the recorded body
+    fn insert(&self, key: u64, entry: CachedResponse, body: Vec<u8>) {
+        let Ok(body) = String::from_utf8(body) else {
+            return;
+        };
+        // quotes inside text deltas are escaped, so these only match event types
+        if entry.stream
+            && (!body.contains(r#""message_stop""#) || body.contains(r#""type":"error""#))
+        {
+            return;
+        }
+        let cached = Arc::new(CachedResponse {
+            created_at: Utc::now().timestamp(),
+            body,
+            ..entry
+        });
+        if disk_enabled() {
+            write_disk(key, cached.to_owned());
+        }
+        self.memory().insert(key, cached);
+    }
+
+    /// Drops every cached response
+    ///
+    /// # Returns
+    /// * `u64` - Number of responses dropped from memory
+    pub async fn purge(&self) -> u64 {
+        let memory = self.memory();
+        memory.run_pending_tasks();
+        let count = memory.entry_count();
+        memory.invalidate_all();
+        if let Err(e) = tokio::fs::remove_dir_all(cache_dir()).await
+            && e.kind() != ErrorKind::NotFound
+        {
+            warn!("Failed to purge the response cache directory: {}", e);
+        }
+        count
+    }
+}
+
+/// Invokes the provider unless the response is cached, and caches the response of a miss
+///
+/// Hits are recorded in the audit log and the request metrics with a `cache_hit` status.
+pub async fn invoke_cached<P>(
+    provider: &P,
+    backend: &'static str,
+    invocation: ClaudeInvocation,
+) -> Result<ClaudeProviderResponse, ClewdrError>
+where
+    P: LLMProvider<Request = ClaudeInvocation, Output = ClaudeProviderResponse>,
+{
+    let key = RESPONSE_CACHE.key(backend, &invocation.params);
+    let stopwatch = Instant::now();
+    if let Some(cached) = RESPONSE_CACHE.get(key).await {
+        let response = record_hit(backend, &invocation, &cached, stopwatch.elapsed()).await;
+        return Ok(ClaudeProviderResponse {
+            context: cached.context(&invocation),
+            response,
+        });
+    }
+    let ClaudeProviderResponse { context, response } = provider.invoke(invocation).await?;
+    let response = RESPONSE_CACHE.store(key, &context, response).await;
+    Ok(ClaudeProviderResponse { context, response })
+}
+
+/// Opens and completes the audit entry of a cache hit and counts it in the request metrics
+///
+/// # Returns
+/// * `Response` - The cached response, its body captured when body logging is enabled
+async fn record_hit(
+    endpoint: &'static str,
+    invocation: &ClaudeInvocation,
+    cached: &CachedResponse,
+    elapsed: Duration,
+) -> Response {
+    let model = invocation.params.model.as_str();
+    let format = invocation.context.api_format().to_string();
+    let stream = invocation.context.is_stream();
+    let audit_id = AUDIT_LOG.start(
+        AuditRequest {
+            endpoint,
+            format: format.to_owned(),
+            model,
+            stream,
+            api_key: invocation.context.api_key(),
+        },
+        &invocation.params,
+    );
+    if let Some(backend) = cached.backend.as_deref() {
+        AUDIT_LOG.record_backend(audit_id, backend);
+    }
+    AUDIT_LOG.finish(audit_id, CACHE_HIT, elapsed);
+    METRICS.record_request(endpoint, model, &format, CACHE_HIT, elapsed);
+    AUDIT_LOG
+        .capture_response(audit_id, cached.to_response())
+        .await
+}
+
+/// Hash of a request with `temperature: 0` and the backend serving it
+fn request_hash(backend: &str, params: &CreateMessageParams) -> Option<u64> {
+    if params.temperature != Some(0.0) {
+        return None;
+    }
+    // object keys of a JSON value are sorted
+    let mut normalized = serde_json::to_value(params).ok()?;
+    normalized["stream"] = Value::Bool(params.stream.unwrap_or_default());
+    let mut hasher = DefaultHasher::new();
+    backend.hash(&mut hasher);
+    normalized.to_string().hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+fn disk_enabled() -> bool {
+    let config = CLEWDR_CONFIG.load();
+    config.response_cache_disk && !config.no_fs
+}
+
+/// Directory of the disk tier, next to the config file
+fn cache_dir() -> PathBuf {
+    CONFIG_PATH.with_file_name("response_cache")
+}
+
+fn disk_path(key: u64) -> PathBuf {
+    cache_dir().join(format!("{key:016x}.json"))
+}
+
+async fn read_disk(key: u64) -> Option<CachedResponse> {
+    if !disk_enabled() {
+        return None;
+    }
+    let path = disk_path(key);
+    let bytes = tokio::fs::read(&path).await.ok()?;
+    let cached = serde_json::from_slice::<CachedResponse>(&bytes).ok()?;
+    let ttl = CLEWDR_CONFIG.load().response_cache_ttl as i64;
+    if Utc::now().timestamp() - cached.created_at >= ttl {
+        let _ = tokio::fs::remove_file(&path).await;
+        return None;
+    }
+    Some(cached)
+}
+
+/// Spawns the job that drops expired responses from the disk tier and trims it to
+/// `response_cache_size` responses, the oldest going first
+pub fn spawn_disk_sweeper() {
+    spawn_periodic(
+        || {
+            if disk_enabled() {
+                SWEEP_INTERVAL.as_secs()
+            } else {
+                0
+            }
+        },
+        || async {
+            if let Err(e) = sweep_disk().await {
+                warn!("Failed to sweep the response cache directory: {}", e);
+            }
+        },
+    );
+}
+
+/// Runs one sweep of the disk tier, files being aged by their modification time
+async fn sweep_disk() -> std::io::Result<()> {
+    let (ttl, size) = {
+        let config = CLEWDR_CONFIG.load();
+        (config.response_cache_ttl, config.response_cache_size)
+    };
+    let mut dir = match tokio::fs::read_dir(cache_dir()).await {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut files = vec![];
+    while let Some(entry) = dir.next_entry().await? {
+        files.push((entry.metadata().await?.modified()?, entry.path()));
+    }
+    let ttl = Duration::from_secs(ttl);
+    let now = SystemTime::now();
+    // newest first, so the files past the size limit are the oldest
+    files.sort_by(|a, b| b.0.cmp(&a.0));
+    let mut removed = 0;
+    for (i, (modified, path)) in files.into_iter().enumerate() {
+        let expired = now.duration_since(modified).unwrap_or_default() >= ttl;
+        if expired || i as u64 >= size {
+            tokio::fs::remove_file(&path).await?;
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("[CACHE] swept {} responses from disk", removed);
+    }
+    Ok(())
+}
+
+fn write_disk(key: u64, cached: Arc<CachedResponse>) {
+    tokio::spawn(async move {
+        let Ok(json) = serde_json::to_vec(cached.as_ref()) else {
+            return;
+        };
+        if let Err(e) = tokio::fs::create_dir_all(cache_dir()).await {
+            warn!("Failed to create the response cache directory: {}", e);
+            return;
+        }
+        if let Err(e) = tokio::fs::write(disk_path(key), json).await {
+            warn!("Failed to write a cached response: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_deterministic_requests_are_hashed() {
+        let mut params = CreateMessageParams {
+            model: "claude-sonnet-4".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(request_hash("web", &params), None);
+
+        params.temperature = Some(0.0);
+        let hash = request_hash("web", &params);
+        assert!(hash.is_some());
+        params.stream = Some(false);
+        assert_eq!(request_hash("web", &params), hash);
+        assert_ne!(request_hash("code", &params), hash);
+        params.stream = Some(true);
+        assert_ne!(request_hash("web", &params), hash);
+    }
+
+    #[test]
+    fn hits_restore_the_serving_backend() {
+        // entries written before the backend was recorded still load
+        let cached = serde_json::from_str::<CachedResponse>(
+            r#"{"created_at":0,"stream":false,"body":"{}"}"#,
+        )
+        .unwrap();
+        assert!(!cached.web);
+        assert!(cached.to_response().headers().get(BACKEND_HEADER).is_none());
+
+        let cached = CachedResponse {
+            backend: Some("web".to_string()),
+            web: true,
+            ..cached
+        };
+        let response = cached.to_response();
+        assert_eq!(response.headers()[BACKEND_HEADER], "web");
+        assert_eq!(response.headers()[CACHE_HEADER], "HIT");
+    }
+
+    #[test]
+    fn memory_entries_keep_the_ttl_left() {
+        let cached = CachedResponse {
+            created_at: 1_000,
+            stream: false,
+            backend: None,
+            web: false,
+            body: String::new(),
+        };
+        let expiry = RemainingTtl(3600);
+        assert_eq!(expiry.at(&cached, 1_000), Duration::from_secs(3600));
+        assert_eq!(expiry.at(&cached, 4_000), Duration::from_secs(600));
+        assert_eq!(expiry.at(&cached, 9_000), Duration::ZERO);
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex, PoisonError},
+    time::Duration,
+};
+
+use chrono::Utc;
+use futures::{
+    FutureExt,
+    future::{BoxFuture, Shared},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    claude_code_state::ClaudeCodeState,
+    config::{CLEWDR_CONFIG, CookieStatus, Reason, TokenInfo},
+    error::ClewdrError,
+    services::{
+        cookie_actor::CookieActorHandle,
+        periodic::{run_all, spawn_periodic},
+    },
+};
+
+/// Time between two scans for tokens close to expiry
+const REFRESH_POLL: Duration = Duration::from_secs(60);
+
+/// Tokens refreshed at the same time by the background job
+const REFRESH_CONCURRENCY: usize = 4;
+
+/// Time a finished refresh is kept, so callers still holding the replaced token reuse it
+const FLIGHT_TTL: Duration = Duration::from_secs(300);
+
+/// Outcome of a refresh, shared by every caller waiting on it
+#[derive(Clone)]
+enum RefreshError {
+    Cookie(Reason),
+    Other(String),
+}
+
+impl From<ClewdrError> for RefreshError {
+    fn from(e: ClewdrError) -> Self {
+        match e {
+            ClewdrError::InvalidCookie { reason } => Self::Cookie(reason),
+            e => Self::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<RefreshError> for ClewdrError {
+    fn from(e: RefreshError) -> Self {
+        match e {
+            RefreshError::Cookie(reason) => reason.into(),
+            RefreshError::Other(message) => ClewdrError::Whatever {
+                message,
+                source: None,
+            },
+        }
+    }
+}
+
+type Flight = Shared<BoxFuture<'static, Result<TokenInfo, RefreshError>>>;
+
+/// Refreshes in flight or finished less than `FLIGHT_TTL` ago, by the token they replace
+static FLIGHTS: LazyLock<Mutex<HashMap<String, Flight>>> = LazyLock::new(Default::default);
+
+/// Key of the refresh replacing the token of the cookie
+fn flight_key(cookie: &CookieStatus) -> String {
+    match cookie.token {
+        Some(ref token) => format!("refresh:{}", token.refresh_token),
+        None => format!("cookie:{}", cookie.cookie),
+    }
+}
+
+/// Obtains a token for the cookie, or refreshes its token even if not expired yet, and
+/// stores it in the cookie actor
+async fn obtain(handle: CookieActorHandle, cookie: CookieStatus) -> Result<TokenInfo, ClewdrError> {
+    let ellipse = cookie.cookie.ellipse();
+    let mut state = ClaudeCodeState::from_cookie(handle.to_owned(), cookie)?;
+    if state.cookie.as_ref().is_some_and(|c| c.token.is_some()) {
+        info!("[TOKEN] {}: refreshing token", ellipse);
+        state.renew_token().await?;
+    } else {
+        info!("[TOKEN] {}: requesting new token", ellipse);
+        let org = state.get_organization().await?;
+        let code_res = state.exchange_code(&org).await?;
+        state.exchange_token(code_res).await?;
+    }
+    let cookie = state.cookie.ok_or(ClewdrError::UnexpectedNone {
+        msg: "No cookie after token exchange",
+    })?;
+    let token = cookie.token.to_owned().ok_or(ClewdrError::UnexpectedNone {
+        msg: "No token after token exchange",
+    })?;
+    handle.store_token(cookie).await?;
+    Ok(token)
+}
+
+/// Joins the refresh replacing the token of the cookie, starting it if none is in flight
+fn join(handle: &CookieActorHandle, cookie: &CookieStatus) -> Flight {
+    let key = flight_key(cookie);
+    let mut flights = FLIGHTS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(flight) = flights.get(&key) {
+        return flight.to_owned();
+    }
+    let flight = obtain(handle.to_owned(), cookie.to_owned())
+        .map(|r| r.map_err(RefreshError::from))
+        .boxed()
+        .shared();
+    flights.insert(key.to_owned(), flight.to_owned());
+    // drives the refresh to its end even if every caller gives up
+    let done = flight.to_owned();
+    tokio::spawn(async move {
+        if done.await.is_ok() {
+            tokio::time::sleep(FLIGHT_TTL).await;
+        }
+        FLIGHTS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&key);
+    });
+    flight
+}
+
+/// Returns a usable token of the cookie, obtaining or refreshing it when needed
+///
+/// Concurrent calls for the same cookie share a single refresh, so its refresh token is
+/// only redeemed once.
+pub async fn usable_token(
+    handle: &CookieActorHandle,
+    cookie: &CookieStatus,
+) -> Result<TokenInfo, ClewdrError> {
+    if let Some(ref token) = cookie.token
+        && !token.is_expired()
+    {
+        return Ok(token.to_owned());
+    }
+    Ok(join(handle, cookie).await?)
+}
+
+/// Spawns the job that refreshes tokens in the background before they expire
+///
+/// Tokens expiring within `token_refresh_lead` seconds are refreshed, so requests rarely
+/// wait on a refresh. A lead of 0 disables the job.
+pub fn spawn_token_refresher(handle: CookieActorHandle) {
+    spawn_periodic(
+        || match CLEWDR_CONFIG.load().token_refresh_lead {
+            0 => 0,
+            _ => REFRESH_POLL.as_secs(),
+        },
+        move || {
+            let handle = handle.to_owned();
+            async move { refresh_expiring(&handle).await }
+        },
+    );
+}
+
+/// Refreshes the tokens of the valid and exhausted cookies expiring within
+/// `token_refresh_lead` seconds
+async fn refresh_expiring(handle: &CookieActorHandle) {
+    let status = match handle.get_status().await {
+        Ok(status) => status,
+        Err(e) => {
+            error!("[TOKEN] Failed to get cookie status: {}", e);
+            return;
+        }
+    };
+    let deadline = Utc::now().timestamp() + CLEWDR_CONFIG.load().token_refresh_lead as i64;
+    let expiring = status
+        .valid
+        .into_iter()
+        .chain(status.exhausted)
+        .filter(|c| {
+            c.token
+                .as_ref()
+                .is_some_and(|t| t.expires_at.timestamp() <= deadline)
+        })
+        .collect::<Vec<_>>();
+    if expiring.is_empty() {
+        return;
+    }
+    let refreshes = expiring.into_iter().map(|cookie| async move {
+        let Err(e) = join(handle, &cookie).await else {
+            return true;
+        };
+        let e = ClewdrError::from(e);
+        warn!("[TOKEN] {}: {}", cookie.cookie.ellipse(), e);
+        if let ClewdrError::InvalidCookie { reason } = e
+            && let Err(e) = handle.return_cookie(cookie, Some(reason)).await
+        {
+            error!("[TOKEN] Failed to return cookie: {}", e);
+        }
+        false
+    });
+    let (refreshed, failed) = run_all(refreshes, REFRESH_CONCURRENCY).await;
+    info!("[TOKEN] refreshed {} tokens, {} failed", refreshed, failed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refreshes_are_keyed_by_the_replaced_token() {
+        let mut cookie = CookieStatus::oauth();
+        assert!(flight_key(&cookie).starts_with("cookie:oauth-"));
+        cookie.token = Some(TokenInfo::from_refresh_token("rt".to_string()));
+        assert_eq!(flight_key(&cookie), "refresh:rt");
+
+        let e = ClewdrError::from(RefreshError::from(ClewdrError::from(Reason::Null)));
+        assert!(matches!(
+            e,
+            ClewdrError::InvalidCookie {
+                reason: Reason::Null
+            }
+        ));
+    }
+}
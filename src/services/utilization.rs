@@ -0,0 +1,61 @@
+use chrono::Utc;
+use tracing::{error, info, warn};
+
+use crate::{
+    claude_code_state::ClaudeCodeState,
+    config::{CLEWDR_CONFIG, CookieStatus, Utilization},
+    error::ClewdrError,
+    services::{
+        cookie_actor::CookieActorHandle,
+        periodic::{run_all, spawn_periodic},
+    },
+};
+
+/// Cookies polled at the same time
+const POLL_CONCURRENCY: usize = 4;
+
+/// Spawns the job that periodically snapshots the utilization of every valid cookie
+///
+/// Dispatch skips cookies whose snapshot is over `session_utilization_threshold` or
+/// `weekly_utilization_threshold`, before Anthropic answers them with a 429.
+pub fn spawn_utilization_poller(handle: CookieActorHandle) {
+    spawn_periodic(
+        || CLEWDR_CONFIG.load().utilization_poll_interval,
+        move || {
+            let handle = handle.to_owned();
+            async move { poll_all(&handle).await }
+        },
+    );
+}
+
+/// Runs one round of polls over the valid cookies
+async fn poll_all(handle: &CookieActorHandle) {
+    let status = match handle.get_status().await {
+        Ok(status) => status,
+        Err(e) => {
+            error!("[USAGE] Failed to get cookie status: {}", e);
+            return;
+        }
+    };
+    let polls = status.valid.into_iter().map(|cookie| async move {
+        let ellipse = cookie.cookie.ellipse();
+        poll(handle, cookie)
+            .await
+            .inspect_err(|e| warn!("[USAGE] {}: {}", ellipse, e))
+            .is_ok()
+    });
+    let (polled, failed) = run_all(polls, POLL_CONCURRENCY).await;
+    info!("[USAGE] polled {} cookies, {} failed", polled, failed);
+}
+
+/// Fetches the utilization of a cookie and stores it in the cookie actor
+async fn poll(handle: &CookieActorHandle, cookie: CookieStatus) -> Result<(), ClewdrError> {
+    let mut state = ClaudeCodeState::from_cookie(handle.to_owned(), cookie)?;
+    let usage = state.fetch_usage_metrics().await?;
+    let utilization = Utilization::from_usage(&usage, Utc::now().timestamp());
+    if let Some(mut cookie) = state.cookie {
+        cookie.utilization = Some(utilization);
+        handle.record_utilization(cookie).await?;
+    }
+    Ok(())
+}
@@ -771,6 +771,7 @@ impl CreateMessageResponse {
             .iter()
             .map(|block| match block {
                 ContentBlock::Text { text, .. } => text.as_str(),
+                ContentBlock::Thinking { thinking, .. } => thinking.as_str(),
                 ContentBlock::Image {
                     source: ImageSource::Base64 { data, .. },
                     ..
@@ -966,6 +967,88 @@ pub struct StreamError {
     pub message: String,
 }
 
+/// Builds the events of a Claude message stream in the order of the spec
+///
+/// `message_start` is sent once, blocks are numbered consecutively and each one is
+/// stopped before the next starts, and the message ends with `message_delta` and
+/// `message_stop` after the last block.
+#[derive(Debug, Default)]
+pub struct StreamEventBuilder {
+    started: bool,
+    /// Index of the open block, or of the next one
+    index: usize,
+    open: bool,
+}
+
+impl StreamEventBuilder {
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Sends `message_start`, only once
+    pub fn start(&mut self, message: MessageStartContent, out: &mut Vec<StreamEvent>) {
+        if std::mem::replace(&mut self.started, true) {
+            return;
+        }
+        out.push(StreamEvent::MessageStart { message });
+    }
+
+    /// Starts a block, after stopping the open one
+    pub fn open(&mut self, content_block: ContentBlock, out: &mut Vec<StreamEvent>) {
+        self.close(out);
+        out.push(StreamEvent::ContentBlockStart {
+            index: self.index,
+            content_block,
+        });
+        self.open = true;
+    }
+
+    /// Sends a delta of the open block
+    pub fn delta(&mut self, delta: ContentBlockDelta, out: &mut Vec<StreamEvent>) {
+        if self.open {
+            out.push(StreamEvent::ContentBlockDelta {
+                index: self.index,
+                delta,
+            });
+        }
+    }
+
+    /// Stops the open block, if any
+    pub fn close(&mut self, out: &mut Vec<StreamEvent>) {
+        if std::mem::replace(&mut self.open, false) {
+            out.push(StreamEvent::ContentBlockStop { index: self.index });
+            self.index += 1;
+        }
+    }
+
+    /// Sends a whole block with its content in a single delta
+    pub fn block(
+        &mut self,
+        content_block: ContentBlock,
+        delta: ContentBlockDelta,
+        out: &mut Vec<StreamEvent>,
+    ) {
+        self.open(content_block, out);
+        self.delta(delta, out);
+        self.close(out);
+    }
+
+    /// Ends the message, after stopping the open block
+    pub fn finish(
+        &mut self,
+        delta: MessageDeltaContent,
+        usage: StreamUsage,
+        out: &mut Vec<StreamEvent>,
+    ) {
+        self.close(out);
+        out.push(StreamEvent::MessageDelta {
+            delta,
+            usage: Some(usage),
+        });
+        out.push(StreamEvent::MessageStop);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
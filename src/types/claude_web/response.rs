@@ -1,51 +1,30 @@
+use std::convert::Infallible;
+
 use async_stream::try_stream;
 use axum::{
     BoxError, Json,
-    response::{IntoResponse, Sse, sse::Event as SseEvent},
+    response::{IntoResponse, Sse},
 };
-use bytes::Bytes;
-use eventsource_stream::{EventStream, Eventsource};
-use futures::{Stream, TryStreamExt};
-use serde::Deserialize;
+use eventsource_stream::Eventsource;
+use futures::TryStreamExt;
 use url::Url;
 use wreq::Proxy;
 
 use crate::{
     claude_code_state::ClaudeCodeState,
-    claude_web_state::ClaudeWebState,
+    claude_web_state::{
+        ClaudeWebState,
+        tools::{apply_tool_calls, emulates_tools, stream_events},
+    },
     error::{CheckClaudeErr, ClewdrError},
-    types::claude::{
-        ContentBlock, CountMessageTokensResponse, CreateMessageParams, CreateMessageResponse,
-        Message, Role,
+    services::key_usage::record_usage,
+    types::{
+        claude::{ContentBlock, CountMessageTokensResponse, CreateMessageParams, Message, Role},
+        claude_web::stream::WebEventNormalizer,
     },
-    utils::print_out_text,
+    utils::{print_out_text, sse_event},
 };
 
-/// Merges server-sent events (SSE) from a stream into a single string
-/// Extracts and concatenates completion data from events
-///
-/// # Arguments
-/// * `stream` - Event stream to process
-///
-/// # Returns
-/// Combined completion text from all events
-pub async fn merge_sse(
-    stream: EventStream<impl Stream<Item = Result<Bytes, wreq::Error>>>,
-) -> Result<String, ClewdrError> {
-    #[derive(Deserialize)]
-    struct Data {
-        completion: String,
-    }
-    Ok(stream
-        .try_filter_map(async |event| {
-            Ok(serde_json::from_str::<Data>(&event.data)
-                .map(|data| data.completion)
-                .ok())
-        })
-        .try_collect()
-        .await?)
-}
-
 impl<S> From<S> for Message
 where
     S: Into<String>,
@@ -68,6 +47,10 @@ impl ClaudeWebState {
     /// This method transforms streams of bytes from Claude's web response into the appropriate
     /// format based on the client's requested API format (Claude or OpenAI). It handles both
     /// streaming and non-streaming responses, and manages caching for responses.
+    /// claude.ai events are normalized into a spec compliant Claude message stream, with
+    /// thinking in separate blocks, or aggregated into a single message.
+    /// Requests with emulated tools are buffered, so tool calls can be parsed out of the
+    /// whole completion, and replayed as a stream when the client asked for one.
     ///
     /// # Arguments
     /// * `input` - The response stream from the Claude Web API
@@ -78,13 +61,22 @@ impl ClaudeWebState {
         &mut self,
         wreq_res: wreq::Response,
     ) -> Result<axum::response::Response, ClewdrError> {
-        if self.stream {
+        let buffered = self.last_params.as_ref().is_some_and(emulates_tools);
+        if self.stream && !buffered {
             // Stream through while accumulating completion text; persist usage at end
             let mut input_tokens = self.usage.input_tokens as u64;
             let handle = self.cookie_actor_handle.clone();
             let cookie = self.cookie.clone();
+            // the cookie stays leased until the stream ends
+            let lease = self.lease.clone();
             let enable_precise = crate::config::CLEWDR_CONFIG.load().enable_web_count_tokens;
             let last_params = self.last_params.clone();
+            let api_key = self.api_key.clone();
+            let audit_id = self.audit_id;
+            let family = last_params
+                .as_ref()
+                .map(|p| crate::config::ModelFamily::from_model(&p.model))
+                .unwrap_or_default();
             let endpoint = self.endpoint.clone();
             let proxy = self.proxy.clone();
             let client = self.client.clone();
@@ -99,73 +91,46 @@ impl ClaudeWebState {
                 .bytes_stream()
                 .eventsource()
                 .map_err(axum::Error::new);
+            let model = last_params
+                .as_ref()
+                .map(|p| p.model.clone())
+                .unwrap_or_default();
             let stream = try_stream! {
-                let mut acc = String::new();
-                #[derive(serde::Deserialize)]
-                struct Data { completion: String }
+                let mut normalizer = WebEventNormalizer::new(model.to_owned(), input_tokens as u32);
                 futures::pin_mut!(stream);
                 while let Some(event) = stream.try_next().await? {
-                    if let Ok(d) = serde_json::from_str::<Data>(&event.data) {
-                        acc.push_str(&d.completion);
+                    for e in normalizer.push(&event.data) {
+                        yield sse_event(&e);
                     }
-                    let e = SseEvent::default().event(event.event).id(event.id);
-                    let e = if let Some(retry) = event.retry { e.retry(retry) } else { e };
-                    yield e.data(event.data);
                 }
                 // on end of stream, compute output tokens and persist totals
+                let acc = normalizer.text();
+                let mut out = 0;
                 if !acc.is_empty() {
                     // Prefer official count_tokens if enabled and possible; else estimate locally
-                    let mut out = None;
-                    if enable_precise
-                        && let Some(model) = last_params.as_ref().map(|p| p.model.clone())
-                    {
-                        out = count_code_output_tokens_for_text(
+                    let mut precise = None;
+                    if enable_precise && !model.is_empty() {
+                        precise = count_code_output_tokens_for_text(
                             cookie.clone(), endpoint.clone(), proxy.clone(), client.clone(),
-                            model, acc.clone(), handle.clone()
+                            model.clone(), acc.clone(), handle.clone()
                         ).await.map(|v| v as u64);
                     }
-                    let out = out.unwrap_or_else(|| {
+                    out = precise.unwrap_or_else(|| {
                         let usage = crate::types::claude::Usage { input_tokens: input_tokens as u32, output_tokens: 0 };
                         let resp = crate::types::claude::CreateMessageResponse::text(acc.clone(), Default::default(), usage);
                         resp.count_tokens() as u64
                     });
-                    if let Some(mut c) = cookie.clone() {
-                        let family = last_params
-                            .as_ref()
-                            .map(|p| p.model.as_str())
-                            .map(|m| {
-                                let m = m.to_ascii_lowercase();
-                                if m.contains("opus") {
-                                    crate::config::ModelFamily::Opus
-                                } else if m.contains("sonnet") {
-                                    crate::config::ModelFamily::Sonnet
-                                } else {
-                                    crate::config::ModelFamily::Other
-                                }
-                            })
-                            .unwrap_or(crate::config::ModelFamily::Other);
-                        c.add_and_bucket_usage(input_tokens, out, family);
-                        let _ = handle.return_cookie(c, None).await;
-                    }
-                } else if let Some(mut c) = cookie.clone() {
-                    // still persist input tokens to maintain parity
-                    let family = last_params
-                        .as_ref()
-                        .map(|p| p.model.as_str())
-                        .map(|m| {
-                            let m = m.to_ascii_lowercase();
-                            if m.contains("opus") {
-                                crate::config::ModelFamily::Opus
-                            } else if m.contains("sonnet") {
-                                crate::config::ModelFamily::Sonnet
-                            } else {
-                                crate::config::ModelFamily::Other
-                            }
-                        })
-                        .unwrap_or(crate::config::ModelFamily::Other);
-                    c.add_and_bucket_usage(input_tokens, 0, family);
+                }
+                record_usage("web", family, api_key.as_deref(), audit_id, input_tokens, out);
+                if let Some(mut c) = cookie.clone() {
+                    // input tokens are persisted even without output
+                    c.add_and_bucket_usage(input_tokens, out, family);
                     let _ = handle.return_cookie(c, None).await;
                 }
+                drop(lease);
+                for e in normalizer.finish(out as u32) {
+                    yield sse_event(&e);
+                }
             };
             // normalize error type for axum SSE
             let stream = stream.map_err(|e: axum::Error| -> BoxError { e.into() });
@@ -174,12 +139,23 @@ impl ClaudeWebState {
                 .into_response());
         }
 
-        let stream = wreq_res.bytes_stream();
-        let stream = stream.eventsource();
-        let text = merge_sse(stream).await?;
+        let model = self
+            .last_params
+            .as_ref()
+            .map(|p| p.model.clone())
+            .unwrap_or_default();
+        let mut normalizer = WebEventNormalizer::new(model, self.usage.input_tokens);
+        let stream = wreq_res.bytes_stream().eventsource();
+        futures::pin_mut!(stream);
+        while let Some(event) = stream.try_next().await? {
+            normalizer.push(&event.data);
+        }
+        let text = normalizer.text();
         print_out_text(text.to_owned(), "claude_web_non_stream.txt");
-        let mut response =
-            CreateMessageResponse::text(text.clone(), Default::default(), self.usage.to_owned());
+        let mut response = normalizer.into_response(self.usage.to_owned());
+        if buffered {
+            apply_tool_calls(&mut response, &text);
+        }
 
         // Prefer official counting if enabled
         let enable_precise = crate::config::CLEWDR_CONFIG.load().enable_web_count_tokens;
@@ -207,6 +183,15 @@ impl ClaudeWebState {
         response.usage = Some(usage.clone());
         self.persist_usage_totals(usage.input_tokens as u64, output_tokens as u64)
             .await;
+        if self.stream {
+            let events = stream_events(response)
+                .iter()
+                .map(|e| Ok::<_, Infallible>(sse_event(e)))
+                .collect::<Vec<_>>();
+            return Ok(Sse::new(futures::stream::iter(events))
+                .keep_alive(Default::default())
+                .into_response());
+        }
         Ok(Json(response).into_response())
     }
 }
@@ -0,0 +1,348 @@
+use serde_json::Value;
+
+use crate::types::claude::{
+    ContentBlock, ContentBlockDelta, CreateMessageResponse, MessageDeltaContent,
+    MessageStartContent, Role, StopReason, StreamError, StreamEvent, StreamEventBuilder,
+    StreamUsage, Usage,
+};
+
+/// Kind of the content block claude.ai is currently streaming
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Text,
+    Thinking,
+    /// Blocks of claude.ai's own tools, e.g. web search, which are not forwarded
+    Skipped,
+}
+
+/// Turns the events of a claude.ai completion into a spec compliant Claude message stream
+///
+/// claude.ai streams either raw `completion` events or Claude like events with extra
+/// fields, extra event types and blocks of its own tools. Only text and thinking blocks
+/// are kept and renumbered, and the events always come in the order `message_start`,
+/// `content_block_start/delta/stop` per block, `message_delta`, `message_stop`.
+/// The content is accumulated as well, to build non stream responses.
+pub struct WebEventNormalizer {
+    id: String,
+    model: String,
+    input_tokens: u32,
+    events: StreamEventBuilder,
+    finished: bool,
+    open: Option<BlockKind>,
+    content: Vec<ContentBlock>,
+    stop_reason: Option<StopReason>,
+    stop_sequence: Option<String>,
+}
+
+impl WebEventNormalizer {
+    pub fn new(model: impl Into<String>, input_tokens: u32) -> Self {
+        Self {
+            id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+            model: model.into(),
+            input_tokens,
+            events: StreamEventBuilder::default(),
+            finished: false,
+            open: None,
+            content: vec![],
+            stop_reason: None,
+            stop_sequence: None,
+        }
+    }
+
+    /// Translates the data of one claude.ai event
+    ///
+    /// `message_stop` is held back, the stream is closed by [`Self::finish`] once the
+    /// output tokens are known.
+    pub fn push(&mut self, data: &str) -> Vec<StreamEvent> {
+        let mut out = vec![];
+        if self.finished {
+            return out;
+        }
+        let Ok(event) = serde_json::from_str::<Value>(data) else {
+            return out;
+        };
+        match event["type"].as_str().unwrap_or_default() {
+            "message_start" => {
+                if !self.events.is_started()
+                    && let Some(id) = event["message"]["id"].as_str()
+                {
+                    self.id = id.to_string();
+                }
+                self.start(&mut out);
+            }
+            "content_block_start" => {
+                let block = &event["content_block"];
+                let kind = match block["type"].as_str() {
+                    Some("text") => BlockKind::Text,
+                    Some("thinking") => BlockKind::Thinking,
+                    _ => BlockKind::Skipped,
+                };
+                self.open(kind, &mut out);
+                let initial = match kind {
+                    BlockKind::Text => block["text"].as_str(),
+                    BlockKind::Thinking => block["thinking"].as_str(),
+                    BlockKind::Skipped => None,
+                };
+                if let Some(initial) = initial.filter(|s| !s.is_empty()) {
+                    self.delta(kind, initial, &mut out);
+                }
+            }
+            "content_block_delta" => {
+                let delta = &event["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") if self.open != Some(BlockKind::Skipped) => {
+                        if let Some(text) = delta["text"].as_str() {
+                            self.delta(BlockKind::Text, text, &mut out);
+                        }
+                    }
+                    Some("thinking_delta") => {
+                        if let Some(thinking) = delta["thinking"].as_str() {
+                            self.delta(BlockKind::Thinking, thinking, &mut out);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            "content_block_stop" => self.close(&mut out),
+            "completion" => {
+                if let Some(text) = event["completion"].as_str().filter(|s| !s.is_empty()) {
+                    self.delta(BlockKind::Text, text, &mut out);
+                }
+                self.record_stop(&event);
+            }
+            "message_delta" => self.record_stop(&event["delta"]),
+            "ping" if self.events.is_started() => out.push(StreamEvent::Ping),
+            "error" => {
+                self.close(&mut out);
+                self.finished = true;
+                let error = &event["error"];
+                out.push(StreamEvent::Error {
+                    error: StreamError {
+                        type_: error["type"].as_str().unwrap_or("api_error").to_string(),
+                        message: error["message"]
+                            .as_str()
+                            .unwrap_or("Unknown error")
+                            .to_string(),
+                    },
+                });
+            }
+            _ => {}
+        }
+        out
+    }
+
+    /// Closes the stream with the final `message_delta` and `message_stop`
+    pub fn finish(&mut self, output_tokens: u32) -> Vec<StreamEvent> {
+        let mut out = vec![];
+        if self.finished {
+            return out;
+        }
+        self.start(&mut out);
+        self.close(&mut out);
+        self.finished = true;
+        self.events.finish(
+            MessageDeltaContent {
+                stop_reason: Some(self.stop_reason.take().unwrap_or(StopReason::EndTurn)),
+                stop_sequence: self.stop_sequence.take(),
+            },
+            StreamUsage {
+                input_tokens: self.input_tokens,
+                output_tokens,
+            },
+            &mut out,
+        );
+        out
+    }
+
+    /// Text of all text blocks received so far
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|b| match b {
+                ContentBlock::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Builds a non stream response from the received content
+    pub fn into_response(self, usage: Usage) -> CreateMessageResponse {
+        let content = if self.content.is_empty() {
+            vec![ContentBlock::text("")]
+        } else {
+            self.content
+        };
+        CreateMessageResponse {
+            content,
+            id: self.id,
+            model: self.model,
+            role: Role::Assistant,
+            stop_reason: Some(self.stop_reason.unwrap_or(StopReason::EndTurn)),
+            stop_sequence: self.stop_sequence,
+            type_: "message".into(),
+            usage: Some(usage),
+        }
+    }
+
+    fn record_stop(&mut self, v: &Value) {
+        if let Some(reason) = v["stop_reason"]
+            .as_str()
+            .and_then(|r| serde_json::from_value(Value::from(r)).ok())
+        {
+            self.stop_reason = Some(reason);
+        }
+        if let Some(sequence) = v["stop_sequence"].as_str() {
+            self.stop_sequence = Some(sequence.to_string());
+        }
+    }
+
+    fn start(&mut self, out: &mut Vec<StreamEvent>) {
+        if self.events.is_started() {
+            return;
+        }
+        let message = MessageStartContent {
+            id: self.id.to_owned(),
+            type_: "message".into(),
+            role: Role::Assistant,
+            content: vec![],
+            model: self.model.to_owned(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Some(Usage {
+                input_tokens: self.input_tokens,
+                output_tokens: 0,
+            }),
+        };
+        self.events.start(message, out);
+    }
+
+    fn open(&mut self, kind: BlockKind, out: &mut Vec<StreamEvent>) {
+        self.start(out);
+        self.close(out);
+        self.open = Some(kind);
+        let block = match kind {
+            BlockKind::Text => ContentBlock::text(""),
+            BlockKind::Thinking => ContentBlock::Thinking {
+                signature: String::new(),
+                thinking: String::new(),
+            },
+            BlockKind::Skipped => return,
+        };
+        self.content.push(block.to_owned());
+        self.events.open(block, out);
+    }
+
+    fn close(&mut self, out: &mut Vec<StreamEvent>) {
+        // skipped blocks were never started
+        self.open = None;
+        self.events.close(out);
+    }
+
+    fn delta(&mut self, kind: BlockKind, s: &str, out: &mut Vec<StreamEvent>) {
+        if self.open != Some(kind) {
+            self.open(kind, out);
+        }
+        let delta = match self.content.last_mut() {
+            Some(ContentBlock::Text { text, .. }) => {
+                text.push_str(s);
+                ContentBlockDelta::TextDelta {
+                    text: s.to_string(),
+                }
+            }
+            Some(ContentBlock::Thinking { thinking, .. }) => {
+                thinking.push_str(s);
+                ContentBlockDelta::ThinkingDelta {
+                    thinking: s.to_string(),
+                }
+            }
+            _ => return,
+        };
+        self.events.delta(delta, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(events: &[StreamEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|e| {
+                serde_json::to_value(e).unwrap()["type"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn separates_thinking_and_drops_web_tool_blocks() {
+        let mut n = WebEventNormalizer::new("claude-sonnet-4", 10);
+        let mut events = vec![];
+        for data in [
+            r#"{"type":"message_start","message":{"id":"chatcompl_1","uuid":"x"}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"hmm"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","name":"web_search"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"content_block_start","index":2,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":2,"delta":{"type":"text_delta","text":"Hi"}}"#,
+            r#"{"type":"content_block_stop","index":2}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"max_tokens"}}"#,
+            r#"{"type":"message_limit","message_limit":{}}"#,
+            r#"{"type":"message_stop"}"#,
+        ] {
+            events.extend(n.push(data));
+        }
+        events.extend(n.finish(3));
+        assert_eq!(
+            types(&events),
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert!(matches!(
+            events[4],
+            StreamEvent::ContentBlockStart { index: 1, .. }
+        ));
+        let response = n.into_response(Usage::default());
+        assert_eq!(response.id, "chatcompl_1");
+        assert!(matches!(response.stop_reason, Some(StopReason::MaxTokens)));
+        assert!(
+            matches!(response.content[0], ContentBlock::Thinking { ref thinking, .. } if thinking == "hmm")
+        );
+    }
+
+    #[test]
+    fn wraps_raw_completions() {
+        let mut n = WebEventNormalizer::new("claude-sonnet-4", 10);
+        let mut events = n.push(r#"{"type":"completion","completion":"Hel","stop_reason":null}"#);
+        events
+            .extend(n.push(r#"{"type":"completion","completion":"lo","stop_reason":"end_turn"}"#));
+        events.extend(n.finish(1));
+        assert_eq!(
+            types(&events),
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_eq!(n.text(), "Hello");
+    }
+}
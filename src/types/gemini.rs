@@ -0,0 +1,787 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::types::claude::{
+    ContentBlock, ContentBlockDelta, CreateMessageParams, CreateMessageResponse, ImageSource,
+    MessageContent, MessageDeltaContent, MessageStartContent, OutputFormat, Role, StopReason,
+    StreamEvent, StreamEventBuilder, StreamUsage, Thinking, Tool as ClaudeTool, ToolChoice, Usage,
+};
+
+/// Body of `generateContent` and `streamGenerateContent`
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentRequest {
+    pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+}
+
+/// A turn of the conversation
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Content {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+/// A piece of a turn, exactly one of the data fields is set
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Blob>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<FileData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
+    /// Whether `text` is a thought summary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thought: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thought_signature: Option<String>,
+}
+
+impl Part {
+    fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: Some(text.into()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FileData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    pub file_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FunctionResponse {
+    pub name: String,
+    pub response: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_json_schema: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking_config: Option<ThinkingConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThinkingConfig {
+    pub include_thoughts: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionDeclaration {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub parameters_json_schema: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolConfig {
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCallingConfig {
+    /// `AUTO`, `ANY` or `NONE`
+    pub mode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_function_names: Option<Vec<String>>,
+}
+
+/// Response of `generateContent`, also a single chunk of `streamGenerateContent`
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_metadata: Option<UsageMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    #[serde(default)]
+    pub content: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: u32,
+    #[serde(default)]
+    pub candidates_token_count: u32,
+    #[serde(default)]
+    pub thoughts_token_count: u32,
+}
+
+impl UsageMetadata {
+    /// Output tokens as billed, thoughts included
+    pub fn output_tokens(&self) -> u32 {
+        self.candidates_token_count + self.thoughts_token_count
+    }
+}
+
+/// Maps a Gemini finish reason to the Claude stop reason
+fn stop_reason(finish_reason: &str, tool_use: bool) -> StopReason {
+    match finish_reason {
+        "MAX_TOKENS" => StopReason::MaxTokens,
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" => {
+            StopReason::Refusal
+        }
+        _ if tool_use => StopReason::ToolUse,
+        _ => StopReason::EndTurn,
+    }
+}
+
+/// Key of the thought signature of a function call in the `caller` of its tool use block
+///
+/// Gemini rejects function calls sent back without the signature they were generated with.
+const THOUGHT_SIGNATURE: &str = "gemini_thought_signature";
+
+/// Stores the thought signature of a function call in the `caller` of its tool use block
+fn signature_caller(signature: Option<String>) -> Option<Value> {
+    signature.map(|s| json!({ (THOUGHT_SIGNATURE): s }))
+}
+
+fn tool_use_id() -> String {
+    format!("toolu_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Splits a `data:` URL into its media type and base64 payload
+fn parse_data_url(url: &str) -> Option<Blob> {
+    let (meta, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime_type = meta.strip_suffix(";base64")?;
+    Some(Blob {
+        mime_type: mime_type.to_string(),
+        data: data.to_string(),
+    })
+}
+
+/// Flattens the content of a tool result into the object Gemini expects
+fn tool_result_response(content: Value, is_error: bool) -> Value {
+    let content = match content {
+        Value::Array(blocks) => Value::String(
+            blocks
+                .iter()
+                .filter_map(|b| b["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        other => other,
+    };
+    if is_error {
+        json!({ "error": content })
+    } else {
+        json!({ "output": content })
+    }
+}
+
+/// Converts a Claude content block to a Gemini part
+///
+/// `tool_names` maps tool use ids seen so far to their function names,
+/// Gemini matches function responses by name instead of id.
+fn convert_block(block: ContentBlock, tool_names: &mut HashMap<String, String>) -> Option<Part> {
+    match block {
+        ContentBlock::Text { text, .. } => Some(Part::text(text)),
+        ContentBlock::Image { source, .. } => match source {
+            ImageSource::Base64 { media_type, data } => Some(Part {
+                inline_data: Some(Blob {
+                    mime_type: media_type,
+                    data,
+                }),
+                ..Default::default()
+            }),
+            ImageSource::Url { url } => Some(Part {
+                file_data: Some(FileData {
+                    mime_type: None,
+                    file_uri: url,
+                }),
+                ..Default::default()
+            }),
+            ImageSource::File { .. } => None,
+        },
+        ContentBlock::ImageUrl { image_url } => Some(match parse_data_url(&image_url.url) {
+            Some(blob) => Part {
+                inline_data: Some(blob),
+                ..Default::default()
+            },
+            None => Part {
+                file_data: Some(FileData {
+                    mime_type: None,
+                    file_uri: image_url.url,
+                }),
+                ..Default::default()
+            },
+        }),
+        ContentBlock::Document { source, .. } => match source["type"].as_str() {
+            Some("base64") => Some(Part {
+                inline_data: Some(Blob {
+                    mime_type: source["media_type"].as_str()?.to_string(),
+                    data: source["data"].as_str()?.to_string(),
+                }),
+                ..Default::default()
+            }),
+            Some("text") => Some(Part::text(source["data"].as_str()?)),
+            _ => None,
+        },
+        ContentBlock::ToolUse {
+            id,
+            name,
+            input,
+            caller,
+            ..
+        } => {
+            tool_names.insert(id, name.to_owned());
+            Some(Part {
+                function_call: Some(FunctionCall { name, args: input }),
+                thought_signature: caller
+                    .as_ref()
+                    .and_then(|c| c[THOUGHT_SIGNATURE].as_str())
+                    .map(str::to_string),
+                ..Default::default()
+            })
+        }
+        ContentBlock::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+            ..
+        } => Some(Part {
+            function_response: Some(FunctionResponse {
+                name: tool_names.get(&tool_use_id).cloned().unwrap_or(tool_use_id),
+                response: tool_result_response(content, is_error.unwrap_or_default()),
+            }),
+            ..Default::default()
+        }),
+        // thinking signatures are Claude specific, other blocks have no Gemini equivalent
+        _ => None,
+    }
+}
+
+impl From<CreateMessageParams> for GenerateContentRequest {
+    fn from(params: CreateMessageParams) -> Self {
+        let system_instruction = params.system.and_then(|system| {
+            let text = match system {
+                Value::String(text) => text,
+                Value::Array(blocks) => blocks
+                    .iter()
+                    .filter_map(|b| b["text"].as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                _ => return None,
+            };
+            Some(Content {
+                role: None,
+                parts: vec![Part::text(text)],
+            })
+        });
+
+        let mut tool_names = HashMap::new();
+        let contents = params
+            .messages
+            .into_iter()
+            .filter_map(|m| {
+                let role = match m.role {
+                    Role::Assistant => "model",
+                    Role::User | Role::System => "user",
+                };
+                let parts = match m.content {
+                    MessageContent::Text { content } => {
+                        vec![Part::text(content)]
+                    }
+                    MessageContent::Blocks { content } => content
+                        .into_iter()
+                        .filter_map(|b| convert_block(b, &mut tool_names))
+                        .collect(),
+                };
+                (!parts.is_empty()).then(|| Content {
+                    role: Some(role.to_string()),
+                    parts,
+                })
+            })
+            .collect();
+
+        let json_schema = params
+            .output_config
+            .and_then(|c| c.format)
+            .or(params.output_format)
+            .map(|f| match f {
+                OutputFormat::JsonSchema { schema } => schema,
+            });
+        let thinking_config = match params.thinking {
+            Some(Thinking::Enabled { budget_tokens }) => Some(ThinkingConfig {
+                include_thoughts: true,
+                thinking_budget: Some(budget_tokens),
+            }),
+            _ => None,
+        };
+        let generation_config = GenerationConfig {
+            max_output_tokens: Some(params.max_tokens),
+            temperature: params.temperature,
+            top_p: params.top_p,
+            top_k: params.top_k,
+            stop_sequences: params.stop_sequences.filter(|s| !s.is_empty()),
+            response_mime_type: json_schema.as_ref().map(|_| "application/json".to_string()),
+            response_json_schema: json_schema,
+            thinking_config,
+        };
+
+        let function_declarations = params
+            .tools
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|t| match t {
+                ClaudeTool::Custom(t) => Some(FunctionDeclaration {
+                    name: t.name,
+                    description: t.description,
+                    parameters_json_schema: t.input_schema,
+                }),
+                // server side tools can not be executed by Gemini
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let tool_config = params.tool_choice.map(|choice| {
+            let (mode, allowed_function_names) = match choice {
+                ToolChoice::Auto { .. } => ("AUTO", None),
+                ToolChoice::Any { .. } => ("ANY", None),
+                ToolChoice::Tool { name, .. } => ("ANY", Some(vec![name])),
+                ToolChoice::None => ("NONE", None),
+            };
+            ToolConfig {
+                function_calling_config: FunctionCallingConfig {
+                    mode: mode.to_string(),
+                    allowed_function_names,
+                },
+            }
+        });
+
+        Self {
+            contents,
+            system_instruction,
+            generation_config: Some(generation_config),
+            tools: if function_declarations.is_empty() {
+                vec![]
+            } else {
+                vec![Tool {
+                    function_declarations,
+                }]
+            },
+            tool_config,
+        }
+    }
+}
+
+impl GenerateContentResponse {
+    /// Converts a complete response to a Claude message
+    ///
+    /// `model` is reported when upstream does not name the model version.
+    pub fn into_claude(self, model: &str) -> CreateMessageResponse {
+        let usage = self.usage_metadata.unwrap_or_default();
+        let candidate = self.candidates.into_iter().next().unwrap_or_default();
+        let content = candidate
+            .content
+            .map(|c| c.parts)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|part| {
+                if let Some(call) = part.function_call {
+                    return Some(ContentBlock::ToolUse {
+                        id: tool_use_id(),
+                        name: call.name,
+                        input: call.args,
+                        cache_control: None,
+                        caller: signature_caller(part.thought_signature),
+                    });
+                }
+                let text = part.text?;
+                if part.thought == Some(true) {
+                    return Some(ContentBlock::Thinking {
+                        signature: part.thought_signature.unwrap_or_default(),
+                        thinking: text,
+                    });
+                }
+                Some(ContentBlock::text(text))
+            })
+            .collect::<Vec<_>>();
+        let tool_use = content
+            .iter()
+            .any(|b| matches!(b, ContentBlock::ToolUse { .. }));
+        CreateMessageResponse {
+            content,
+            id: self
+                .response_id
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            model: self.model_version.unwrap_or_else(|| model.to_string()),
+            role: Role::Assistant,
+            stop_reason: candidate.finish_reason.map(|r| stop_reason(&r, tool_use)),
+            stop_sequence: None,
+            type_: "message".into(),
+            usage: Some(Usage {
+                input_tokens: usage.prompt_token_count,
+                output_tokens: usage.output_tokens(),
+            }),
+        }
+    }
+}
+
+/// Kind of the content block currently open in a translated stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpenBlock {
+    Text,
+    Thinking,
+}
+
+/// Translates `streamGenerateContent` chunks into Claude stream events
+///
+/// Gemini streams whole parts without explicit block boundaries, consecutive
+/// text or thought parts are merged into one block and function calls are
+/// emitted as complete tool use blocks.
+#[derive(Debug)]
+pub struct GeminiStreamTranslator {
+    model: String,
+    events: StreamEventBuilder,
+    open: Option<OpenBlock>,
+    tool_use: bool,
+    finish_reason: Option<String>,
+    usage: UsageMetadata,
+}
+
+impl GeminiStreamTranslator {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            events: StreamEventBuilder::default(),
+            open: None,
+            tool_use: false,
+            finish_reason: None,
+            usage: UsageMetadata::default(),
+        }
+    }
+
+    /// Usage reported so far
+    pub fn usage(&self) -> UsageMetadata {
+        self.usage
+    }
+
+    fn close_block(&mut self, events: &mut Vec<StreamEvent>) {
+        self.open = None;
+        self.events.close(events);
+    }
+
+    fn open_block(&mut self, kind: OpenBlock, events: &mut Vec<StreamEvent>) {
+        if self.open == Some(kind) {
+            return;
+        }
+        let content_block = match kind {
+            OpenBlock::Text => ContentBlock::text(""),
+            OpenBlock::Thinking => ContentBlock::Thinking {
+                signature: String::new(),
+                thinking: String::new(),
+            },
+        };
+        self.events.open(content_block, events);
+        self.open = Some(kind);
+    }
+
+    /// Emits `message_start` ahead of the first event
+    fn start(&mut self, id: Option<String>, model: Option<String>, events: &mut Vec<StreamEvent>) {
+        if self.events.is_started() {
+            return;
+        }
+        let message = MessageStartContent {
+            id: id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            type_: "message".into(),
+            role: Role::Assistant,
+            content: vec![],
+            model: model.unwrap_or_else(|| self.model.to_owned()),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Some(Usage {
+                input_tokens: self.usage.prompt_token_count,
+                output_tokens: 0,
+            }),
+        };
+        self.events.start(message, events);
+    }
+
+    /// Translates one upstream chunk
+    pub fn push(&mut self, chunk: GenerateContentResponse) -> Vec<StreamEvent> {
+        let mut events = vec![];
+        if let Some(usage) = chunk.usage_metadata {
+            self.usage = usage;
+        }
+        self.start(chunk.response_id, chunk.model_version, &mut events);
+        let Some(candidate) = chunk.candidates.into_iter().next() else {
+            return events;
+        };
+        for part in candidate.content.map(|c| c.parts).unwrap_or_default() {
+            if let Some(call) = part.function_call {
+                self.open = None;
+                self.tool_use = true;
+                self.events.block(
+                    ContentBlock::ToolUse {
+                        id: tool_use_id(),
+                        name: call.name,
+                        input: json!({}),
+                        cache_control: None,
+                        caller: signature_caller(part.thought_signature),
+                    },
+                    ContentBlockDelta::InputJsonDelta {
+                        partial_json: call.args.to_string(),
+                    },
+                    &mut events,
+                );
+                continue;
+            }
+            let Some(text) = part.text else {
+                continue;
+            };
+            if part.thought == Some(true) {
+                self.open_block(OpenBlock::Thinking, &mut events);
+                self.events.delta(
+                    ContentBlockDelta::ThinkingDelta { thinking: text },
+                    &mut events,
+                );
+                if let Some(signature) = part.thought_signature {
+                    self.events
+                        .delta(ContentBlockDelta::SignatureDelta { signature }, &mut events);
+                }
+            } else if !text.is_empty() {
+                self.open_block(OpenBlock::Text, &mut events);
+                self.events
+                    .delta(ContentBlockDelta::TextDelta { text }, &mut events);
+            }
+        }
+        if candidate.finish_reason.is_some() {
+            self.finish_reason = candidate.finish_reason;
+        }
+        events
+    }
+
+    /// Closes the message once upstream ended the stream
+    ///
+    /// The message is still started when upstream sent no chunk at all.
+    pub fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = vec![];
+        self.start(None, None, &mut events);
+        self.close_block(&mut events);
+        self.events.finish(
+            MessageDeltaContent {
+                stop_reason: Some(stop_reason(
+                    self.finish_reason.as_deref().unwrap_or("STOP"),
+                    self.tool_use,
+                )),
+                stop_sequence: None,
+            },
+            StreamUsage {
+                input_tokens: self.usage.prompt_token_count,
+                output_tokens: self.usage.output_tokens(),
+            },
+            &mut events,
+        );
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_claude_request() {
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "gemini-2.5-flash",
+            "max_tokens": 256,
+            "system": "be brief",
+            "messages": [
+                { "role": "user", "content": "weather in Paris?" },
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "toolu_1", "name": "weather", "input": { "city": "Paris" } }
+                ] },
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_1", "content": "sunny" }
+                ] }
+            ],
+            "tools": [
+                { "name": "weather", "input_schema": { "type": "object" } }
+            ],
+            "tool_choice": { "type": "tool", "name": "weather" }
+        }))
+        .unwrap();
+        let body = serde_json::to_value(GenerateContentRequest::from(params)).unwrap();
+
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "be brief");
+        assert_eq!(body["contents"][1]["role"], "model");
+        assert_eq!(
+            body["contents"][1]["parts"][0]["functionCall"]["args"]["city"],
+            "Paris"
+        );
+        let response = &body["contents"][2]["parts"][0]["functionResponse"];
+        assert_eq!(response["name"], "weather");
+        assert_eq!(response["response"]["output"], "sunny");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 256);
+        assert_eq!(
+            body["tools"][0]["functionDeclarations"][0]["name"],
+            "weather"
+        );
+        assert_eq!(body["toolConfig"]["functionCallingConfig"]["mode"], "ANY");
+    }
+
+    #[test]
+    fn translates_stream() {
+        let chunk =
+            |value: Value| serde_json::from_value::<GenerateContentResponse>(value).unwrap();
+        let mut translator = GeminiStreamTranslator::new("gemini-2.5-pro");
+        let mut events = translator.push(chunk(json!({
+            "candidates": [{ "content": { "role": "model", "parts": [
+                { "text": "planning", "thought": true },
+                { "text": "Hel" }
+            ] } }],
+            "usageMetadata": { "promptTokenCount": 7 }
+        })));
+        events.extend(translator.push(chunk(json!({
+            "candidates": [{ "content": { "role": "model", "parts": [
+                { "text": "lo" },
+                { "functionCall": { "name": "lookup", "args": { "q": "x" } } }
+            ] }, "finishReason": "STOP" }],
+            "usageMetadata": { "promptTokenCount": 7, "candidatesTokenCount": 5, "thoughtsTokenCount": 3 }
+        }))));
+        events.extend(translator.finish());
+
+        let types = events
+            .iter()
+            .map(|e| {
+                serde_json::to_value(e).unwrap()["type"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        let delta = serde_json::to_value(&events[11]).unwrap();
+        assert_eq!(delta["delta"]["stop_reason"], "tool_use");
+        assert_eq!(delta["usage"]["output_tokens"], 8);
+    }
+
+    #[test]
+    fn round_trips_function_call_signatures() {
+        let response = serde_json::from_value::<GenerateContentResponse>(json!({
+            "candidates": [{ "content": { "role": "model", "parts": [
+                { "functionCall": { "name": "lookup", "args": { "q": "x" } }, "thoughtSignature": "c2ln" }
+            ] }, "finishReason": "STOP" }]
+        }))
+        .unwrap()
+        .into_claude("gemini-2.5-pro");
+        let tool_use = serde_json::to_value(&response.content[0]).unwrap();
+        assert_eq!(tool_use["caller"][THOUGHT_SIGNATURE], "c2ln");
+
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 256,
+            "messages": [
+                { "role": "user", "content": "look up x" },
+                { "role": "assistant", "content": [tool_use] }
+            ]
+        }))
+        .unwrap();
+        let body = serde_json::to_value(GenerateContentRequest::from(params)).unwrap();
+        assert_eq!(body["contents"][1]["parts"][0]["thoughtSignature"], "c2ln");
+    }
+
+    #[test]
+    fn starts_empty_stream() {
+        let events = GeminiStreamTranslator::new("gemini-2.5-pro").finish();
+        let types = events
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap()["type"].to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["message_start", "message_delta", "message_stop"]);
+    }
+}
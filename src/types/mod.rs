@@ -1,3 +1,4 @@
 pub mod claude;
 pub mod claude_web;
+pub mod gemini;
 pub mod oai;
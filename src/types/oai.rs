@@ -3,7 +3,6 @@ use serde_json::{Value, json};
 use tiktoken_rs::o200k_base;
 
 use super::claude::{CreateMessageParams as ClaudeCreateMessageParams, *};
-use crate::types::claude::Message;
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -16,26 +15,48 @@ pub enum Effort {
 
 impl From<CreateMessageParams> for ClaudeCreateMessageParams {
     fn from(params: CreateMessageParams) -> Self {
-        let (systems, messages): (Vec<Message>, Vec<Message>) = params
+        let (systems, messages): (Vec<OaiMessage>, Vec<OaiMessage>) = params
             .messages
             .into_iter()
-            .partition(|m| m.role == Role::System);
+            .partition(|m| matches!(m.role, OaiRole::System | OaiRole::Developer));
         let systems = systems
             .into_iter()
-            .map(|m| m.content)
-            .flat_map(|c| match c {
-                MessageContent::Text { content } => vec![ContentBlock::text(content)],
-                MessageContent::Blocks { content } => content,
-            })
+            .filter_map(|m| m.content)
+            .flat_map(OaiContent::into_blocks)
             .filter(|b| matches!(b, ContentBlock::Text { .. }))
             .map(|b| json!(b))
             .collect::<Vec<_>>();
         let system = (!systems.is_empty()).then(|| json!(systems));
+        let has_tools = params.tools.as_ref().is_some_and(|t| !t.is_empty());
+        let tool_choice = params
+            .tool_choice
+            .and_then(convert_tool_choice)
+            // `parallel_tool_calls` alone still has to reach Claude through a tool choice
+            .or_else(|| {
+                (has_tools && params.parallel_tool_calls == Some(false)).then_some(
+                    ToolChoice::Auto {
+                        disable_parallel_tool_use: None,
+                    },
+                )
+            })
+            .map(|choice| match (choice, params.parallel_tool_calls) {
+                (ToolChoice::Auto { .. }, Some(parallel)) => ToolChoice::Auto {
+                    disable_parallel_tool_use: Some(!parallel),
+                },
+                (ToolChoice::Any { .. }, Some(parallel)) => ToolChoice::Any {
+                    disable_parallel_tool_use: Some(!parallel),
+                },
+                (ToolChoice::Tool { name, .. }, Some(parallel)) => ToolChoice::Tool {
+                    name,
+                    disable_parallel_tool_use: Some(!parallel),
+                },
+                (choice, _) => choice,
+            });
         Self {
             max_tokens: (params.max_tokens.or(params.max_completion_tokens))
                 .unwrap_or_else(default_max_tokens),
             system,
-            messages,
+            messages: convert_messages(messages),
             model: params.model,
             container: None,
             context_management: None,
@@ -48,8 +69,10 @@ impl From<CreateMessageParams> for ClaudeCreateMessageParams {
             stream: params.stream,
             top_k: params.top_k,
             top_p: params.top_p,
-            tools: params.tools,
-            tool_choice: params.tool_choice,
+            tools: params
+                .tools
+                .map(|tools| tools.into_iter().map(Into::into).collect()),
+            tool_choice,
             metadata: params.metadata,
             output_config: None,
             output_format: None,
@@ -59,6 +82,219 @@ impl From<CreateMessageParams> for ClaudeCreateMessageParams {
     }
 }
 
+/// Converts OpenAI chat messages into Claude messages
+///
+/// Assistant `tool_calls` become `tool_use` blocks, and consecutive `tool` messages
+/// are folded into a single user turn of `tool_result` blocks, as Claude expects the
+/// results of parallel calls to arrive together.
+fn convert_messages(messages: Vec<OaiMessage>) -> Vec<Message> {
+    let mut result: Vec<Message> = Vec::with_capacity(messages.len());
+    let mut pending_results: Vec<ContentBlock> = vec![];
+    for msg in messages {
+        if msg.role == OaiRole::Tool {
+            pending_results.push(ContentBlock::ToolResult {
+                tool_use_id: msg.tool_call_id.unwrap_or_default(),
+                content: msg.content.map(OaiContent::into_value).unwrap_or(json!("")),
+                cache_control: None,
+                is_error: None,
+            });
+            continue;
+        }
+        if !pending_results.is_empty() {
+            result.push(Message::new_blocks(
+                Role::User,
+                std::mem::take(&mut pending_results),
+            ));
+        }
+        let role = match msg.role {
+            OaiRole::Assistant => Role::Assistant,
+            _ => Role::User,
+        };
+        let tool_calls = msg.tool_calls.unwrap_or_default();
+        if tool_calls.is_empty() {
+            let content = match msg.content {
+                Some(OaiContent::Text(content)) => MessageContent::Text { content },
+                Some(OaiContent::Parts(content)) => MessageContent::Blocks { content },
+                None => MessageContent::Text {
+                    content: String::new(),
+                },
+            };
+            result.push(Message { role, content });
+            continue;
+        }
+        let mut blocks = msg.content.map(OaiContent::into_blocks).unwrap_or_default();
+        blocks.retain(|b| !matches!(b, ContentBlock::Text { text, .. } if text.is_empty()));
+        blocks.extend(tool_calls.into_iter().map(|call| {
+            ContentBlock::ToolUse {
+                input: serde_json::from_str(&call.function.arguments)
+                    .ok()
+                    .filter(Value::is_object)
+                    .unwrap_or_else(|| json!({})),
+                id: call.id,
+                name: call.function.name,
+                cache_control: None,
+                caller: None,
+            }
+        }));
+        result.push(Message::new_blocks(role, blocks));
+    }
+    if !pending_results.is_empty() {
+        result.push(Message::new_blocks(Role::User, pending_results));
+    }
+    result
+}
+
+/// Maps an OpenAI `tool_choice` value onto Claude's tool choice
+fn convert_tool_choice(choice: Value) -> Option<ToolChoice> {
+    match choice.as_str() {
+        Some("none") => return Some(ToolChoice::None),
+        Some("auto") => {
+            return Some(ToolChoice::Auto {
+                disable_parallel_tool_use: None,
+            });
+        }
+        Some("required") => {
+            return Some(ToolChoice::Any {
+                disable_parallel_tool_use: None,
+            });
+        }
+        Some(_) => return None,
+        None => {}
+    }
+    if choice["type"] == "function" {
+        let name = choice["function"]["name"].as_str()?;
+        return Some(ToolChoice::Tool {
+            name: name.to_string(),
+            disable_parallel_tool_use: None,
+        });
+    }
+    serde_json::from_value(choice).ok()
+}
+
+/// Role of an OpenAI chat message
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OaiRole {
+    System,
+    Developer,
+    User,
+    Assistant,
+    Tool,
+}
+
+/// Content of an OpenAI chat message, either a plain string or content parts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum OaiContent {
+    Text(String),
+    Parts(Vec<ContentBlock>),
+}
+
+impl OaiContent {
+    fn into_blocks(self) -> Vec<ContentBlock> {
+        match self {
+            OaiContent::Text(text) => vec![ContentBlock::text(text)],
+            OaiContent::Parts(parts) => parts,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            OaiContent::Text(text) => json!(text),
+            OaiContent::Parts(parts) => json!(parts),
+        }
+    }
+}
+
+/// Message in an OpenAI chat completion request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OaiMessage {
+    pub role: OaiRole,
+    #[serde(default)]
+    pub content: Option<OaiContent>,
+    /// Function calls issued by the assistant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Identifier of the call a `tool` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Function call issued by the assistant
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub type_: String,
+    pub function: FunctionCall,
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON encoded arguments
+    #[serde(default)]
+    pub arguments: String,
+}
+
+/// Tool definition accepted by the OpenAI compatible endpoints
+///
+/// OpenAI `{"type": "function", "function": {...}}` definitions are converted into
+/// Claude custom tools, while Claude native tool definitions are passed through.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum OaiTool {
+    Function { function: FunctionDefinition },
+    Claude(Tool),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl From<OaiTool> for Tool {
+    fn from(tool: OaiTool) -> Self {
+        match tool {
+            OaiTool::Function { function } => Tool::Custom(CustomTool {
+                name: function.name,
+                description: function.description,
+                input_schema: function
+                    .parameters
+                    .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                allowed_callers: None,
+                cache_control: None,
+                defer_loading: None,
+                input_examples: None,
+                strict: function.strict,
+                type_: None,
+                extra: Default::default(),
+            }),
+            OaiTool::Claude(tool) => tool,
+        }
+    }
+}
+
+/// Options of a streamed response
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct StreamOptions {
+    /// Whether the stream ends with a chunk holding the token usage of the request
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct CreateMessageParams {
     /// Maximum number of tokens to generate
@@ -67,7 +303,7 @@ pub struct CreateMessageParams {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
     /// Input messages for the conversation
-    pub messages: Vec<Message>,
+    pub messages: Vec<OaiMessage>,
     /// Model to use
     pub model: String,
     /// Reasoning effort for response generation
@@ -85,6 +321,9 @@ pub struct CreateMessageParams {
     /// Whether to stream the response
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    /// Options of the streamed response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
     /// Thinking mode configuration
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking: Option<Thinking>,
@@ -99,10 +338,13 @@ pub struct CreateMessageParams {
     pub logit_bias: Option<Value>,
     /// Tools that the model may use
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tools: Option<Vec<Tool>>,
+    pub tools: Option<Vec<OaiTool>>,
     /// How the model should use tools
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_choice: Option<ToolChoice>,
+    pub tool_choice: Option<Value>,
+    /// Whether the model may issue several tool calls at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
     /// Request metadata
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Metadata>,
@@ -117,18 +359,133 @@ impl CreateMessageParams {
         let messages = self
             .messages
             .iter()
-            .map(|msg| match msg.content {
-                MessageContent::Text { ref content } => content.to_string(),
-                MessageContent::Blocks { ref content } => content
-                    .iter()
-                    .map(|block| match block {
-                        ContentBlock::Text { text, .. } => text,
-                        _ => "",
-                    })
-                    .collect::<String>(),
+            .filter_map(|msg| match msg.content {
+                Some(OaiContent::Text(ref content)) => Some(content.to_string()),
+                Some(OaiContent::Parts(ref content)) => Some(
+                    content
+                        .iter()
+                        .map(|block| match block {
+                            ContentBlock::Text { text, .. } => text.as_str(),
+                            _ => "",
+                        })
+                        .collect::<String>(),
+                ),
+                None => None,
             })
             .collect::<Vec<_>>()
             .join("\n");
         bpe.encode_with_special_tokens(&messages).len() as u32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_tool_calls_and_results() {
+        let body = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                { "role": "system", "content": "be helpful" },
+                { "role": "user", "content": "weather in Paris and Rome?" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [
+                        { "id": "call_1", "type": "function", "function": { "name": "weather", "arguments": "{\"city\":\"Paris\"}" } },
+                        { "id": "call_2", "type": "function", "function": { "name": "weather", "arguments": "{\"city\":\"Rome\"}" } }
+                    ]
+                },
+                { "role": "tool", "tool_call_id": "call_1", "content": "sunny" },
+                { "role": "tool", "tool_call_id": "call_2", "content": "rainy" }
+            ],
+            "tools": [
+                { "type": "function", "function": { "name": "weather", "parameters": { "type": "object" } } }
+            ],
+            "tool_choice": { "type": "function", "function": { "name": "weather" } }
+        });
+
+        let params: CreateMessageParams = serde_json::from_value(body).unwrap();
+        let params: ClaudeCreateMessageParams = params.into();
+
+        assert!(params.system.is_some());
+        assert_eq!(params.messages.len(), 3);
+        let MessageContent::Blocks { content } = &params.messages[1].content else {
+            panic!("assistant turn should carry blocks");
+        };
+        assert!(matches!(
+            &content[..],
+            [ContentBlock::ToolUse { id, input, .. }, ContentBlock::ToolUse { .. }]
+                if id == "call_1" && input["city"] == "Paris"
+        ));
+        let MessageContent::Blocks { content } = &params.messages[2].content else {
+            panic!("tool results should be folded into one user turn");
+        };
+        assert_eq!(params.messages[2].role, Role::User);
+        assert!(matches!(
+            &content[..],
+            [ContentBlock::ToolResult { tool_use_id, .. }, ContentBlock::ToolResult { .. }]
+                if tool_use_id == "call_1"
+        ));
+        assert!(matches!(
+            params.tools.as_deref(),
+            Some([Tool::Custom(CustomTool { name, .. })]) if name == "weather"
+        ));
+        assert!(matches!(
+            params.tool_choice,
+            Some(ToolChoice::Tool { ref name, .. }) if name == "weather"
+        ));
+    }
+
+    #[test]
+    fn converts_string_tool_choices() {
+        let params = |extra: Value| {
+            let mut body = json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "tools": [
+                    { "type": "function", "function": { "name": "weather", "parameters": { "type": "object" } } }
+                ]
+            });
+            body.as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().to_owned());
+            let params: CreateMessageParams = serde_json::from_value(body).unwrap();
+            ClaudeCreateMessageParams::from(params).tool_choice
+        };
+        assert!(matches!(
+            params(json!({ "tool_choice": "none" })),
+            Some(ToolChoice::None)
+        ));
+        assert!(matches!(
+            params(json!({ "tool_choice": "auto" })),
+            Some(ToolChoice::Auto {
+                disable_parallel_tool_use: None
+            })
+        ));
+        assert!(matches!(
+            params(json!({ "tool_choice": "required" })),
+            Some(ToolChoice::Any { .. })
+        ));
+        assert!(matches!(
+            params(json!({ "parallel_tool_calls": false })),
+            Some(ToolChoice::Auto {
+                disable_parallel_tool_use: Some(true)
+            })
+        ));
+        assert!(params(json!({})).is_none());
+    }
+
+    #[test]
+    fn keeps_plain_text_messages_as_text() {
+        let body = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "Hi" }]
+        });
+
+        let params: CreateMessageParams = serde_json::from_value(body).unwrap();
+        let params: ClaudeCreateMessageParams = params.into();
+        assert_eq!(params.messages, vec![Message::new_text(Role::User, "Hi")]);
+    }
+}
@@ -1,4 +1,4 @@
-use axum::body::Body;
+use axum::{body::Body, response::sse::Event as SseEvent};
 use colored::{ColoredString, Colorize};
 use tokio::spawn;
 use tracing::error;
@@ -6,6 +6,7 @@ use tracing::error;
 use crate::{
     config::{CLEWDR_CONFIG, LOG_DIR},
     error::ClewdrError,
+    types::claude::StreamEvent,
 };
 
 /// Helper function to format a boolean value as "Enabled" or "Disabled"
@@ -53,6 +54,13 @@ pub fn print_out_text(text: String, file_name: &str) {
     });
 }
 
+/// Wraps a Claude stream event into an SSE event named after its type
+pub fn sse_event(event: &StreamEvent) -> SseEvent {
+    let data = serde_json::to_value(event).unwrap_or_default();
+    let name = data["type"].as_str().unwrap_or("message").to_string();
+    SseEvent::default().event(name).data(data.to_string())
+}
+
 /// Timezone for the API
 pub const TIME_ZONE: &str = "America/New_York";
 